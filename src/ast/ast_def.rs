@@ -1,6 +1,42 @@
 // Implemented:
 // CompUnit, Decl, Def, Func, Stmt
 // Initialize Symbol
+
+// Source Span: byte offsets [start, end) of a node in the source text
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    // Span::default() is used by errors raised before any location is known
+    pub fn is_unknown(&self) -> bool {
+        self.start == 0 && self.end == 0
+    }
+
+    // 1-based (line, column) of the span start
+    pub fn line_col(&self, source: &str) -> (usize, usize) {
+        let start = self.start.min(source.len());
+        let before = &source[..start];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |pos| pos + 1);
+        let col = source[line_start..start].chars().count() + 1;
+        (line, col)
+    }
+
+    // Whole source line containing the span start, without the line break
+    pub fn source_line<'a>(&self, source: &'a str) -> &'a str {
+        let start = self.start.min(source.len());
+        let line_start = source[..start].rfind('\n').map_or(0, |pos| pos + 1);
+        let line_end = source[start..].find('\n').map_or(source.len(), |pos| start + pos);
+        source[line_start..line_end].trim_end_matches('\r')
+    }
+}
 #[derive(Debug)]
 pub struct CompileInit {
     pub init: Vec<DeclOrFunc>,
@@ -10,6 +46,7 @@ pub struct CompileInit {
 pub enum DeclOrFunc {
     Decl(Decl),
     Func(FuncDef),
+    Error(Span), // Syntax error, already reported by the parser
 }
 
 #[derive(Debug)]
@@ -24,8 +61,11 @@ pub struct ConstDecl {
 #[derive(Debug)]
 pub struct ConstDef {
     pub id: String,
+    pub id_span: Span, // The identifier alone, where warnings about the name point
     pub dims: Vec<ConstExpr>,
     pub init_val: ConstInitVal,
+    pub span: Span,
+    pub shape: Vec<usize>, // Semantic: evaluated dims
 }
 #[derive(Debug)]
 pub enum ConstInitVal {
@@ -39,8 +79,11 @@ pub struct VarDecl {
 #[derive(Debug)]
 pub struct VarDef {
     pub id: String,
+    pub id_span: Span, // The identifier alone, where warnings about the name point
     pub dims: Vec<ConstExpr>,
     pub init_val: Option<InitVal>,
+    pub span: Span,
+    pub shape: Vec<usize>, // Semantic: evaluated dims
 }
 #[derive(Debug)]
 pub enum InitVal {
@@ -55,6 +98,7 @@ pub struct FuncDef {
     pub func_name: String,
     pub func_params: Vec<Param>,
     pub func_body: Block,
+    pub span: Span, // Function head: from type to ")"
 }
 //
 #[derive(Debug)]
@@ -66,12 +110,16 @@ pub enum FuncType {
 #[derive(Debug)]
 pub struct Param {
     pub param_id: String,
+    pub id_span: Span, // The identifier alone, where warnings about the name point
     pub param_dims: Option<Vec<ConstExpr>>,
+    pub span: Span,
+    pub shape: Vec<usize>, // Semantic: evaluated dims after the leading []
 }
 // Code Block {}
 #[derive(Debug)]
 pub struct Block {
     pub items: Vec<BlockItem>,
+    pub span: Span,
 }
 #[derive(Debug)]
 pub enum BlockItem {
@@ -79,6 +127,17 @@ pub enum BlockItem {
     Stmt(Stmt),
 }
 
+impl BlockItem {
+    // A declaration spans its first definition
+    pub fn span(&self) -> Span {
+        match self {
+            BlockItem::Decl(Decl::Const(decl)) => decl.defs.first().map_or(Span::default(), |def| def.span),
+            BlockItem::Decl(Decl::Var(decl)) => decl.defs.first().map_or(Span::default(), |def| def.span),
+            BlockItem::Stmt(stmt) => stmt.span(),
+        }
+    }
+}
+
 // Statement
 #[derive(Debug)]
 pub enum Stmt {
@@ -90,12 +149,30 @@ pub enum Stmt {
     WhileStmt(Box<WhileStmt>),
     BreakStmt(BreakStmt),
     ContinueStmt(ContinueStmt),
+    Error(Span), // Syntax error, already reported by the parser
+}
+
+impl Stmt {
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::ReturnStmt(stmt) => stmt.span,
+            Stmt::AssignStmt(stmt) => stmt.span,
+            Stmt::ExprStmt(stmt) => stmt.span,
+            Stmt::BlockStmt(block) => block.span,
+            Stmt::IfStmt(stmt) => stmt.span,
+            Stmt::WhileStmt(stmt) => stmt.span,
+            Stmt::BreakStmt(stmt) => stmt.span,
+            Stmt::ContinueStmt(stmt) => stmt.span,
+            Stmt::Error(span) => *span,
+        }
+    }
 }
 
 // Statement Type: Return    ( return expr; )
 #[derive(Debug)]
 pub struct ReturnStmt {
     pub expr: Option<Expr>,
+    pub span: Span,
 }
 
 // Statement Type: Assign    ( lval = rval; )
@@ -103,12 +180,14 @@ pub struct ReturnStmt {
 pub struct AssignStmt {
     pub lval: LVal,
     pub expr: Expr,
+    pub span: Span,
 }
 
 // Statement Type: Expr    ( expr; )
 #[derive(Debug)]
 pub struct ExprStmt {
     pub expr: Option<Expr>,
+    pub span: Span,
 }
 
 // Statement Type: If    ( if() {} else {} )
@@ -117,6 +196,7 @@ pub struct IfStmt {
     pub condition: Expr,
     pub then_stmt: Stmt, // Block or Single Stmt
     pub else_stmt: Option<Stmt>,
+    pub span: Span, // "if (cond)"
 }
 
 // Statement Type: While    ( while() {})
@@ -124,15 +204,20 @@ pub struct IfStmt {
 pub struct WhileStmt {
     pub condition: Expr,
     pub body_stmt: Stmt, // Block or Single Stmt
+    pub span: Span, // "while (cond)"
 }
 
 // Statement Type: Break    ( break; )
 #[derive(Debug)]
-pub struct BreakStmt;
+pub struct BreakStmt {
+    pub span: Span,
+}
 
 // Statement Type: Continue    ( continue; )
 #[derive(Debug)]
-pub struct ContinueStmt;
+pub struct ContinueStmt {
+    pub span: Span,
+}
 
 // Lv.1
 
@@ -227,6 +312,7 @@ pub enum UnaryOp {
 pub struct FuncCall {
     pub funcid: String,
     pub args: Vec<Expr>,
+    pub span: Span,
 }
 
 #[derive(Debug)]
@@ -240,6 +326,8 @@ pub enum PrimExpr {
 pub struct LVal {
     pub id: String,
     pub inds: Vec<Expr>,
+    pub span: Span,
+    pub const_val: Option<i32>, // Semantic: value of a scalar constant
 }
 #[derive(Debug)]
 pub struct ConstExpr {
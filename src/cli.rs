@@ -0,0 +1,194 @@
+/*
+    Command line:
+        compiler [options] [input]
+        compiler (-koopa | -riscv | -perf) <input> -o <output>   (course test harness)
+*/
+use compiler::diagnostics::WarningConfig;
+use compiler::{is_known_pass, OptLevel, OptOptions, RegAlloc};
+
+pub const USAGE: &str = "\
+Usage: compiler [options] [input]
+       compiler (-koopa | -riscv | -perf) <input> -o <output>
+
+Reads SysY source from <input>, or from stdin when it is `-` or missing.
+
+Options:
+  --emit=<kinds>    Comma separated outputs among ast, koopa, asm (default: asm)
+  -o <file>         Write output to <file>, `-` for stdout (default: stdout)
+                    With several kinds, each goes to <file> with the kind's
+                    extension: .ast, .koopa, .s
+  -O0, -O1, -O2     Optimization level (default: -O0)
+  --enable-pass=<names>, --disable-pass=<names>
+                    Add IR passes to, or remove them from, the level's pipeline
+  --dump-after=<names>
+                    Print the Koopa IR to stderr after each named pass, `all`
+                    for every pass
+  --inline-threshold=<n>
+                    Inline functions of at most <n> IR instructions (default: 40)
+  --unroll-factor=<n>
+                    Copies of a counted loop's body per iteration (default: 4)
+  --regalloc=<kind> Register allocator among stack, linear, coloring
+                    (default: coloring at -O2, linear otherwise)
+  -W<name>, -Wno-<name>
+                    Enable or disable a warning
+  -Wall             Enable every warning
+  -Werror, -Werror=<name>
+                    Turn every warning, or the named one, into an error
+  -koopa            Same as --emit=koopa
+  -riscv            Same as --emit=asm
+  -perf             Same as --emit=asm -O2
+  -h, --help        Print this help
+
+Passes: mem2reg, sccp, gvn, licm, strength, unroll, inline, tre, dce, verify
+
+Warnings: return-type, unused-variable, unused-parameter, unused-function,
+          unreachable-code, constant-condition
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmitKind {
+    Ast,
+    Koopa,
+    Asm,
+}
+
+impl EmitKind {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Ast => "ast",
+            Self::Koopa => "koopa",
+            Self::Asm => "s",
+        }
+    }
+
+    fn parse(kind: &str) -> Result<Self, String> {
+        match kind {
+            "ast" => Ok(Self::Ast),
+            "koopa" => Ok(Self::Koopa),
+            "asm" => Ok(Self::Asm),
+            _ => Err(format!("unknown emit kind `{}`, expected ast, koopa or asm", kind)),
+        }
+    }
+}
+
+pub struct Options {
+    pub input: Option<String>,  // None: stdin
+    pub output: Option<String>, // None: stdout
+    pub emit: Vec<EmitKind>,
+    pub opt: OptOptions,
+    pub regalloc: RegAlloc,
+    pub warnings: WarningConfig,
+}
+
+pub enum Command {
+    Compile(Box<Options>),
+    Help,
+}
+
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut input = None;
+    let mut output = None;
+    let mut emit = Vec::new();
+    let mut opt_level = None;
+    let mut legacy_perf = false;
+    let mut regalloc = None;
+    let mut warnings = WarningConfig::new();
+    let mut opt = OptOptions::default();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-koopa" => emit.push(EmitKind::Koopa),
+            "-riscv" => emit.push(EmitKind::Asm),
+            "-perf" => {
+                emit.push(EmitKind::Asm);
+                legacy_perf = true;
+            },
+            "-o" => {
+                let path = args.next().ok_or("`-o` expects a file name")?;
+                if output.replace(path).is_some() {
+                    return Err("`-o` given more than once".to_owned());
+                }
+            },
+            "-O0" => opt_level = Some(OptLevel::O0),
+            "-O1" => opt_level = Some(OptLevel::O1),
+            "-O2" => opt_level = Some(OptLevel::O2),
+            "--emit" => {
+                let kinds = args.next().ok_or("`--emit` expects a list of kinds")?;
+                for kind in kinds.split(',') {
+                    emit.push(EmitKind::parse(kind)?);
+                }
+            },
+            _ if arg.starts_with("--emit=") => {
+                for kind in arg["--emit=".len()..].split(',') {
+                    emit.push(EmitKind::parse(kind)?);
+                }
+            },
+            _ if arg.starts_with("--enable-pass=") => {
+                opt.enable.extend(pass_list(&arg["--enable-pass=".len()..])?);
+            },
+            _ if arg.starts_with("--disable-pass=") => {
+                opt.disable.extend(pass_list(&arg["--disable-pass=".len()..])?);
+            },
+            _ if arg.starts_with("--dump-after=") => {
+                for name in arg["--dump-after=".len()..].split(',') {
+                    if name != "all" && !is_known_pass(name) {
+                        return Err(format!("unknown pass `{}`", name));
+                    }
+                    opt.dump_after.push(name.to_owned());
+                }
+            },
+            _ if arg.starts_with("--inline-threshold=") => {
+                let threshold = &arg["--inline-threshold=".len()..];
+                opt.inline_threshold = Some(threshold.parse().map_err(|_| format!("invalid inline threshold `{}`", threshold))?);
+            },
+            _ if arg.starts_with("--unroll-factor=") => {
+                let factor = &arg["--unroll-factor=".len()..];
+                opt.unroll_factor = Some(factor.parse().map_err(|_| format!("invalid unroll factor `{}`", factor))?);
+            },
+            _ if arg.starts_with("--regalloc=") => {
+                regalloc = Some(match &arg["--regalloc=".len()..] {
+                    "stack" => RegAlloc::Stack,
+                    "linear" => RegAlloc::LinearScan,
+                    "coloring" => RegAlloc::GraphColoring,
+                    kind => return Err(format!("unknown register allocator `{}`, expected stack, linear or coloring", kind)),
+                });
+            },
+            _ if arg.starts_with("-W") => {
+                warnings.parse_flag(&arg)?;
+            },
+            _ if arg.starts_with('-') && arg != "-" => {
+                return Err(format!("unknown option `{}`", arg));
+            },
+            _ => {
+                if input.replace(arg).is_some() {
+                    return Err("more than one input file".to_owned());
+                }
+            },
+        }
+    }
+
+    if emit.is_empty() {
+        emit.push(EmitKind::Asm);
+    }
+    let mut kinds = Vec::new();
+    for kind in emit {
+        if !kinds.contains(&kind) {
+            kinds.push(kind);
+        }
+    }
+    let emit = kinds;
+    let input = input.filter(|path| path != "-");
+    let output = output.filter(|path| path != "-");
+    // The harness runs -perf for the performance tests
+    opt.level = opt_level.unwrap_or(if legacy_perf { OptLevel::O2 } else { OptLevel::O0 });
+    let regalloc = regalloc.unwrap_or(if opt.level == OptLevel::O2 { RegAlloc::GraphColoring } else { RegAlloc::LinearScan });
+    return Ok(Command::Compile(Box::new(Options{ input, output, emit, opt, regalloc, warnings })));
+}
+
+fn pass_list(names: &str) -> Result<Vec<String>, String> {
+    names.split(',').map(|name| {
+        if is_known_pass(name) { Ok(name.to_owned()) } else { Err(format!("unknown pass `{}`", name)) }
+    }).collect()
+}
@@ -0,0 +1,232 @@
+/*
+    Diagnostics:
+        Render parse errors and compile errors in rustc style:
+
+        error: use of undeclared variable `b`
+         --> test.sy:4:13
+          |
+        4 |     a = a + b;
+          |             ^
+*/
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use lalrpop_util::ParseError;
+use lalrpop_util::lexer::Token;
+
+use crate::ast::ast_def::Span;
+use crate::koopa_generator::CompileError;
+use crate::semantic::CompileWarning;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Span>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: String, span: Option<Span>) -> Self {
+        Self {
+            severity: Severity::Error,
+            message,
+            span,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn warning(message: String, span: Option<Span>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(message, span)
+        }
+    }
+
+    pub fn with_note(mut self, note: String) -> Self {
+        self.notes.push(note);
+        self
+    }
+
+    pub fn from_parse_error(err: &ParseError<usize, Token<'_>, &str>) -> Self {
+        match err {
+            ParseError::InvalidToken { location } => {
+                Self::error("unrecognized token".to_owned(), Some(Span::new(*location, *location + 1)))
+            },
+            ParseError::UnrecognizedEof { location, expected } => {
+                let diag = Self::error("unexpected end of file".to_owned(), Some(Span::new(*location, *location)));
+                with_expected(diag, expected)
+            },
+            ParseError::UnrecognizedToken { token: (l, tok, r), expected } => {
+                let diag = Self::error(format!("unexpected token `{}`", tok.1), Some(Span::new(*l, *r)));
+                with_expected(diag, expected)
+            },
+            ParseError::ExtraToken { token: (l, tok, r) } => {
+                Self::error(format!("extra token `{}`", tok.1), Some(Span::new(*l, *r)))
+            },
+            ParseError::User { error } => Self::error(error.to_string(), None),
+        }
+    }
+
+    pub fn from_compile_error(err: &CompileError) -> Self {
+        let span = err.span();
+        Self::error(err.to_string(), (!span.is_unknown()).then_some(span))
+    }
+
+    // Severity::Error when the warning was promoted by -Werror
+    pub fn from_compile_warning(warning: &CompileWarning, severity: Severity) -> Self {
+        let span = warning.span();
+        let span = (!span.is_unknown()).then_some(span);
+        match severity {
+            Severity::Error => Self::error(format!("{} [-Werror={}]", warning, warning.name()), span),
+            Severity::Warning => Self::warning(format!("{} [-W{}]", warning, warning.name()), span),
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    pub fn render(&self, file_name: &str, source: &str) -> String {
+        let mut out = String::new();
+        let level = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let _ = writeln!(out, "{}: {}", level, self.message);
+
+        if let Some(span) = self.span {
+            let (line, col) = span.line_col(source);
+            let line_text = span.source_line(source);
+            let gutter = " ".repeat(line.to_string().len());
+            // underline the span, clipped to the first line it covers
+            let line_rest = line_text.chars().count().saturating_sub(col - 1);
+            let span_len = source.get(span.start..span.end.min(source.len()))
+                .map_or(0, |text| text.chars().take_while(|c| *c != '\n').count());
+            let carets = span_len.min(line_rest).max(1);
+            let _ = writeln!(out, "{}--> {}:{}:{}", gutter, file_name, line, col);
+            let _ = writeln!(out, "{} |", gutter);
+            let _ = writeln!(out, "{} | {}", line, line_text);
+            let _ = writeln!(out, "{} | {}{}", gutter, " ".repeat(col - 1), "^".repeat(carets));
+            for note in &self.notes {
+                let _ = writeln!(out, "{} = note: {}", gutter, note);
+            }
+        }
+        else {
+            let _ = writeln!(out, " --> {}", file_name);
+            for note in &self.notes {
+                let _ = writeln!(out, "  = note: {}", note);
+            }
+        }
+        return out;
+    }
+}
+
+fn with_expected(diag: Diagnostic, expected: &[String]) -> Diagnostic {
+    if expected.is_empty() {
+        return diag;
+    }
+    let mut names: Vec<String> = Vec::new();
+    for name in expected.iter().map(|name| terminal_name(name)) {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    let note = if names.len() == 1 {
+        format!("expected {}", names[0])
+    } else {
+        format!("expected one of {}", names.join(", "))
+    };
+    diag.with_note(note)
+}
+
+// lalrpop reports terminals as they are written in the grammar: "\";\"" or r#"r\"[0-9]+\""#
+fn terminal_name(terminal: &str) -> String {
+    if terminal.starts_with("r#") || terminal.starts_with("r\"") {
+        if terminal.contains("a-zA-Z_") {
+            return "identifier".to_owned();
+        }
+        return "integer literal".to_owned();
+    }
+    format!("`{}`", terminal.trim_matches('"'))
+}
+
+// Warning switches, in command line order:
+// -Wall, -W<name>, -Wno-<name>, -Werror, -Werror=<name>
+pub struct WarningConfig {
+    enabled: HashMap<&'static str, bool>,
+    werror_all: bool,
+    werror: HashSet<&'static str>,
+}
+
+impl Default for WarningConfig {
+    fn default() -> Self {
+        Self {
+            enabled: CompileWarning::ALL.iter().copied().collect(),
+            werror_all: false,
+            werror: HashSet::new(),
+        }
+    }
+}
+
+impl WarningConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Ok(false) if `flag` is not a warning switch at all
+    pub fn parse_flag(&mut self, flag: &str) -> Result<bool, String> {
+        let Some(switch) = flag.strip_prefix("-W") else {
+            return Ok(false);
+        };
+        match switch {
+            "all" => self.enabled.values_mut().for_each(|on| *on = true),
+            "error" => self.werror_all = true,
+            _ => {
+                let (name, on, werror) = if let Some(name) = switch.strip_prefix("error=") {
+                    (name, true, true)
+                } else if let Some(name) = switch.strip_prefix("no-") {
+                    (name, false, false)
+                } else {
+                    (switch, true, false)
+                };
+                let name = self.known_name(name).ok_or_else(|| format!("unknown warning option `{}`", flag))?;
+                self.enabled.insert(name, on);
+                if werror {
+                    self.werror.insert(name);
+                }
+            },
+        }
+        Ok(true)
+    }
+
+    fn known_name(&self, name: &str) -> Option<&'static str> {
+        self.enabled.keys().copied().find(|known| *known == name)
+    }
+
+    // None when the warning is switched off
+    pub fn severity(&self, warning: &CompileWarning) -> Option<Severity> {
+        let name = warning.name();
+        if !self.enabled.get(name).copied().unwrap_or(true) {
+            return None;
+        }
+        if self.werror_all || self.werror.contains(name) {
+            return Some(Severity::Error);
+        }
+        Some(Severity::Warning)
+    }
+}
+
+// Print every diagnostic to stderr, return the number of errors among them
+pub fn emit(diags: &[Diagnostic], file_name: &str, source: &str) -> usize {
+    for diag in diags {
+        eprint!("{}", diag.render(file_name, source));
+        eprintln!();
+    }
+    diags.iter().filter(|diag| diag.is_error()).count()
+}
@@ -11,7 +11,7 @@ use koopa::ir;
 use koopa::ir::values::FuncArgRef;
 use koopa::ir::values::GetElemPtr;
 use koopa::ir::{*, builder_traits::*};
-use super::const_evaluator::*;
+use crate::semantic::const_evaluator::*;
 
 
 
@@ -72,7 +72,7 @@ impl CompileInit{
     fn load_lib_func(&self, namespace: &mut Namesp, program: &mut Program){
         let mut create_lib_func = |name, params_type, return_type| {
             let new_func = program.new_func(FunctionData::new_decl( format!("@{}", name), params_type, return_type));
-            namespace.new_func(name, new_func);
+            namespace.new_func(name, new_func, Span::default());
         };
         create_lib_func("getint", Vec::new(), Type::get_i32());
         create_lib_func("getch", Vec::new(), Type::get_i32());
@@ -107,6 +107,7 @@ impl GenerateKoopa for DeclOrFunc {
             DeclOrFunc::Func(func) => {
                 func.generate(namespace, program)?;
             }
+            DeclOrFunc::Error(_) => {}
         }
         return Ok(());
     }
@@ -137,51 +138,24 @@ impl GenerateKoopa for ConstDecl {
     }
 }
 
-fn dim_vec_to_type(dims: &Vec<ConstExpr>, namespace: &mut Namesp) -> CResult<Type> {
-    dims.iter().rev()
-    .fold(Ok(Type::get_i32()), |acc, dim| {
-        let len = dim.const_eval(namespace).unwrap_or_default(); //debug
-        if len>=1 {
-            Ok(Type::get_array(acc?, len as usize))
-        }
-        else{
-            return Err(CompileError::InvalidInit("".to_owned()));
-        }
-    })
-
+// Dims were evaluated by the semantic pass
+fn shape_to_type(shape: &[usize]) -> Type {
+    shape.iter().rev()
+    .fold(Type::get_i32(), |acc, len| Type::get_array(acc, *len))
 }
 
 impl GenerateKoopa for ConstDef {
     type Out = ();
 
     fn generate(&self, namespace: &mut Namesp, program: &mut Program) -> CResult<Self::Out> {
-        let ty_from_dims = dim_vec_to_type(&self.dims, namespace);
-        let ty_from_dims = match ty_from_dims {
-            Ok(ty) => ty,
-            Err(_) => {
-                let ty = Type::get_i32();
-                if namespace.is_global() {
-                    let init_data = program.new_value().zero_init(ty);
-                    let value = program.new_value().global_alloc(init_data);
-                    program.set_value_name(value, Some(format!("@{}", self.id)));
-                    return Ok(());
-                }
-                else {
-                    let func_interface = namespace.get_cur_func_interf_mut()?;
-                    let alloc = func_interface.alloc_new_value(program, ty, Some(&self.id));
-                    let value = func_interface.value_builder(program).integer(0);
-                    let value = func_interface.value_builder(program).store(value, alloc);
-                    return Ok(());
-                };
-            },
-        };
+        let ty_from_dims = shape_to_type(&self.shape);
 
         let init = self.init_val.generate(namespace, program)?.init_rebuild(&ty_from_dims)?;
 
         if ty_from_dims.is_i32() {
             match init {
                 InitValue::Const(val) => {
-                    namespace.new_value(&self.id, NamespValue::ConstInt(val), true);
+                    namespace.new_value(&self.id, NamespValue::ConstInt(val), true, self.span)?;
                 },
                 _ => unreachable!(),
             }
@@ -199,7 +173,7 @@ impl GenerateKoopa for ConstDef {
                 init.into_ptr_stored(program, namespace, alloc);
                 alloc
             };
-            namespace.new_value(&self.id, NamespValue::Var(value), true);
+            namespace.new_value(&self.id, NamespValue::Var(value), true, self.span)?;
         }
 
         return Ok(());
@@ -211,7 +185,7 @@ impl GenerateKoopa for ConstInitVal {
 
     fn generate(&self, namespace: &mut Namesp, program: &mut Program) -> CResult<Self::Out> {
         let result = match self{
-            Self::Expr(expr) => InitValue::Const(expr.const_eval(namespace).unwrap_or_default()),
+            Self::Expr(expr) => InitValue::Const(expr.const_eval().ok_or(CompileError::InvalidInit("const initializer must be a compile-time constant".to_owned(), Span::default()))?),
 
             Self::List(list) => {
                 let mut result = Vec::new();
@@ -241,26 +215,7 @@ impl GenerateKoopa for VarDef {
     type Out = ();
 
     fn generate(&self, namespace: &mut Namesp, program: &mut Program) -> CResult<Self::Out> {
-        let type_from_dims = dim_vec_to_type(&self.dims, namespace);
-        let type_from_dim = match type_from_dims {
-            Ok(ty) => ty,
-            Err(_) => {
-                let ty = Type::get_i32();
-                if namespace.is_global() {
-                    let init_data = program.new_value().zero_init(ty);
-                    let value = program.new_value().global_alloc(init_data);
-                    program.set_value_name(value, Some(format!("@{}", self.id)));
-                    return Ok(());
-                }
-                else {
-                    let func_interface = namespace.get_cur_func_interf_mut()?;
-                    let alloc = func_interface.alloc_new_value(program, ty, Some(&self.id));
-                    let value = func_interface.value_builder(program).integer(0);
-                    let value = func_interface.value_builder(program).store(value, alloc);
-                    return Ok(());
-                };
-            },
-        };
+        let type_from_dim = shape_to_type(&self.shape);
         let init = match &self.init_val {
             Some(init) => Some(init.generate(namespace, program)?.init_rebuild(&type_from_dim)?),
             None => None,
@@ -282,7 +237,7 @@ impl GenerateKoopa for VarDef {
             }
             alloc
         };
-        namespace.new_value(&self.id, NamespValue::Var(value), false);
+        namespace.new_value(&self.id, NamespValue::Var(value), false, self.span)?;
         return Ok(());
     }
 }
@@ -294,7 +249,7 @@ impl GenerateKoopa for InitVal {
         let result = match self{
             Self::Expr(expr) =>{
                 if namespace.is_global() {
-                    InitValue::Const(expr.const_eval(namespace).ok_or(CompileError::InvalidInit("".to_string()))?)
+                    InitValue::Const(expr.const_eval().ok_or(CompileError::InvalidInit("global initializer must be a compile-time constant".to_string(), Span::default()))?)
                 }
                 else {
                     InitValue::Var(expr.generate(namespace, program)?.into_value(program, namespace)?)
@@ -355,10 +310,10 @@ impl GenerateKoopa for FuncDef {
             let alloc = func_interface.alloc_new_value(program, ty, Some("pa"));
             let new_store = func_interface.value_builder(program).store(param_value, alloc);
             func_interface.push_inst_to_bb(program, func_block, new_store);
-            namespace.new_value(func_param.param_id.as_str(), NamespValue::Var(alloc), false)?;
+            namespace.new_value(func_param.param_id.as_str(), NamespValue::Var(alloc), false, func_param.span)?;
         }
         // dump
-        namespace.new_func(&self.func_name, new_func)?;
+        namespace.new_func(&self.func_name, new_func, self.span)?;
         namespace.cur_function = Some(func_interface);
 
         self.func_body.generate(namespace, program)?;
@@ -379,7 +334,7 @@ impl GenerateKoopa for Param {
         Ok(
             match &self.param_dims{
                 None => Type::get_i32(),
-                Some(dims) => Type::get_pointer(dim_vec_to_type(dims, namespace)?),
+                Some(_) => Type::get_pointer(shape_to_type(&self.shape)),
             }
         )
     }
@@ -440,6 +395,7 @@ impl GenerateKoopa for Stmt{
             Stmt::ContinueStmt(continue_stmt) => {
                 continue_stmt.generate(namespace, program)?;
             }
+            Stmt::Error(_) => {}
         }
         return Ok(());
     }
@@ -479,9 +435,6 @@ impl GenerateKoopa for ReturnStmt {
                 let store = func_interface.value_builder(program).store(ret_exp, ret);
                 func_interface.push_inst_to_bb(program, func_interface.current_bb(), store);
             }
-            else if self.expr.is_some() {
-                return Err(CompileError::InvalidReturn("".to_owned()));
-            }
         }
         let func_interface = namespace.get_cur_func_interf_mut()?;
         let jump = func_interface.value_builder(program).jump(func_interface.get_bblock_from_list("%end"));
@@ -560,7 +513,7 @@ impl GenerateKoopa for WhileStmt {
 impl GenerateKoopa for BreakStmt {
     type Out = ();
     fn generate(&self, namespace: &mut Namesp, program: &mut Program) -> CResult<Self::Out> {
-        let jump_to = namespace.get_break_to()?;
+        let jump_to = namespace.get_break_to(self.span)?;
         let func_interface = namespace.get_cur_func_interf_mut()?;
         
         let jump = func_interface.value_builder(program).jump(jump_to);
@@ -574,7 +527,7 @@ impl GenerateKoopa for BreakStmt {
 impl GenerateKoopa for ContinueStmt {
     type Out = ();
     fn generate(&self, namespace: &mut Namesp, program: &mut Program) -> CResult<Self::Out> {
-        let jump_to = namespace.get_continue_to()?;
+        let jump_to = namespace.get_continue_to(self.span)?;
         let func_interface = namespace.get_cur_func_interf_mut()?;
         
         let jump = func_interface.value_builder(program).jump(jump_to);
@@ -589,7 +542,7 @@ impl GenerateKoopa for ConstExpr {
     type Out = i32;
 
     fn generate(&self, namespace: &mut Namesp, program: &mut Program) -> CResult<Self::Out> {
-        return self.const_eval(namespace).ok_or(CompileError::InvalidInit("".to_owned()));
+        return self.const_eval().ok_or(CompileError::InvalidInit("expression is not a compile-time constant".to_owned(), Span::default()));
     }
 }
 
@@ -818,30 +771,17 @@ impl GenerateKoopa for UnaryExpr{
 impl GenerateKoopa for FuncCall{
     type Out = ExprValue;
 
+    // Arity and argument types were checked by the semantic pass
     fn generate(&self, namespace: &mut Namesp, program: &mut Program) -> CResult<Self::Out> {
-        let func_target: Function = namespace.get_func(&self.funcid)?.to_owned();
-        let (params, void_ret) = match program.func(func_target).ty().kind(){
-            TypeKind::Function(params, ret) => {
-                (params.to_owned(), ret.is_unit())
-            },
+        let func_target = *namespace.get_func(&self.funcid, self.span)?;
+        let void_ret = match program.func(func_target).ty().kind(){
+            TypeKind::Function(_, ret) => ret.is_unit(),
             _ => unreachable!()
         };
 
-        let args = self
-            .args
-            .iter()
-            .map(|arg| arg.generate(namespace, program)?.into_value_or_ptr(program, namespace))
-            .collect::<CResult<Vec<Value>>>()?;
-
-        if params.len() != args.len() {
-            return Err(CompileError::InvalidFunccall("".to_owned()));
-        }
-
-        for (param_needed, arg_input) in params.iter().zip(args.iter()) {
-            let arg_ty = get_type(namespace, program, *arg_input);
-            if param_needed != &arg_ty {
-                return Err(CompileError::InvalidFunccall("".to_owned()));
-            }
+        let mut args = Vec::new();
+        for arg in &self.args {
+            args.push(arg.generate(namespace, program)?.into_value_or_ptr(program, namespace)?);
         }
 
         let func_interface = namespace.get_cur_func_interf()?;
@@ -879,15 +819,15 @@ impl GenerateKoopa for LVal{
     type Out = ExprValue;
 
     fn generate(&self, namespace: &mut Namesp, program: &mut Program) -> CResult<Self::Out> {
-        let mut val = match namespace.get_value(&self.id)? {
+        // Scalar constants were folded by the semantic pass
+        if let Some(i) = self.const_val {
+            let ir_int = namespace.get_cur_func_interf()?.value_builder(program).integer(i);
+            return Ok(ExprValue::VarInt(ir_int));
+        }
+        let mut val = match namespace.get_value(&self.id, self.span)? {
             NamespValue::ConstInt(i) => {
-                if(!self.inds.is_empty()){
-                    return Err(CompileError::InvalidIdentifier("".to_owned()));
-                }
-                else {
-                    let ir_int = namespace.get_cur_func_interf()?.value_builder(program).integer(*i);
-                    return Ok(ExprValue::VarInt(ir_int));
-                }
+                let ir_int = namespace.get_cur_func_interf()?.value_builder(program).integer(*i);
+                return Ok(ExprValue::VarInt(ir_int));
             },
             NamespValue::Var(v) => *v,
         };
@@ -920,7 +860,7 @@ impl GenerateKoopa for LVal{
 
         for (i, ind) in self.inds.iter().enumerate() {
             if dims == 0 {
-                return Err(CompileError::InvalidArrayDeref("".to_owned()));
+                return Err(CompileError::InvalidArrayDeref(format!("`{}` has fewer dimensions than subscripts", self.id), self.span));
             }
             dims -= 1;
             let ind_int = ind.generate(namespace, program)?.into_value_or_ptr(program, namespace)?;
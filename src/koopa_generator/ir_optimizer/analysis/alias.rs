@@ -0,0 +1,106 @@
+use koopa::ir::{FunctionData, Value, ValueKind};
+use std::collections::HashSet;
+
+// The object a pointer was derived from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Base {
+    Local(Value),  // An alloc of this function
+    Global(Value),
+    Arg(Value),    // A pointer parameter: caller memory, never one of our allocs
+    Unknown,       // A pointer loaded from memory
+}
+
+// A pointer as its base and the getptr / getelemptr steps taken from it
+struct Location {
+    base: Base,
+    path: Vec<(bool, Option<i32>)>, // (is getptr, constant index)
+}
+
+// May-alias queries between the pointers of one function. Indices are
+// assumed in bounds, as the language leaves anything else undefined.
+pub struct AliasInfo {
+    escaped: HashSet<Value>, // Allocs a callee may reach
+}
+
+impl AliasInfo {
+    pub fn new(data: &FunctionData) -> Self {
+        let mut escaped = HashSet::new();
+        for (_, node) in data.layout().bbs() {
+            for &inst in node.insts().keys() {
+                if matches!(data.dfg().value(inst).kind(), ValueKind::Alloc(_)) && escapes(data, inst) {
+                    escaped.insert(inst);
+                }
+            }
+        }
+        return Self{ escaped };
+    }
+
+    pub fn may_alias(&self, data: &FunctionData, a: Value, b: Value) -> bool {
+        let (a, b) = (location(data, a), location(data, b));
+        match (a.base, b.base) {
+            (Base::Unknown, _) | (_, Base::Unknown) => true,
+            // Same object: a differing constant index anywhere picks another element
+            (x, y) if x == y => {
+                let same_shape = a.path.len() == b.path.len() && a.path.iter().zip(&b.path).all(|(x, y)| x.0 == y.0);
+                !same_shape || !a.path.iter().zip(&b.path).any(|(x, y)| matches!((x.1, y.1), (Some(i), Some(j)) if i != j))
+            },
+            (Base::Arg(_), Base::Local(_)) | (Base::Local(_), Base::Arg(_)) => false,
+            (Base::Arg(_), _) | (_, Base::Arg(_)) => true,
+            _ => false,
+        }
+    }
+
+    // Whether a call may write to or read from what `ptr` points to
+    pub fn call_may_access(&self, data: &FunctionData, ptr: Value) -> bool {
+        match location(data, ptr).base {
+            Base::Local(alloc) => self.escaped.contains(&alloc),
+            _ => true,
+        }
+    }
+
+    // Whether `ptr` sits at a constant offset into a global, so a load from it never faults
+    pub fn is_global_element(&self, data: &FunctionData, ptr: Value) -> bool {
+        let location = location(data, ptr);
+        matches!(location.base, Base::Global(_)) && location.path.iter().all(|step| step.1.is_some())
+    }
+}
+
+fn location(data: &FunctionData, mut ptr: Value) -> Location {
+    let mut path = Vec::new();
+    let base = loop {
+        if ptr.is_global() {
+            break Base::Global(ptr);
+        }
+        let (src, index, is_getptr) = match data.dfg().value(ptr).kind() {
+            ValueKind::GetPtr(gp) => (gp.src(), gp.index(), true),
+            ValueKind::GetElemPtr(gep) => (gep.src(), gep.index(), false),
+            ValueKind::Alloc(_) => break Base::Local(ptr),
+            ValueKind::FuncArgRef(_) => break Base::Arg(ptr),
+            _ => break Base::Unknown,
+        };
+        let index = match data.dfg().value(index).kind() {
+            ValueKind::Integer(int) => Some(int.value()),
+            _ => None,
+        };
+        path.push((is_getptr, index));
+        ptr = src;
+    };
+    path.reverse();
+    return Location{ base, path };
+}
+
+// Whether a pointer into `alloc` is passed to a call or stored somewhere
+fn escapes(data: &FunctionData, alloc: Value) -> bool {
+    let mut pointers = vec![alloc];
+    while let Some(pointer) = pointers.pop() {
+        for &user in data.dfg().value(pointer).used_by() {
+            match data.dfg().value(user).kind() {
+                ValueKind::Load(_) => {},
+                ValueKind::Store(store) if store.value() != pointer => {},
+                ValueKind::GetPtr(_) | ValueKind::GetElemPtr(_) => pointers.push(user),
+                _ => return true,
+            }
+        }
+    }
+    return false;
+}
@@ -0,0 +1,79 @@
+use koopa::ir::{BasicBlock, FunctionData};
+use std::collections::{HashMap, HashSet};
+
+pub struct Cfg {
+    entry: BasicBlock,
+    preds: HashMap<BasicBlock, Vec<BasicBlock>>, // Reachable predecessors only
+    succs: HashMap<BasicBlock, Vec<BasicBlock>>,
+    rpo: Vec<BasicBlock>, // Reachable blocks in reverse postorder, entry first
+}
+
+impl Cfg {
+    // `data` must be a function definition: every block ends with a terminator
+    pub fn new(data: &FunctionData) -> Self {
+        let entry = data.layout().entry_bb().unwrap();
+        let mut succs = HashMap::new();
+        for (&bb, node) in data.layout().bbs() {
+            let mut targets = Vec::new();
+            if let Some(&term) = node.insts().back_key() {
+                for target in data.dfg().value(term).kind().bb_uses() {
+                    if !targets.contains(&target) {
+                        targets.push(target);
+                    }
+                }
+            }
+            succs.insert(bb, targets);
+        }
+
+        // Iterative DFS, a block is finished once all its successors are
+        let mut postorder = Vec::new();
+        let mut visited = HashSet::from([entry]);
+        let mut stack = vec![(entry, 0)];
+        while let Some((bb, next)) = stack.pop() {
+            match succs[&bb].get(next) {
+                Some(&succ) => {
+                    stack.push((bb, next + 1));
+                    if visited.insert(succ) {
+                        stack.push((succ, 0));
+                    }
+                },
+                None => postorder.push(bb),
+            }
+        }
+        let rpo = postorder.into_iter().rev().collect::<Vec<_>>();
+
+        let mut preds: HashMap<BasicBlock, Vec<BasicBlock>> = succs.keys().map(|&bb| (bb, Vec::new())).collect();
+        for &bb in &rpo {
+            for succ in &succs[&bb] {
+                preds.get_mut(succ).unwrap().push(bb);
+            }
+        }
+        return Self{ entry, preds, succs, rpo };
+    }
+
+    pub fn entry(&self) -> BasicBlock {
+        self.entry
+    }
+
+    pub fn preds(&self, bb: BasicBlock) -> &[BasicBlock] {
+        &self.preds[&bb]
+    }
+
+    pub fn succs(&self, bb: BasicBlock) -> &[BasicBlock] {
+        &self.succs[&bb]
+    }
+
+    pub fn rpo(&self) -> &[BasicBlock] {
+        &self.rpo
+    }
+
+    pub fn is_reachable(&self, bb: BasicBlock) -> bool {
+        self.rpo.contains(&bb)
+    }
+
+    // Blocks of the layout the entry can not reach
+    pub fn unreachable(&self, data: &FunctionData) -> Vec<BasicBlock> {
+        let reachable = self.rpo.iter().collect::<HashSet<_>>();
+        data.layout().bbs().keys().filter(|bb| !reachable.contains(bb)).copied().collect()
+    }
+}
@@ -0,0 +1,113 @@
+use koopa::ir::BasicBlock;
+use std::collections::HashMap;
+use super::Cfg;
+
+// Dominators of the reachable blocks (Cooper, Harvey and Kennedy)
+pub struct DomTree {
+    idom: HashMap<BasicBlock, BasicBlock>, // The entry is its own idom
+    children: HashMap<BasicBlock, Vec<BasicBlock>>,
+    frontiers: HashMap<BasicBlock, Vec<BasicBlock>>,
+    order: HashMap<BasicBlock, (usize, usize)>, // Pre/post numbers in the tree, for dominates
+}
+
+impl DomTree {
+    pub fn new(cfg: &Cfg) -> Self {
+        let index = cfg.rpo().iter().enumerate().map(|(i, &bb)| (bb, i)).collect::<HashMap<_, _>>();
+        let entry = cfg.entry();
+        let mut idom = HashMap::from([(entry, entry)]);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &bb in &cfg.rpo()[1..] {
+                let mut new_idom = None;
+                for &pred in cfg.preds(bb) {
+                    if !idom.contains_key(&pred) {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => pred,
+                        Some(cur) => Self::intersect(&idom, &index, cur, pred),
+                    });
+                }
+                let new_idom = new_idom.unwrap();
+                if idom.get(&bb) != Some(&new_idom) {
+                    idom.insert(bb, new_idom);
+                    changed = true;
+                }
+            }
+        }
+
+        let mut children: HashMap<BasicBlock, Vec<BasicBlock>> = cfg.rpo().iter().map(|&bb| (bb, Vec::new())).collect();
+        for &bb in &cfg.rpo()[1..] {
+            children.get_mut(&idom[&bb]).unwrap().push(bb);
+        }
+
+        let mut frontiers: HashMap<BasicBlock, Vec<BasicBlock>> = cfg.rpo().iter().map(|&bb| (bb, Vec::new())).collect();
+        for &bb in cfg.rpo() {
+            if cfg.preds(bb).len() < 2 {
+                continue;
+            }
+            for &pred in cfg.preds(bb) {
+                let mut runner = pred;
+                while runner != idom[&bb] {
+                    let frontier = frontiers.get_mut(&runner).unwrap();
+                    if !frontier.contains(&bb) {
+                        frontier.push(bb);
+                    }
+                    runner = idom[&runner];
+                }
+            }
+        }
+
+        let mut order: HashMap<BasicBlock, (usize, usize)> = HashMap::new();
+        let mut counter = 0;
+        let mut stack = vec![(entry, false)];
+        while let Some((bb, done)) = stack.pop() {
+            if done {
+                order.get_mut(&bb).unwrap().1 = counter;
+            }
+            else {
+                order.insert(bb, (counter, 0));
+                stack.push((bb, true));
+                stack.extend(children[&bb].iter().rev().map(|&child| (child, false)));
+            }
+            counter += 1;
+        }
+
+        return Self{ idom, children, frontiers, order };
+    }
+
+    fn intersect(idom: &HashMap<BasicBlock, BasicBlock>, index: &HashMap<BasicBlock, usize>, mut a: BasicBlock, mut b: BasicBlock) -> BasicBlock {
+        while a != b {
+            while index[&a] > index[&b] {
+                a = idom[&a];
+            }
+            while index[&b] > index[&a] {
+                b = idom[&b];
+            }
+        }
+        return a;
+    }
+
+    // None for the entry and unreachable blocks
+    pub fn idom(&self, bb: BasicBlock) -> Option<BasicBlock> {
+        self.idom.get(&bb).copied().filter(|&idom| idom != bb)
+    }
+
+    // Children in reverse postorder
+    pub fn children(&self, bb: BasicBlock) -> &[BasicBlock] {
+        &self.children[&bb]
+    }
+
+    pub fn frontier(&self, bb: BasicBlock) -> &[BasicBlock] {
+        &self.frontiers[&bb]
+    }
+
+    // Every block dominates itself
+    pub fn dominates(&self, a: BasicBlock, b: BasicBlock) -> bool {
+        match (self.order.get(&a), self.order.get(&b)) {
+            (Some(&(a_pre, a_post)), Some(&(b_pre, b_post))) => a_pre <= b_pre && b_post <= a_post,
+            _ => false,
+        }
+    }
+}
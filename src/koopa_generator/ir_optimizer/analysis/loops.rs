@@ -0,0 +1,95 @@
+use koopa::ir::BasicBlock;
+use std::collections::{HashMap, HashSet};
+use super::{Cfg, DomTree};
+
+// A natural loop: every back edge to `header` with the blocks reaching it
+pub struct Loop {
+    pub header: BasicBlock,
+    pub latches: Vec<BasicBlock>, // Sources of the back edges
+    pub blocks: Vec<BasicBlock>,  // Header first, then reverse postorder
+    pub parent: Option<usize>,    // Index of the enclosing loop
+    pub depth: usize,             // 1 for an outermost loop
+    members: HashSet<BasicBlock>,
+}
+
+impl Loop {
+    pub fn contains(&self, bb: BasicBlock) -> bool {
+        self.members.contains(&bb)
+    }
+
+    // Edges leaving the loop, as (inside, outside)
+    pub fn exits(&self, cfg: &Cfg) -> Vec<(BasicBlock, BasicBlock)> {
+        self.blocks.iter()
+            .flat_map(|&bb| cfg.succs(bb).iter().map(move |&succ| (bb, succ)))
+            .filter(|&(_, succ)| !self.contains(succ))
+            .collect()
+    }
+
+    // The only block outside the loop jumping to the header, if any
+    pub fn preheader(&self, cfg: &Cfg) -> Option<BasicBlock> {
+        let mut outside = cfg.preds(self.header).iter().filter(|&&pred| !self.contains(pred));
+        match (outside.next(), outside.next()) {
+            (Some(&pred), None) => Some(pred),
+            _ => None,
+        }
+    }
+}
+
+pub struct LoopForest {
+    loops: Vec<Loop>, // Outer loops before the loops they contain
+    innermost: HashMap<BasicBlock, usize>,
+}
+
+impl LoopForest {
+    pub fn new(cfg: &Cfg, dom: &DomTree) -> Self {
+        let rpo_index = cfg.rpo().iter().enumerate().map(|(i, &bb)| (bb, i)).collect::<HashMap<_, _>>();
+
+        let mut loops = Vec::new();
+        for &header in cfg.rpo() {
+            let latches = cfg.preds(header).iter().copied().filter(|&pred| dom.dominates(header, pred)).collect::<Vec<_>>();
+            if latches.is_empty() {
+                continue;
+            }
+            // Walk back from the latches, the header dominates everything found
+            let mut members = HashSet::from([header]);
+            let mut work = latches.clone();
+            while let Some(bb) = work.pop() {
+                if members.insert(bb) {
+                    work.extend(cfg.preds(bb).iter().copied());
+                }
+            }
+            let mut blocks = members.iter().copied().collect::<Vec<_>>();
+            blocks.sort_by_key(|bb| rpo_index[bb]);
+            loops.push(Loop{ header, latches, blocks, parent: None, depth: 1, members });
+        }
+
+        // Headers come in reverse postorder, so an enclosing loop is always listed first
+        for inner in 0..loops.len() {
+            let parent = (0..inner).rev().find(|&outer| loops[outer].contains(loops[inner].header));
+            if let Some(parent) = parent {
+                loops[inner].parent = Some(parent);
+                loops[inner].depth = loops[parent].depth + 1;
+            }
+        }
+        let mut innermost = HashMap::new();
+        for (index, lp) in loops.iter().enumerate() {
+            for &bb in &lp.blocks {
+                innermost.insert(bb, index);
+            }
+        }
+        return Self{ loops, innermost };
+    }
+
+    pub fn loops(&self) -> &[Loop] {
+        &self.loops
+    }
+
+    pub fn innermost(&self, bb: BasicBlock) -> Option<&Loop> {
+        self.innermost.get(&bb).map(|&index| &self.loops[index])
+    }
+
+    // Number of loops around `bb`, 0 outside any loop
+    pub fn depth(&self, bb: BasicBlock) -> usize {
+        self.innermost(bb).map_or(0, |lp| lp.depth)
+    }
+}
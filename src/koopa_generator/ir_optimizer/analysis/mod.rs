@@ -0,0 +1,96 @@
+/*
+    Analyses over one Koopa function:
+        Cfg         predecessors / successors and reverse postorder
+        DomTree     immediate dominators, dominator tree and dominance frontiers
+        LoopForest  natural loops with their nesting depth
+        AliasInfo   may-alias queries between pointers, built by passes as needed
+
+    FunctionAnalyses computes them on demand and keeps them until the
+    function's control flow changes.
+*/
+mod alias;
+mod cfg;
+mod dominators;
+mod loops;
+
+pub use alias::AliasInfo;
+pub use cfg::Cfg;
+pub use dominators::DomTree;
+pub use loops::{Loop, LoopForest};
+
+use koopa::ir::{Function, FunctionData};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+#[derive(Default)]
+pub struct FunctionAnalyses {
+    shape: u64, // Control flow the cached results were computed on
+    cfg: Option<Rc<Cfg>>,
+    dom: Option<Rc<DomTree>>,
+    loops: Option<Rc<LoopForest>>,
+}
+
+impl FunctionAnalyses {
+    pub fn cfg(&mut self, data: &FunctionData) -> Rc<Cfg> {
+        self.validate(data);
+        self.cfg.get_or_insert_with(|| Rc::new(Cfg::new(data))).clone()
+    }
+
+    pub fn dom_tree(&mut self, data: &FunctionData) -> Rc<DomTree> {
+        let cfg = self.cfg(data);
+        self.dom.get_or_insert_with(|| Rc::new(DomTree::new(&cfg))).clone()
+    }
+
+    pub fn loops(&mut self, data: &FunctionData) -> Rc<LoopForest> {
+        let cfg = self.cfg(data);
+        let dom = self.dom_tree(data);
+        self.loops.get_or_insert_with(|| Rc::new(LoopForest::new(&cfg, &dom))).clone()
+    }
+
+    // Drops every result, for passes that want to be explicit about it
+    pub fn invalidate(&mut self) {
+        self.cfg = None;
+        self.dom = None;
+        self.loops = None;
+    }
+
+    // Results only depend on the blocks and the targets of their terminators:
+    // anything else a pass changes keeps them valid
+    fn validate(&mut self, data: &FunctionData) {
+        let shape = cfg_shape(data);
+        if shape != self.shape {
+            self.invalidate();
+            self.shape = shape;
+        }
+    }
+}
+
+fn cfg_shape(data: &FunctionData) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for (bb, node) in data.layout().bbs() {
+        bb.hash(&mut hasher);
+        if let Some(&term) = node.insts().back_key() {
+            data.dfg().value(term).kind().bb_uses().for_each(|target| target.hash(&mut hasher));
+        }
+    }
+    hasher.finish()
+}
+
+// Cached analyses of every function, owned by the PassManager
+#[derive(Default)]
+pub struct AnalysisManager {
+    funcs: HashMap<Function, FunctionAnalyses>,
+}
+
+impl AnalysisManager {
+    pub fn function(&mut self, func: Function) -> &mut FunctionAnalyses {
+        self.funcs.entry(func).or_default()
+    }
+
+    // For module passes that rebuild or remove functions
+    pub fn invalidate_all(&mut self) {
+        self.funcs.clear();
+    }
+}
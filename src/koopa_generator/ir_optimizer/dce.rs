@@ -0,0 +1,216 @@
+/*
+    Dead code elimination:
+        Blocks unreachable from the entry are deleted first. Values are then
+        marked live from the instructions with side effects, a block
+        parameter keeping alive only the arguments passed to it, and the
+        rest is swept along with stores into allocs nothing reads.
+    Finally blocks that only jump on are bypassed, and a block whose only
+    predecessor jumps straight to it is merged into that predecessor.
+*/
+use koopa::ir::{BasicBlock, Function, FunctionData, Value, ValueKind};
+use std::collections::{HashMap, HashSet};
+use super::analysis::FunctionAnalyses;
+use super::ir_edit::*;
+use super::pass_manager::FunctionPass;
+
+pub struct Dce;
+
+impl FunctionPass for Dce {
+    fn name(&self) -> &'static str {
+        "dce"
+    }
+
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData, analyses: &mut FunctionAnalyses) {
+        let unreachable = analyses.cfg(data).unreachable(data);
+        remove_blocks(data, &unreachable);
+        remove_dead_values(data);
+        forward_empty_blocks(data);
+        merge_blocks(data);
+    }
+}
+
+fn remove_dead_values(data: &mut FunctionData) {
+    let write_only = write_only_allocs(data);
+    let mut param_of = HashMap::new();
+    for &bb in data.layout().bbs().keys() {
+        for (index, &param) in data.dfg().bb(bb).params().iter().enumerate() {
+            param_of.insert(param, (bb, index));
+        }
+    }
+
+    // Mark
+    let mut live = HashSet::new();
+    let mut work = Vec::new();
+    for (_, node) in data.layout().bbs() {
+        for &inst in node.insts().keys() {
+            let is_root = match data.dfg().value(inst).kind() {
+                ValueKind::Store(store) => !write_only.contains(&store.dest()),
+                ValueKind::Call(_) | ValueKind::Return(_) | ValueKind::Jump(_) | ValueKind::Branch(_) => true,
+                _ => false,
+            };
+            if is_root && live.insert(inst) {
+                work.push(inst);
+            }
+        }
+    }
+    while let Some(value) = work.pop() {
+        let mut operands = Vec::new();
+        if let Some(&(bb, index)) = param_of.get(&value) {
+            for &term in data.dfg().bb(bb).used_by() {
+                for args in edge_args(data.dfg().value(term).kind(), bb) {
+                    operands.push(args[index]);
+                }
+            }
+        }
+        else {
+            match data.dfg().value(value).kind() {
+                // Arguments only matter if the parameter receiving them does
+                ValueKind::Jump(_) => {},
+                ValueKind::Branch(br) => operands.push(br.cond()),
+                kind => operands.extend(kind.value_uses()),
+            }
+        }
+        for operand in operands {
+            if !operand.is_global() && live.insert(operand) {
+                work.push(operand);
+            }
+        }
+    }
+
+    // Drop the arguments of dead parameters
+    let mut param_live = HashMap::new();
+    for (&param, &(bb, index)) in &param_of {
+        if !live.contains(&param) {
+            let params = data.dfg().bb(bb).params();
+            param_live.entry(bb).or_insert_with(|| params.iter().map(|p| live.contains(p)).collect::<Vec<_>>())[index] = false;
+        }
+    }
+    for (&bb, mask) in &param_live {
+        let terms = data.dfg().bb(bb).used_by().iter().copied().collect::<Vec<_>>();
+        for term in terms {
+            edit_inst(data.dfg_mut(), term, |kind| for_each_edge_args_mut(kind, bb, |args| {
+                let mut keep = mask.iter();
+                args.retain(|_| *keep.next().unwrap());
+            }));
+        }
+    }
+
+    // Sweep
+    let mut dead = Vec::new();
+    for (_, node) in data.layout().bbs() {
+        dead.extend(node.insts().keys().copied().filter(|inst| !live.contains(inst)));
+    }
+    for &bb in param_live.keys() {
+        let params = data.dfg().bb(bb).params().to_vec();
+        dead.extend(params.iter().copied().filter(|param| !live.contains(param)));
+        data.dfg_mut().bb_mut(bb).params_mut().retain(|param| live.contains(param));
+    }
+    remove_insts(data, &dead);
+    for &bb in param_live.keys() {
+        let params = data.dfg().bb(bb).params().to_vec();
+        for (index, param) in params.into_iter().enumerate() {
+            edit_inst(data.dfg_mut(), param, |kind| match kind {
+                ValueKind::BlockArgRef(arg) => *arg.index_mut() = index,
+                _ => unreachable!(),
+            });
+        }
+    }
+}
+
+// Allocs whose memory is only ever written, directly or through pointers into it
+fn write_only_allocs(data: &FunctionData) -> HashSet<Value> {
+    let mut result = HashSet::new();
+    let allocs = data.layout().bbs().iter()
+        .flat_map(|(_, node)| node.insts().keys().copied())
+        .filter(|&inst| matches!(data.dfg().value(inst).kind(), ValueKind::Alloc(_)));
+    for alloc in allocs {
+        let mut pointers = vec![alloc];
+        let mut index = 0;
+        let mut only_written = true;
+        while only_written && index < pointers.len() {
+            let pointer = pointers[index];
+            index += 1;
+            for &user in data.dfg().value(pointer).used_by() {
+                match data.dfg().value(user).kind() {
+                    ValueKind::Store(store) if store.dest() == pointer && store.value() != pointer => {},
+                    ValueKind::GetPtr(ptr) if ptr.src() == pointer => pointers.push(user),
+                    ValueKind::GetElemPtr(ptr) if ptr.src() == pointer => pointers.push(user),
+                    _ => only_written = false,
+                }
+            }
+        }
+        if only_written {
+            result.extend(pointers);
+        }
+    }
+    return result;
+}
+
+// A block with nothing but `jump C(args)` is skipped: its predecessors jump to C directly.
+// The arguments dominate the block, so they dominate every predecessor too.
+fn forward_empty_blocks(data: &mut FunctionData) {
+    let entry = data.layout().entry_bb().unwrap();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        let bbs = data.layout().bbs().keys().copied().collect::<Vec<_>>();
+        for bb in bbs {
+            let Some((target, args)) = forwarded_jump(data, bb) else {
+                continue;
+            };
+            if bb == entry {
+                continue;
+            }
+            let preds = data.dfg().bb(bb).used_by().iter().copied().collect::<Vec<_>>();
+            for pred in preds {
+                retarget(data.dfg_mut(), pred, bb, target, &args);
+            }
+            remove_blocks(data, &[bb]);
+            changed = true;
+        }
+    }
+}
+
+fn forwarded_jump(data: &FunctionData, bb: BasicBlock) -> Option<(BasicBlock, Vec<Value>)> {
+    if !data.dfg().bb(bb).params().is_empty() || data.layout().bbs().node(&bb).unwrap().insts().len() != 1 {
+        return None;
+    }
+    match data.dfg().value(terminator(data, bb)).kind() {
+        ValueKind::Jump(jump) if jump.target() != bb => Some((jump.target(), jump.args().to_vec())),
+        _ => None,
+    }
+}
+
+// Merges `B` into `A` when `A` ends with `jump B(args)` and nothing else reaches `B`
+fn merge_blocks(data: &mut FunctionData) {
+    let entry = data.layout().entry_bb().unwrap();
+    let bbs = data.layout().bbs().keys().copied().collect::<Vec<_>>();
+    for bb in bbs {
+        // Blocks merged into an earlier one are gone by the time they come up
+        if data.layout().bbs().node(&bb).is_none() {
+            continue;
+        }
+        loop {
+            let jump = terminator(data, bb);
+            let (next, args) = match data.dfg().value(jump).kind() {
+                ValueKind::Jump(j) => (j.target(), j.args().to_vec()),
+                _ => break,
+            };
+            if next == bb || next == entry || data.dfg().bb(next).used_by().len() != 1 {
+                break;
+            }
+            remove_inst(data, jump);
+            let params = data.dfg().bb(next).params().to_vec();
+            for (param, arg) in params.into_iter().zip(args) {
+                replace_all_uses(data.dfg_mut(), param, arg);
+            }
+            let insts = data.layout().bbs().node(&next).unwrap().insts().keys().copied().collect::<Vec<_>>();
+            for inst in insts {
+                data.layout_mut().bb_mut(next).insts_mut().remove(&inst);
+                data.layout_mut().bb_mut(bb).insts_mut().push_key_back(inst).unwrap();
+            }
+            data.layout_mut().bbs_mut().remove(&next);
+            data.dfg_mut().remove_bb(next);
+        }
+    }
+}
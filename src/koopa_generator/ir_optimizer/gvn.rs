@@ -0,0 +1,177 @@
+/*
+    Global value numbering:
+        Walks the dominator tree with a scoped table of the pure values seen
+        so far (binaries, getptr, getelemptr): a value computing the same
+        thing as one in a dominating position is replaced by it.
+    Loads are numbered too, but memory changes along the way: the known
+    contents of memory flow only into a block whose single predecessor is
+    its immediate dominator, and stores and calls forget whatever they may
+    write to.
+*/
+use koopa::ir::{BasicBlock, BinaryOp, Function, FunctionData, Value, ValueKind};
+use std::collections::HashMap;
+use super::analysis::{AliasInfo, FunctionAnalyses};
+use super::ir_edit::*;
+use super::pass_manager::FunctionPass;
+
+pub struct Gvn;
+
+impl FunctionPass for Gvn {
+    fn name(&self) -> &'static str {
+        "gvn"
+    }
+
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData, analyses: &mut FunctionAnalyses) {
+        let cfg = analyses.cfg(data);
+        let dom = analyses.dom_tree(data);
+        let alias = AliasInfo::new(data);
+
+        let mut table = HashMap::new();
+        // Redundant values and their leaders, replaced all at once after the walk:
+        // one at a time would rebuild their users over and over
+        let mut replaced = HashMap::new();
+        let mut memory_out = HashMap::new(); // Known memory at the end of each block
+        enum Visit {
+            Enter(BasicBlock),
+            Exit(Vec<(Expr, Option<Value>)>), // Table entries to restore
+        }
+        let mut stack = vec![Visit::Enter(cfg.entry())];
+        while let Some(visit) = stack.pop() {
+            let bb = match visit {
+                Visit::Enter(bb) => bb,
+                Visit::Exit(undo) => {
+                    for (expr, old) in undo.into_iter().rev() {
+                        match old {
+                            Some(old) => table.insert(expr, old),
+                            None => table.remove(&expr),
+                        };
+                    }
+                    continue;
+                },
+            };
+
+            let mut memory = match (cfg.preds(bb), dom.idom(bb)) {
+                (&[pred], Some(idom)) if pred == idom => memory_out.get(&idom).cloned().unwrap_or_default(),
+                _ => Memory::default(),
+            };
+            let mut undo = Vec::new();
+            let insts = data.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect::<Vec<_>>();
+            for inst in insts {
+                let leader = match data.dfg().value(inst).kind() {
+                    ValueKind::Load(load) => memory.load(leader_of(&replaced, load.src())),
+                    ValueKind::Store(store) => {
+                        let (dest, value) = (leader_of(&replaced, store.dest()), leader_of(&replaced, store.value()));
+                        memory.store(data, &alias, dest, value);
+                        continue;
+                    },
+                    ValueKind::Call(_) => {
+                        memory.call(data, &alias);
+                        continue;
+                    },
+                    kind => match Expr::of(data, kind, &replaced) {
+                        Some(expr) => {
+                            let leader = expr.commuted().iter().chain(Some(&expr)).find_map(|e| table.get(e).copied());
+                            if leader.is_none() {
+                                undo.push((expr.clone(), table.insert(expr, inst)));
+                            }
+                            leader
+                        },
+                        None => continue,
+                    },
+                };
+                match leader {
+                    Some(leader) => {
+                        replaced.insert(inst, leader);
+                    },
+                    // The loaded value is what the address holds from now on
+                    None => if let ValueKind::Load(load) = data.dfg().value(inst).kind() {
+                        memory.known.push((leader_of(&replaced, load.src()), inst));
+                    },
+                }
+            }
+            memory_out.insert(bb, memory);
+
+            stack.push(Visit::Exit(undo));
+            stack.extend(dom.children(bb).iter().rev().map(|&child| Visit::Enter(child)));
+        }
+
+        replace_uses(data.dfg_mut(), &replaced);
+        for &inst in replaced.keys() {
+            remove_inst(data, inst);
+        }
+    }
+}
+
+// A leader is never replaced itself
+fn leader_of(replaced: &HashMap<Value, Value>, value: Value) -> Value {
+    replaced.get(&value).copied().unwrap_or(value)
+}
+
+// An integer operand stands for its value: every constant is a value of its own
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Operand {
+    Const(i32),
+    Value(Value),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Expr {
+    Binary(BinaryOp, Operand, Operand),
+    GetPtr(Operand, Operand),
+    GetElemPtr(Operand, Operand),
+}
+
+impl Expr {
+    // Operands already found redundant stand for their leaders
+    fn of(data: &FunctionData, kind: &ValueKind, replaced: &HashMap<Value, Value>) -> Option<Self> {
+        let operand = |value: Value| {
+            if value.is_global() {
+                return Operand::Value(value);
+            }
+            let value = leader_of(replaced, value);
+            match data.dfg().value(value).kind() {
+                ValueKind::Integer(int) => Operand::Const(int.value()),
+                _ => Operand::Value(value),
+            }
+        };
+        match kind {
+            ValueKind::Binary(bin) => Some(Self::Binary(bin.op(), operand(bin.lhs()), operand(bin.rhs()))),
+            ValueKind::GetPtr(ptr) => Some(Self::GetPtr(operand(ptr.src()), operand(ptr.index()))),
+            ValueKind::GetElemPtr(ptr) => Some(Self::GetElemPtr(operand(ptr.src()), operand(ptr.index()))),
+            _ => None,
+        }
+    }
+
+    // The same expression with its operands swapped, if that computes the same thing
+    fn commuted(&self) -> Option<Self> {
+        match self {
+            Self::Binary(op, lhs, rhs) => match op {
+                BinaryOp::Add | BinaryOp::Mul | BinaryOp::And | BinaryOp::Or | BinaryOp::Xor
+                | BinaryOp::Eq | BinaryOp::NotEq => Some(Self::Binary(*op, rhs.clone(), lhs.clone())),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+// What memory is known to hold, as (address, value) pairs
+#[derive(Default, Clone)]
+struct Memory {
+    known: Vec<(Value, Value)>,
+}
+
+impl Memory {
+    fn load(&self, src: Value) -> Option<Value> {
+        self.known.iter().rev().find(|&&(addr, _)| addr == src).map(|&(_, value)| value)
+    }
+
+    fn store(&mut self, data: &FunctionData, alias: &AliasInfo, dest: Value, value: Value) {
+        self.known.retain(|&(addr, _)| !alias.may_alias(data, addr, dest));
+        self.known.push((dest, value));
+    }
+
+    fn call(&mut self, data: &FunctionData, alias: &AliasInfo) {
+        self.known.retain(|&(addr, _)| !alias.call_may_access(data, addr));
+    }
+}
@@ -0,0 +1,274 @@
+/*
+    Inliner:
+        Calls to small non-recursive functions are replaced by a copy of the
+        callee's body. Callees are handled before their callers, so a body is
+        measured and copied with its own calls already inlined.
+    The calling block is split after the call: every `ret` of the copy
+    (usually the single one in `%end`, loading `%ret`) jumps to the second
+    half, passing the result as its parameter. Copied allocs go to the
+    caller's entry block, where mem2reg expects them.
+*/
+use koopa::ir::builder_traits::*;
+use koopa::ir::dfg::DataFlowGraph;
+use koopa::ir::entities::ValueData;
+use koopa::ir::{BasicBlock, Function, FunctionData, Program, Value, ValueKind};
+use std::collections::{HashMap, HashSet};
+use super::analysis::{AnalysisManager, Cfg};
+use super::ir_edit::*;
+use super::pass_manager::Pass;
+
+pub const DEFAULT_THRESHOLD: usize = 40;
+
+pub struct Inline {
+    pub threshold: usize, // Largest callee inlined, in instructions
+}
+
+impl Pass for Inline {
+    fn name(&self) -> &'static str {
+        "inline"
+    }
+
+    fn run(&mut self, program: &mut Program, analyses: &mut AnalysisManager) {
+        let graph = call_graph(program);
+        for caller in bottom_up(program, &graph) {
+            let mut changed = false;
+            while let Some((call, callee)) = self.next_site(program, &graph, caller) {
+                let body = Body::of(program.func(callee));
+                inline_call(program.func_mut(caller), call, &body);
+                changed = true;
+            }
+            if changed {
+                analyses.function(caller).invalidate();
+            }
+        }
+    }
+}
+
+impl Inline {
+    // The first call in `caller` worth inlining
+    fn next_site(&self, program: &Program, graph: &HashMap<Function, HashSet<Function>>, caller: Function) -> Option<(Value, Function)> {
+        let data = program.func(caller);
+        for (_, node) in data.layout().bbs() {
+            for &inst in node.insts().keys() {
+                let ValueKind::Call(call) = data.dfg().value(inst).kind() else {
+                    continue;
+                };
+                let callee = call.callee();
+                let body = program.func(callee);
+                let is_defined = body.layout().entry_bb().is_some();
+                if is_defined && !is_recursive(graph, callee) && cost(body) <= self.threshold {
+                    return Some((inst, callee));
+                }
+            }
+        }
+        return None;
+    }
+}
+
+fn cost(data: &FunctionData) -> usize {
+    data.layout().bbs().nodes().map(|node| node.insts().len()).sum()
+}
+
+// Defined functions and the defined functions they call
+fn call_graph(program: &Program) -> HashMap<Function, HashSet<Function>> {
+    let mut graph = HashMap::new();
+    for &func in program.func_layout() {
+        let data = program.func(func);
+        if data.layout().entry_bb().is_none() {
+            continue;
+        }
+        let callees = data.layout().bbs().nodes()
+            .flat_map(|node| node.insts().keys())
+            .filter_map(|&inst| match data.dfg().value(inst).kind() {
+                ValueKind::Call(call) => Some(call.callee()),
+                _ => None,
+            })
+            .filter(|&callee| program.func(callee).layout().entry_bb().is_some())
+            .collect();
+        graph.insert(func, callees);
+    }
+    return graph;
+}
+
+fn is_recursive(graph: &HashMap<Function, HashSet<Function>>, func: Function) -> bool {
+    let mut visited = HashSet::new();
+    let mut work = graph[&func].iter().copied().collect::<Vec<_>>();
+    while let Some(callee) = work.pop() {
+        if callee == func {
+            return true;
+        }
+        if visited.insert(callee) {
+            work.extend(graph[&callee].iter().copied());
+        }
+    }
+    return false;
+}
+
+// Defined functions, callees before their callers where there is no cycle
+fn bottom_up(program: &Program, graph: &HashMap<Function, HashSet<Function>>) -> Vec<Function> {
+    let mut order = Vec::new();
+    let mut visited = HashSet::new();
+    for &root in program.func_layout() {
+        if !graph.contains_key(&root) || !visited.insert(root) {
+            continue;
+        }
+        let mut stack = vec![(root, graph[&root].iter().copied().collect::<Vec<_>>())];
+        while let Some((func, pending)) = stack.last_mut() {
+            match pending.pop() {
+                Some(callee) => {
+                    if visited.insert(callee) {
+                        let callees = graph[&callee].iter().copied().collect();
+                        stack.push((callee, callees));
+                    }
+                },
+                None => {
+                    order.push(*func);
+                    stack.pop();
+                },
+            }
+        }
+    }
+    return order;
+}
+
+// What is copied of a callee: its values, and its reachable blocks in reverse postorder
+struct Body {
+    params: Vec<Value>,
+    values: HashMap<Value, ValueData>,
+    blocks: Vec<Block>,
+}
+
+struct Block {
+    bb: BasicBlock,
+    name: Option<String>,
+    params: Vec<Value>,
+    insts: Vec<Value>,
+}
+
+impl Body {
+    fn of(data: &FunctionData) -> Self {
+        let blocks = Cfg::new(data).rpo().iter().map(|&bb| {
+            let insts = data.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect();
+            Block{ bb, name: data.dfg().bb(bb).name().clone(), params: data.dfg().bb(bb).params().to_vec(), insts }
+        }).collect();
+        Self{ params: data.params().to_vec(), values: data.dfg().values().clone(), blocks }
+    }
+}
+
+// Maps values and blocks of the callee to their copies in the caller
+struct Copier<'a> {
+    body: &'a Body,
+    values: HashMap<Value, Value>,
+    bbs: HashMap<BasicBlock, BasicBlock>,
+}
+
+impl Copier<'_> {
+    // The copy of a callee value; constants are copied on first use
+    fn value(&mut self, dfg: &mut DataFlowGraph, value: Value) -> Value {
+        if value.is_global() {
+            return value;
+        }
+        if let Some(&copy) = self.values.get(&value) {
+            return copy;
+        }
+        let mut data = self.body.values[&value].clone();
+        if let ValueKind::Aggregate(agg) = data.kind_mut() {
+            for elem in agg.elems_mut() {
+                *elem = self.value(dfg, *elem);
+            }
+        }
+        let copy = dfg.new_value().raw(data);
+        self.values.insert(value, copy);
+        return copy;
+    }
+
+    fn inst(&mut self, dfg: &mut DataFlowGraph, inst: Value) -> Value {
+        let mut data = self.body.values[&inst].clone();
+        let mut operands = Vec::new();
+        for_each_operand_mut(data.kind_mut(), |operand| operands.push(*operand));
+        let mut copies = operands.into_iter().map(|operand| self.value(dfg, operand)).collect::<Vec<_>>().into_iter();
+        for_each_operand_mut(data.kind_mut(), |operand| *operand = copies.next().unwrap());
+        match data.kind_mut() {
+            ValueKind::Jump(jump) => *jump.target_mut() = self.bbs[&jump.target()],
+            ValueKind::Branch(br) => {
+                *br.true_bb_mut() = self.bbs[&br.true_bb()];
+                *br.false_bb_mut() = self.bbs[&br.false_bb()];
+            },
+            _ => {},
+        }
+        let copy = dfg.new_value().raw(data);
+        self.values.insert(inst, copy);
+        return copy;
+    }
+}
+
+fn inline_call(data: &mut FunctionData, call: Value, body: &Body) {
+    let bb = data.layout().parent_bb(call).unwrap();
+    let entry = data.layout().entry_bb().unwrap();
+    let args = match data.dfg().value(call).kind() {
+        ValueKind::Call(call) => call.args().to_vec(),
+        _ => unreachable!(),
+    };
+
+    // Everything after the call moves to a new block, receiving the result
+    let cont = data.dfg_mut().new_bb().basic_block(None);
+    data.layout_mut().bbs_mut().cursor_mut(bb).insert_key_after(cont).unwrap();
+    let mut after = Vec::new();
+    let mut cursor = data.layout().bbs().node(&bb).unwrap().insts().cursor(call);
+    cursor.move_next();
+    while let Some(&inst) = cursor.key() {
+        after.push(inst);
+        cursor.move_next();
+    }
+    for inst in after {
+        data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+        data.layout_mut().bb_mut(cont).insts_mut().push_key_back(inst).unwrap();
+    }
+    let ty = data.dfg().value(call).ty().clone();
+    let result = (!ty.is_unit()).then(|| add_block_param(data.dfg_mut(), cont, ty));
+    if let Some(result) = result {
+        replace_all_uses(data.dfg_mut(), call, result);
+    }
+    remove_inst(data, call);
+
+    let mut copier = Copier{ body, values: body.params.iter().copied().zip(args).collect(), bbs: HashMap::new() };
+    for block in &body.blocks {
+        let tys = block.params.iter().map(|param| body.values[param].ty().clone()).collect();
+        let new = data.dfg_mut().new_bb().basic_block_with_params(block.name.clone(), tys);
+        data.layout_mut().bbs_mut().cursor_mut(cont).insert_key_before(new).unwrap();
+        copier.bbs.insert(block.bb, new);
+        copier.values.extend(block.params.iter().copied().zip(data.dfg().bb(new).params().to_vec()));
+    }
+    let jump = data.dfg_mut().new_value().jump(copier.bbs[&body.blocks[0].bb]);
+    data.layout_mut().bb_mut(bb).insts_mut().push_key_back(jump).unwrap();
+
+    // Terminators last: they may pass values defined further down a loop
+    let mut allocs = Vec::new();
+    for block in &body.blocks {
+        let new = copier.bbs[&block.bb];
+        for &inst in &block.insts[..block.insts.len() - 1] {
+            let copy = copier.inst(data.dfg_mut(), inst);
+            if matches!(body.values[&inst].kind(), ValueKind::Alloc(_)) {
+                allocs.push(copy);
+            }
+            else {
+                data.layout_mut().bb_mut(new).insts_mut().push_key_back(copy).unwrap();
+            }
+        }
+    }
+    for block in &body.blocks {
+        let new = copier.bbs[&block.bb];
+        let term = *block.insts.last().unwrap();
+        let copy = match body.values[&term].kind() {
+            ValueKind::Return(ret) => {
+                let value = ret.value().map(|value| copier.value(data.dfg_mut(), value));
+                data.dfg_mut().new_value().jump_with_args(cont, value.into_iter().collect())
+            },
+            _ => copier.inst(data.dfg_mut(), term),
+        };
+        data.layout_mut().bb_mut(new).insts_mut().push_key_back(copy).unwrap();
+    }
+    for alloc in allocs.into_iter().rev() {
+        data.layout_mut().bb_mut(entry).insts_mut().push_key_front(alloc).unwrap();
+    }
+}
@@ -0,0 +1,291 @@
+/*
+    IR editing helpers shared by the passes.
+        Koopa keeps `used_by` sets up to date only when values are rebuilt
+        through the dfg, so operands are never changed through `kind_mut`
+        on a live value: the data is cloned, edited and put back instead.
+*/
+use koopa::ir::builder_traits::*;
+use koopa::ir::dfg::DataFlowGraph;
+use koopa::ir::{BasicBlock, FunctionData, Type, Value, ValueKind};
+use std::collections::{HashMap, HashSet};
+
+// Visits the value operands of an instruction, in `value_uses` order
+pub fn for_each_operand_mut(kind: &mut ValueKind, mut f: impl FnMut(&mut Value)) {
+    match kind {
+        ValueKind::Load(load) => f(load.src_mut()),
+        ValueKind::Store(store) => {
+            f(store.value_mut());
+            f(store.dest_mut());
+        },
+        ValueKind::GetPtr(ptr) => {
+            f(ptr.src_mut());
+            f(ptr.index_mut());
+        },
+        ValueKind::GetElemPtr(ptr) => {
+            f(ptr.src_mut());
+            f(ptr.index_mut());
+        },
+        ValueKind::Binary(bin) => {
+            f(bin.lhs_mut());
+            f(bin.rhs_mut());
+        },
+        ValueKind::Branch(br) => {
+            f(br.cond_mut());
+            br.true_args_mut().iter_mut().for_each(&mut f);
+            br.false_args_mut().iter_mut().for_each(&mut f);
+        },
+        ValueKind::Jump(jump) => jump.args_mut().iter_mut().for_each(f),
+        ValueKind::Call(call) => call.args_mut().iter_mut().for_each(f),
+        ValueKind::Return(ret) => ret.value_mut().iter_mut().for_each(f),
+        _ => {},
+    }
+}
+
+// Rebuilds `inst` after `edit` changed a copy of its kind. A rebuilt value
+// forgets its users, so everything depending on it is rebuilt as well,
+// each value after the ones it uses.
+pub fn edit_inst(dfg: &mut DataFlowGraph, inst: Value, edit: impl FnOnce(&mut ValueKind)) {
+    let mut data = dfg.value(inst).clone();
+    edit(data.kind_mut());
+    let dependents = dependents(dfg, [inst], &HashMap::new());
+    dfg.replace_value_with(inst).raw(data);
+    for &user in &dependents[1..] {
+        let data = dfg.value(user).clone();
+        dfg.replace_value_with(user).raw(data);
+    }
+}
+
+// `roots` and the values depending on them, in topological order. Users form
+// a DAG: block parameters are not users of the arguments passed to them.
+// `also_before` adds dependencies: each value comes after the ones listed for it.
+fn dependents(dfg: &DataFlowGraph, roots: impl IntoIterator<Item = Value>, also_before: &HashMap<Value, Vec<Value>>) -> Vec<Value> {
+    let mut postorder = Vec::new();
+    let mut visited = HashSet::new();
+    let mut stack = roots.into_iter().map(|root| (root, false)).collect::<Vec<_>>();
+    while let Some((value, done)) = stack.pop() {
+        if done {
+            postorder.push(value);
+            continue;
+        }
+        if !visited.insert(value) {
+            continue;
+        }
+        stack.push((value, true));
+        stack.extend(dfg.value(value).used_by().iter().map(|&user| (user, false)));
+        stack.extend(also_before.get(&value).into_iter().flatten().map(|&after| (after, false)));
+    }
+    postorder.reverse();
+    return postorder;
+}
+
+// Every user of `old` uses `new` instead, `old` is left unused
+pub fn replace_all_uses(dfg: &mut DataFlowGraph, old: Value, new: Value) {
+    replace_uses(dfg, &HashMap::from([(old, new)]));
+}
+
+// Every use of a key of `map` becomes a use of its value, followed through the
+// map, and the keys are left unused. Replacing many values at once rebuilds
+// each value depending on them once, instead of once per replaced value.
+pub fn replace_uses(dfg: &mut DataFlowGraph, map: &HashMap<Value, Value>) {
+    let resolve = |mut value: Value| {
+        while let Some(&new) = map.get(&value) {
+            value = new;
+        }
+        value
+    };
+    // A replacement depending on a replaced value is rebuilt too, and must be
+    // before the users it gains
+    let mut replaced_by = HashMap::<Value, Vec<Value>>::new();
+    for (&old, &new) in map {
+        replaced_by.entry(new).or_default().push(old);
+    }
+    for value in dependents(dfg, map.keys().copied(), &replaced_by) {
+        let mut data = dfg.value(value).clone();
+        for_each_operand_mut(data.kind_mut(), |operand| *operand = resolve(*operand));
+        dfg.replace_value_with(value).raw(data);
+    }
+}
+
+// Takes an unused instruction out of the layout and the dfg
+pub fn remove_inst(data: &mut FunctionData, inst: Value) {
+    let bb = data.layout().parent_bb(inst).unwrap();
+    data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+    data.dfg_mut().remove_value(inst);
+}
+
+pub fn terminator(data: &FunctionData, bb: BasicBlock) -> Value {
+    *data.layout().bbs().node(&bb).unwrap().insts().back_key().unwrap()
+}
+
+// Adds a new instruction to `bb`, just before its terminator
+pub fn insert_before_terminator(data: &mut FunctionData, bb: BasicBlock, inst: Value) {
+    let term = terminator(data, bb);
+    data.layout_mut().bb_mut(bb).insts_mut().cursor_mut(term).insert_key_before(inst).unwrap();
+}
+
+// Appends a parameter to an existing block. The builder only creates
+// parameters with new blocks, so one is borrowed from a scratch block.
+pub fn add_block_param(dfg: &mut DataFlowGraph, bb: BasicBlock, ty: Type) -> Value {
+    let scratch = dfg.new_bb().basic_block_with_params(None, vec![ty]);
+    let param = dfg.bb_mut(scratch).params_mut().pop().unwrap();
+    dfg.remove_bb(scratch);
+    let index = dfg.bb(bb).params().len();
+    edit_inst(dfg, param, |kind| match kind {
+        ValueKind::BlockArgRef(arg) => *arg.index_mut() = index,
+        _ => unreachable!(),
+    });
+    dfg.bb_mut(bb).params_mut().push(param);
+    return param;
+}
+
+// Argument lists `kind` passes to `target`, two when both branch targets are the same
+pub fn edge_args(kind: &ValueKind, target: BasicBlock) -> Vec<&[Value]> {
+    match kind {
+        ValueKind::Jump(jump) if jump.target() == target => vec![jump.args()],
+        ValueKind::Branch(br) => {
+            let mut args = Vec::new();
+            if br.true_bb() == target {
+                args.push(br.true_args());
+            }
+            if br.false_bb() == target {
+                args.push(br.false_args());
+            }
+            args
+        },
+        _ => Vec::new(),
+    }
+}
+
+// Visits the argument lists `kind` passes to `target`
+pub fn for_each_edge_args_mut(kind: &mut ValueKind, target: BasicBlock, mut f: impl FnMut(&mut Vec<Value>)) {
+    match kind {
+        ValueKind::Jump(jump) if jump.target() == target => f(jump.args_mut()),
+        ValueKind::Branch(br) => {
+            if br.true_bb() == target {
+                f(br.true_args_mut());
+            }
+            if br.false_bb() == target {
+                f(br.false_args_mut());
+            }
+        },
+        _ => {},
+    }
+}
+
+// Appends `arg` to the arguments `term` passes along its edges to `target`
+pub fn push_edge_arg(dfg: &mut DataFlowGraph, term: Value, target: BasicBlock, arg: Value) {
+    edit_inst(dfg, term, |kind| for_each_edge_args_mut(kind, target, |args| args.push(arg)));
+}
+
+// Makes `term` jump to `new` instead of `old`, passing `args`
+pub fn retarget(dfg: &mut DataFlowGraph, term: Value, old: BasicBlock, new: BasicBlock, args: &[Value]) {
+    edit_inst(dfg, term, |kind| match kind {
+        ValueKind::Jump(jump) => {
+            *jump.target_mut() = new;
+            *jump.args_mut() = args.to_vec();
+        },
+        ValueKind::Branch(br) => {
+            if br.true_bb() == old {
+                *br.true_bb_mut() = new;
+                *br.true_args_mut() = args.to_vec();
+            }
+            if br.false_bb() == old {
+                *br.false_bb_mut() = new;
+                *br.false_args_mut() = args.to_vec();
+            }
+        },
+        _ => unreachable!(),
+    });
+}
+
+// Copies `bbs`, given in reverse postorder, into new blocks placed before `before`.
+// Values and jump targets inside the copied region are renamed to their copies,
+// everything else is shared with the original.
+pub fn clone_blocks(data: &mut FunctionData, bbs: &[BasicBlock], before: BasicBlock)
+    -> (HashMap<BasicBlock, BasicBlock>, HashMap<Value, Value>) {
+    let mut bb_map = HashMap::new();
+    let mut value_map = HashMap::new();
+    for &bb in bbs {
+        let name = data.dfg().bb(bb).name().clone();
+        let params = data.dfg().bb(bb).params().to_vec();
+        let tys = params.iter().map(|&param| data.dfg().value(param).ty().clone()).collect();
+        let copy = data.dfg_mut().new_bb().basic_block_with_params(name, tys);
+        data.layout_mut().bbs_mut().cursor_mut(before).insert_key_before(copy).unwrap();
+        value_map.extend(params.into_iter().zip(data.dfg().bb(copy).params().to_vec()));
+        bb_map.insert(bb, copy);
+    }
+
+    // Terminators last: they may pass values defined further down a loop
+    let mut terms = Vec::new();
+    for &bb in bbs {
+        let insts = data.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect::<Vec<_>>();
+        let (&term, insts) = insts.split_last().unwrap();
+        for &inst in insts {
+            let copy = clone_inst(data.dfg_mut(), inst, &bb_map, &value_map);
+            data.layout_mut().bb_mut(bb_map[&bb]).insts_mut().push_key_back(copy).unwrap();
+            value_map.insert(inst, copy);
+        }
+        terms.push((bb, term));
+    }
+    for (bb, term) in terms {
+        let copy = clone_inst(data.dfg_mut(), term, &bb_map, &value_map);
+        data.layout_mut().bb_mut(bb_map[&bb]).insts_mut().push_key_back(copy).unwrap();
+    }
+    return (bb_map, value_map);
+}
+
+fn clone_inst(dfg: &mut DataFlowGraph, inst: Value, bb_map: &HashMap<BasicBlock, BasicBlock>, value_map: &HashMap<Value, Value>) -> Value {
+    let mut data = dfg.value(inst).clone();
+    for_each_operand_mut(data.kind_mut(), |operand| {
+        if let Some(&copy) = value_map.get(operand) {
+            *operand = copy;
+        }
+    });
+    let rename = |bb: BasicBlock| bb_map.get(&bb).copied().unwrap_or(bb);
+    match data.kind_mut() {
+        ValueKind::Jump(jump) => *jump.target_mut() = rename(jump.target()),
+        ValueKind::Branch(br) => {
+            *br.true_bb_mut() = rename(br.true_bb());
+            *br.false_bb_mut() = rename(br.false_bb());
+        },
+        _ => {},
+    }
+    dfg.new_value().raw(data)
+}
+
+// Deletes blocks nothing live jumps to, with everything in them
+pub fn remove_blocks(data: &mut FunctionData, bbs: &[BasicBlock]) {
+    let mut dead = Vec::new();
+    for bb in bbs {
+        let (_, node) = data.layout_mut().bbs_mut().remove(bb).unwrap();
+        dead.extend(node.insts().keys().copied());
+    }
+    remove_values(data, &dead);
+    for &bb in bbs {
+        data.dfg_mut().remove_bb(bb);
+    }
+}
+
+// Removes instructions only used by each other. The ones still in the layout are
+// taken out of it first.
+pub fn remove_insts(data: &mut FunctionData, insts: &[Value]) {
+    for &inst in insts {
+        if let Some(bb) = data.layout().parent_bb(inst) {
+            data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+        }
+    }
+    remove_values(data, insts);
+}
+
+fn remove_values(data: &mut FunctionData, values: &[Value]) {
+    let dead = values.iter().collect::<HashSet<_>>();
+    debug_assert!(values.iter().all(|&value| data.dfg().value(value).used_by().iter().all(|user| dead.contains(user))),
+        "removing a value that is still in use");
+    // Dead values may use each other: drop every operand before removing any
+    for &value in values {
+        data.dfg_mut().replace_value_with(value).integer(0);
+    }
+    for &value in values {
+        data.dfg_mut().remove_value(value);
+    }
+}
@@ -0,0 +1,117 @@
+/*
+    Loop-invariant code motion:
+        Inner loops first, pure values (binaries, getptr, getelemptr) whose
+        operands are all defined outside the loop are moved to the end of
+        its preheader, which is created when the loop has none. Division
+        only moves with a non-zero constant divisor, as the block it comes
+        from may not run.
+    Loads of a constant element of a global move too when nothing in the
+    loop may write to it: no aliasing store and no call.
+*/
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, BinaryOp, Function, FunctionData, ValueKind};
+use std::collections::HashSet;
+use super::analysis::{AliasInfo, FunctionAnalyses, Loop};
+use super::ir_edit::*;
+use super::pass_manager::FunctionPass;
+
+pub struct Licm;
+
+impl FunctionPass for Licm {
+    fn name(&self) -> &'static str {
+        "licm"
+    }
+
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData, analyses: &mut FunctionAnalyses) {
+        let entry = data.layout().entry_bb().unwrap();
+        let headers = analyses.loops(data).loops().iter().rev().map(|lp| lp.header).collect::<Vec<_>>();
+        for header in headers {
+            if header == entry {
+                continue;
+            }
+            // A new preheader changes the loops around it: look them up again
+            let (cfg, loops) = (analyses.cfg(data), analyses.loops(data));
+            let lp = loops.loops().iter().find(|lp| lp.header == header).unwrap();
+            let Some(preheader) = preheader(data, cfg.preds(header), lp) else {
+                continue;
+            };
+            hoist(data, lp, preheader);
+        }
+    }
+}
+
+// The block outside `lp` that only jumps to its header, created if needed.
+// None if an outside branch passes different arguments along its two edges to the header.
+fn preheader(data: &mut FunctionData, preds: &[BasicBlock], lp: &Loop) -> Option<BasicBlock> {
+    let outside = preds.iter().copied().filter(|&pred| !lp.contains(pred)).collect::<Vec<_>>();
+    if let &[pred] = outside.as_slice() {
+        if matches!(data.dfg().value(terminator(data, pred)).kind(), ValueKind::Jump(_)) {
+            return Some(pred);
+        }
+    }
+    let mut edges = Vec::new();
+    for &pred in &outside {
+        let term = terminator(data, pred);
+        let args = edge_args(data.dfg().value(term).kind(), lp.header);
+        if args.windows(2).any(|pair| pair[0] != pair[1]) {
+            return None;
+        }
+        edges.push((term, args[0].to_vec()));
+    }
+
+    let header = lp.header;
+    let tys = data.dfg().bb(header).params().iter().map(|&param| data.dfg().value(param).ty().clone()).collect();
+    let preheader = data.dfg_mut().new_bb().basic_block_with_params(Some("%preheader".into()), tys);
+    data.layout_mut().bbs_mut().cursor_mut(header).insert_key_before(preheader).unwrap();
+    let params = data.dfg().bb(preheader).params().to_vec();
+    let jump = data.dfg_mut().new_value().jump_with_args(header, params);
+    data.layout_mut().bb_mut(preheader).insts_mut().push_key_back(jump).unwrap();
+    for (term, args) in edges {
+        retarget(data.dfg_mut(), term, header, preheader, &args);
+    }
+    return Some(preheader);
+}
+
+fn hoist(data: &mut FunctionData, lp: &Loop, preheader: BasicBlock) {
+    let alias = AliasInfo::new(data);
+    let mut in_loop = HashSet::new();
+    let mut stores = Vec::new();
+    let mut has_call = false;
+    for &bb in &lp.blocks {
+        in_loop.extend(data.dfg().bb(bb).params().iter().copied());
+        for &inst in data.layout().bbs().node(&bb).unwrap().insts().keys() {
+            in_loop.insert(inst);
+            match data.dfg().value(inst).kind() {
+                ValueKind::Store(store) => stores.push(store.dest()),
+                ValueKind::Call(_) => has_call = true,
+                _ => {},
+            }
+        }
+    }
+
+    let anchor = terminator(data, preheader);
+    for &bb in &lp.blocks {
+        let insts = data.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect::<Vec<_>>();
+        for inst in insts {
+            let kind = data.dfg().value(inst).kind();
+            let invariant = kind.value_uses().all(|operand| !in_loop.contains(&operand));
+            let movable = invariant && match kind {
+                ValueKind::Binary(bin) => match bin.op() {
+                    BinaryOp::Div | BinaryOp::Mod => matches!(data.dfg().value(bin.rhs()).kind(), ValueKind::Integer(int) if int.value() != 0),
+                    _ => true,
+                },
+                ValueKind::GetPtr(_) | ValueKind::GetElemPtr(_) => true,
+                ValueKind::Load(load) => {
+                    !has_call && alias.is_global_element(data, load.src())
+                        && !stores.iter().any(|&dest| alias.may_alias(data, dest, load.src()))
+                },
+                _ => false,
+            };
+            if movable {
+                data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+                data.layout_mut().bb_mut(preheader).insts_mut().cursor_mut(anchor).insert_key_before(inst).unwrap();
+                in_loop.remove(&inst);
+            }
+        }
+    }
+}
@@ -0,0 +1,156 @@
+/*
+    mem2reg:
+        Promotes scalar allocs (locals, `pa` parameter copies, `%ret`) whose
+        only uses are loads and stores into SSA values. Blocks in the iterated
+        dominance frontier of the stores get one parameter per promoted alloc,
+        and predecessors pass the reaching value as a jump/branch argument.
+*/
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, Function, FunctionData, TypeKind, Value, ValueKind};
+use std::collections::{HashMap, HashSet};
+use super::analysis::{Cfg, DomTree, FunctionAnalyses};
+use super::ir_edit::*;
+use super::pass_manager::FunctionPass;
+
+pub struct Mem2Reg;
+
+impl FunctionPass for Mem2Reg {
+    fn name(&self) -> &'static str {
+        "mem2reg"
+    }
+
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData, analyses: &mut FunctionAnalyses) {
+        // Unreachable blocks would need arguments too: they go first
+        let unreachable = analyses.cfg(data).unreachable(data);
+        remove_blocks(data, &unreachable);
+
+        let allocs = promotable_allocs(data);
+        if allocs.is_empty() {
+            return;
+        }
+        let cfg = analyses.cfg(data);
+        let dom = analyses.dom_tree(data);
+        let params = insert_params(data, &dom, &allocs);
+        rename(data, &cfg, &dom, &allocs, &params);
+        for alloc in allocs {
+            remove_inst(data, alloc);
+        }
+    }
+}
+
+// Allocs of a scalar whose address never escapes, in layout order
+fn promotable_allocs(data: &FunctionData) -> Vec<Value> {
+    let entry = data.layout().entry_bb().unwrap();
+    let insts = data.layout().bbs().node(&entry).unwrap().insts();
+    return insts.keys().copied().filter(|&inst| {
+        let value = data.dfg().value(inst);
+        let is_scalar = match value.ty().kind() {
+            TypeKind::Pointer(base) => matches!(base.kind(), TypeKind::Int32 | TypeKind::Pointer(_)),
+            _ => false,
+        };
+        matches!(value.kind(), ValueKind::Alloc(_)) && is_scalar && value.used_by().iter().all(|&user| {
+            match data.dfg().value(user).kind() {
+                ValueKind::Load(_) => true,
+                ValueKind::Store(store) => store.dest() == inst && store.value() != inst,
+                _ => false,
+            }
+        })
+    }).collect();
+}
+
+// Block parameters for each alloc: the iterated dominance frontier of its stores
+fn insert_params(data: &mut FunctionData, dom: &DomTree, allocs: &[Value]) -> HashMap<BasicBlock, Vec<(Value, Value)>> {
+    let mut params: HashMap<BasicBlock, Vec<(Value, Value)>> = HashMap::new(); // bb => [(alloc, param)]
+    for &alloc in allocs {
+        let mut work = data.dfg().value(alloc).used_by().iter()
+            .filter(|&&user| matches!(data.dfg().value(user).kind(), ValueKind::Store(_)))
+            .map(|&store| data.layout().parent_bb(store).unwrap())
+            .collect::<Vec<_>>();
+        let ty = match data.dfg().value(alloc).ty().kind() {
+            TypeKind::Pointer(base) => base.clone(),
+            _ => unreachable!(),
+        };
+        let mut placed = HashSet::new();
+        while let Some(bb) = work.pop() {
+            for &frontier in dom.frontier(bb) {
+                if placed.insert(frontier) {
+                    let param = add_block_param(data.dfg_mut(), frontier, ty.clone());
+                    params.entry(frontier).or_default().push((alloc, param));
+                    work.push(frontier);
+                }
+            }
+        }
+    }
+    return params;
+}
+
+// Walks the dominator tree keeping the current value of every alloc on a stack
+fn rename(data: &mut FunctionData, cfg: &Cfg, dom: &DomTree, allocs: &[Value], params: &HashMap<BasicBlock, Vec<(Value, Value)>>) {
+    let mut current: HashMap<Value, Vec<Value>> = HashMap::new();
+    for &alloc in allocs {
+        let ty = match data.dfg().value(alloc).ty().kind() {
+            TypeKind::Pointer(base) => base.clone(),
+            _ => unreachable!(),
+        };
+        // Read before any store: the value is unspecified
+        let undef = data.dfg_mut().new_value().undef(ty);
+        current.insert(alloc, vec![undef]);
+    }
+
+    enum Visit {
+        Enter(BasicBlock),
+        Exit(Vec<Value>), // Allocs whose stack grew in the block
+    }
+    let mut stack = vec![Visit::Enter(cfg.entry())];
+    while let Some(visit) = stack.pop() {
+        let bb = match visit {
+            Visit::Enter(bb) => bb,
+            Visit::Exit(pushed) => {
+                for alloc in pushed {
+                    current.get_mut(&alloc).unwrap().pop();
+                }
+                continue;
+            },
+        };
+
+        let mut pushed = Vec::new();
+        for &(alloc, param) in params.get(&bb).into_iter().flatten() {
+            current.get_mut(&alloc).unwrap().push(param);
+            pushed.push(alloc);
+        }
+        let insts = data.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect::<Vec<_>>();
+        for inst in insts {
+            match data.dfg().value(inst).kind() {
+                ValueKind::Load(load) if current.contains_key(&load.src()) => {
+                    let value = *current[&load.src()].last().unwrap();
+                    replace_all_uses(data.dfg_mut(), inst, value);
+                    remove_inst(data, inst);
+                },
+                ValueKind::Store(store) if current.contains_key(&store.dest()) => {
+                    let (value, alloc) = (store.value(), store.dest());
+                    current.get_mut(&alloc).unwrap().push(value);
+                    pushed.push(alloc);
+                    remove_inst(data, inst);
+                },
+                _ => {},
+            }
+        }
+
+        let term = terminator(data, bb);
+        for &succ in cfg.succs(bb) {
+            for &(alloc, _) in params.get(&succ).into_iter().flatten() {
+                let value = *current[&alloc].last().unwrap();
+                push_edge_arg(data.dfg_mut(), term, succ, value);
+            }
+        }
+
+        stack.push(Visit::Exit(pushed));
+        stack.extend(dom.children(bb).iter().rev().map(|&child| Visit::Enter(child)));
+    }
+
+    for (_, values) in current {
+        if data.dfg().value(values[0]).used_by().is_empty() {
+            data.dfg_mut().remove_value(values[0]);
+        }
+    }
+}
@@ -0,0 +1,95 @@
+/*
+    IR Optimizer:
+        Passes over the generated Koopa IR, run by a PassManager.
+        Each optimization level has its own pipeline, and any registered
+        pass can be switched on or off by name.
+*/
+pub mod analysis;
+pub mod pass_manager;
+mod dce;
+mod gvn;
+mod inline;
+mod ir_edit;
+mod licm;
+mod mem2reg;
+mod sccp;
+mod strength;
+mod tre;
+mod unroll;
+mod verify;
+
+use koopa::ir::Program;
+use pass_manager::*;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    #[default]
+    O0,
+    O1,
+    O2,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct OptOptions {
+    pub level: OptLevel,
+    pub enable: Vec<String>,             // Run after the level's pipeline
+    pub disable: Vec<String>,            // Removed from the pipeline
+    pub dump_after: Vec<String>,         // Pass names or "all"
+    pub inline_threshold: Option<usize>, // None: the inliner's default
+    pub unroll_factor: Option<usize>,    // None: the unroller's default
+}
+
+// Every pass known by name
+fn create_pass(name: &str, options: &OptOptions) -> Option<Box<dyn Pass>> {
+    let pass: Box<dyn Pass> = match name {
+        "dce" => Box::new(ForEachFunction(dce::Dce)),
+        "gvn" => Box::new(ForEachFunction(gvn::Gvn)),
+        "inline" => Box::new(inline::Inline{ threshold: options.inline_threshold.unwrap_or(inline::DEFAULT_THRESHOLD) }),
+        "licm" => Box::new(ForEachFunction(licm::Licm)),
+        "mem2reg" => Box::new(ForEachFunction(mem2reg::Mem2Reg)),
+        "sccp" => Box::new(ForEachFunction(sccp::Sccp)),
+        "strength" => Box::new(ForEachFunction(strength::StrengthReduce)),
+        "tre" => Box::new(ForEachFunction(tre::Tre)),
+        "unroll" => Box::new(ForEachFunction(unroll::Unroll{ factor: options.unroll_factor.unwrap_or(unroll::DEFAULT_FACTOR) })),
+        "verify" => Box::new(ForEachFunction(verify::Verify)),
+        _ => return None,
+    };
+    return Some(pass);
+}
+
+pub fn is_known_pass(name: &str) -> bool {
+    create_pass(name, &OptOptions::default()).is_some()
+}
+
+fn pipeline(level: OptLevel) -> &'static [&'static str] {
+    match level {
+        OptLevel::O0 => &[],
+        OptLevel::O1 => &["mem2reg", "sccp", "dce"],
+        OptLevel::O2 => &["mem2reg", "sccp", "dce", "tre", "inline", "sccp", "gvn", "licm", "strength", "unroll", "sccp", "dce"],
+    }
+}
+
+pub fn optimize(program: &mut Program, level: OptLevel) {
+    optimize_with(program, &OptOptions{ level, ..OptOptions::default() });
+}
+
+pub fn optimize_with(program: &mut Program, options: &OptOptions) {
+    let mut names = pipeline(options.level).iter()
+        .map(|name| name.to_string())
+        .filter(|name| !options.disable.contains(name))
+        .collect::<Vec<_>>();
+    for name in &options.enable {
+        if !names.contains(name) {
+            names.push(name.clone());
+        }
+    }
+
+    let mut pass_manager = PassManager::default();
+    for name in &names {
+        pass_manager.add(create_pass(name, options).unwrap_or_else(|| panic!("unknown pass `{}`", name)));
+    }
+    for name in &options.dump_after {
+        pass_manager.dump_after(name);
+    }
+    pass_manager.run(program);
+}
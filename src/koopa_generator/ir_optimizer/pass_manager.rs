@@ -0,0 +1,75 @@
+use koopa::back::KoopaGenerator;
+use koopa::ir::{Function, FunctionData, Program};
+use super::analysis::{AnalysisManager, FunctionAnalyses};
+use super::verify::Verify;
+
+// Passes are known by name on the command line: --enable-pass, --disable-pass, --dump-after
+pub trait Pass {
+    fn name(&self) -> &'static str;
+    fn run(&mut self, program: &mut Program, analyses: &mut AnalysisManager);
+}
+
+// A pass that looks at one function at a time, declarations are skipped.
+// Cached analyses stay valid as long as the control flow does not change.
+pub trait FunctionPass {
+    fn name(&self) -> &'static str;
+    fn run_on(&mut self, func: Function, data: &mut FunctionData, analyses: &mut FunctionAnalyses);
+}
+
+// Adapter running a FunctionPass over every defined function of the program
+pub struct ForEachFunction<P: FunctionPass>(pub P);
+
+impl<P: FunctionPass> Pass for ForEachFunction<P> {
+    fn name(&self) -> &'static str {
+        self.0.name()
+    }
+
+    fn run(&mut self, program: &mut Program, analyses: &mut AnalysisManager) {
+        for func in program.func_layout().to_vec() {
+            let data = program.func_mut(func);
+            if data.layout().entry_bb().is_some() {
+                self.0.run_on(func, data, analyses.function(func));
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct PassManager {
+    passes: Vec<Box<dyn Pass>>,
+    analyses: AnalysisManager,
+    dump_after: Vec<String>, // Pass names, "all" dumps after every pass
+}
+
+impl PassManager {
+    pub fn add(&mut self, pass: Box<dyn Pass>) {
+        self.passes.push(pass);
+    }
+
+    pub fn dump_after(&mut self, name: &str) {
+        self.dump_after.push(name.to_owned());
+    }
+
+    // Debug builds verify the IR after every pass, so a broken one is caught where it broke
+    pub fn run(&mut self, program: &mut Program) {
+        for pass in &mut self.passes {
+            pass.run(program, &mut self.analyses);
+            let name = pass.name();
+            if cfg!(debug_assertions) {
+                ForEachFunction(Verify).run(program, &mut self.analyses);
+            }
+            if self.dump_after.iter().any(|dump| dump == name || dump == "all") {
+                dump_program(program, name);
+            }
+        }
+    }
+}
+
+// Textual Koopa IR on stderr, so it never mixes with -o -
+fn dump_program(program: &Program, after: &str) {
+    let mut text = Vec::new();
+    if KoopaGenerator::new(&mut text).generate_on(program).is_ok() {
+        eprintln!("; ---------- IR after {} ----------", after);
+        eprintln!("{}", String::from_utf8_lossy(&text));
+    }
+}
@@ -0,0 +1,241 @@
+/*
+    Sparse conditional constant propagation (Wegman and Zadeck):
+        Values start unknown and only move down the lattice
+        Unknown -> Const(c) -> Varying, and blocks count only once an
+        executable edge reaches them. Block parameters meet the arguments
+        of their executable incoming edges.
+    Constant binaries and parameters are then replaced by integers, and
+    branches on a constant become jumps; the blocks this cuts off are
+    left for dce.
+*/
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, BinaryOp, Function, FunctionData, Value, ValueKind};
+use std::collections::{HashMap, HashSet};
+use super::analysis::{Cfg, FunctionAnalyses};
+use super::ir_edit::*;
+use super::pass_manager::FunctionPass;
+
+pub struct Sccp;
+
+impl FunctionPass for Sccp {
+    fn name(&self) -> &'static str {
+        "sccp"
+    }
+
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData, analyses: &mut FunctionAnalyses) {
+        let cfg = analyses.cfg(data);
+        let mut solver = Solver::new(data, &cfg);
+        solver.solve();
+        let Solver{ lattice, executable, .. } = solver;
+        rewrite(data, &lattice, &executable);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Lattice {
+    Unknown,
+    Const(i32),
+    Varying,
+}
+
+impl Lattice {
+    fn meet(self, other: Lattice) -> Lattice {
+        match (self, other) {
+            (Self::Unknown, l) | (l, Self::Unknown) => l,
+            (Self::Const(a), Self::Const(b)) if a == b => self,
+            _ => Self::Varying,
+        }
+    }
+}
+
+struct Solver<'a> {
+    data: &'a FunctionData,
+    cfg: &'a Cfg,
+    lattice: HashMap<Value, Lattice>,
+    edges: HashSet<(BasicBlock, BasicBlock)>, // Executable edges
+    executable: HashSet<BasicBlock>,
+    value_work: Vec<Value>, // Values whose lattice changed
+}
+
+impl<'a> Solver<'a> {
+    fn new(data: &'a FunctionData, cfg: &'a Cfg) -> Self {
+        Self{
+            data,
+            cfg,
+            lattice: HashMap::new(),
+            edges: HashSet::new(),
+            executable: HashSet::new(),
+            value_work: Vec::new(),
+        }
+    }
+
+    fn solve(&mut self) {
+        let entry = self.data.layout().entry_bb().unwrap();
+        self.executable.insert(entry);
+        self.visit_block(entry);
+        while let Some(value) = self.value_work.pop() {
+            let users = self.data.dfg().value(value).used_by().iter().copied().collect::<Vec<_>>();
+            for user in users {
+                let in_executable = self.data.layout().parent_bb(user).is_some_and(|bb| self.executable.contains(&bb));
+                if in_executable {
+                    self.visit_inst(user);
+                }
+            }
+        }
+    }
+
+    fn value(&self, value: Value) -> Lattice {
+        if value.is_global() {
+            return Lattice::Varying;
+        }
+        match self.data.dfg().value(value).kind() {
+            ValueKind::Integer(int) => Lattice::Const(int.value()),
+            // The backend materializes undef as 0, folding must agree with it
+            ValueKind::Undef(_) => Lattice::Const(0),
+            ValueKind::FuncArgRef(_) => Lattice::Varying,
+            _ => self.lattice.get(&value).copied().unwrap_or(Lattice::Unknown),
+        }
+    }
+
+    fn set(&mut self, value: Value, new: Lattice) {
+        let old = self.value(value);
+        let new = old.meet(new); // Never climbs back up
+        if new != old {
+            self.lattice.insert(value, new);
+            self.value_work.push(value);
+        }
+    }
+
+    fn visit_block(&mut self, bb: BasicBlock) {
+        let insts = self.data.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect::<Vec<_>>();
+        for inst in insts {
+            self.visit_inst(inst);
+        }
+    }
+
+    fn visit_inst(&mut self, inst: Value) {
+        let bb = self.data.layout().parent_bb(inst).unwrap();
+        match self.data.dfg().value(inst).kind() {
+            ValueKind::Binary(bin) => {
+                let result = fold(bin.op(), self.value(bin.lhs()), self.value(bin.rhs()));
+                self.set(inst, result);
+            },
+            ValueKind::Jump(jump) => self.mark_edge(bb, jump.target()),
+            ValueKind::Branch(br) => {
+                let (true_bb, false_bb) = (br.true_bb(), br.false_bb());
+                match self.value(br.cond()) {
+                    Lattice::Unknown => {},
+                    Lattice::Const(cond) => self.mark_edge(bb, if cond != 0 { true_bb } else { false_bb }),
+                    Lattice::Varying => {
+                        self.mark_edge(bb, true_bb);
+                        self.mark_edge(bb, false_bb);
+                    },
+                }
+            },
+            ValueKind::Store(_) | ValueKind::Return(_) => {},
+            _ => self.set(inst, Lattice::Varying),
+        }
+    }
+
+    // Also called again when the arguments along an executable edge change
+    fn mark_edge(&mut self, from: BasicBlock, to: BasicBlock) {
+        self.edges.insert((from, to));
+        self.update_params(to);
+        if self.executable.insert(to) {
+            self.visit_block(to);
+        }
+    }
+
+    fn update_params(&mut self, bb: BasicBlock) {
+        let params = self.data.dfg().bb(bb).params().to_vec();
+        if params.is_empty() {
+            return;
+        }
+        let preds = self.cfg.preds(bb).iter().copied().filter(|&pred| self.edges.contains(&(pred, bb))).collect::<Vec<_>>();
+        for (index, &param) in params.iter().enumerate() {
+            let mut result = Lattice::Unknown;
+            for &pred in &preds {
+                let term = terminator(self.data, pred);
+                for args in edge_args(self.data.dfg().value(term).kind(), bb) {
+                    result = result.meet(self.value(args[index]));
+                }
+            }
+            self.set(param, result);
+        }
+    }
+}
+
+fn fold(op: BinaryOp, lhs: Lattice, rhs: Lattice) -> Lattice {
+    // Absorbing constants decide the result whatever the other side is
+    let absorbs = |l: Lattice, zero: i32| l == Lattice::Const(zero);
+    match op {
+        BinaryOp::Mul | BinaryOp::And if absorbs(lhs, 0) || absorbs(rhs, 0) => return Lattice::Const(0),
+        BinaryOp::Or if absorbs(lhs, -1) || absorbs(rhs, -1) => return Lattice::Const(-1),
+        _ => {},
+    }
+    let (a, b) = match (lhs, rhs) {
+        (Lattice::Const(a), Lattice::Const(b)) => (a, b),
+        (Lattice::Varying, _) | (_, Lattice::Varying) => return Lattice::Varying,
+        _ => return Lattice::Unknown,
+    };
+    let result = match op {
+        BinaryOp::Add => a.wrapping_add(b),
+        BinaryOp::Sub => a.wrapping_sub(b),
+        BinaryOp::Mul => a.wrapping_mul(b),
+        // Division by zero is left for the program to hit at run time
+        BinaryOp::Div if b == 0 => return Lattice::Varying,
+        BinaryOp::Mod if b == 0 => return Lattice::Varying,
+        BinaryOp::Div => a.wrapping_div(b),
+        BinaryOp::Mod => a.wrapping_rem(b),
+        BinaryOp::And => a & b,
+        BinaryOp::Or => a | b,
+        BinaryOp::Xor => a ^ b,
+        // RISC-V only uses the low five bits of the shift amount
+        BinaryOp::Shl => a.wrapping_shl(b as u32),
+        BinaryOp::Shr => ((a as u32).wrapping_shr(b as u32)) as i32,
+        BinaryOp::Sar => a.wrapping_shr(b as u32),
+        BinaryOp::Eq => (a == b) as i32,
+        BinaryOp::NotEq => (a != b) as i32,
+        BinaryOp::Lt => (a < b) as i32,
+        BinaryOp::Gt => (a > b) as i32,
+        BinaryOp::Le => (a <= b) as i32,
+        BinaryOp::Ge => (a >= b) as i32,
+    };
+    return Lattice::Const(result);
+}
+
+fn rewrite(data: &mut FunctionData, lattice: &HashMap<Value, Lattice>, executable: &HashSet<BasicBlock>) {
+    let bbs = data.layout().bbs().keys().copied().filter(|bb| executable.contains(bb)).collect::<Vec<_>>();
+    for bb in bbs {
+        let mut folded = data.dfg().bb(bb).params().to_vec();
+        folded.extend(data.layout().bbs().node(&bb).unwrap().insts().keys().copied());
+        for value in folded {
+            let Some(&Lattice::Const(c)) = lattice.get(&value) else {
+                continue;
+            };
+            let is_binary = matches!(data.dfg().value(value).kind(), ValueKind::Binary(_));
+            if !is_binary && !matches!(data.dfg().value(value).kind(), ValueKind::BlockArgRef(_)) {
+                continue;
+            }
+            let int = data.dfg_mut().new_value().integer(c);
+            replace_all_uses(data.dfg_mut(), value, int);
+            // Parameters go with their arguments in dce
+            if is_binary {
+                remove_inst(data, value);
+            }
+        }
+
+        let term = terminator(data, bb);
+        if let ValueKind::Branch(br) = data.dfg().value(term).kind() {
+            if let ValueKind::Integer(cond) = data.dfg().value(br.cond()).kind() {
+                let (target, args) = if cond.value() != 0 {
+                    (br.true_bb(), br.true_args().to_vec())
+                }
+                else {
+                    (br.false_bb(), br.false_args().to_vec())
+                };
+                data.dfg_mut().replace_value_with(term).jump_with_args(target, args);
+            }
+        }
+    }
+}
@@ -0,0 +1,186 @@
+/*
+    Strength reduction:
+        Multiplying by a power of two becomes a shift. Division and
+        remainder by a constant are left to the backend, which turns them
+        into shifts or a multiply-high without spilling the intermediate
+        results.
+    In a loop with a preheader and a single latch, a header parameter
+    stepped by a constant on the latch is an induction variable `i`.
+    `i * c` and `getelemptr / getptr base, i` with an invariant base then
+    get induction variables of their own, updated by an add or a getptr
+    on the latch instead of recomputed from `i`.
+*/
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, BinaryOp, Function, FunctionData, Value, ValueKind};
+use std::collections::{HashMap, HashSet};
+use super::analysis::{Cfg, FunctionAnalyses, Loop};
+use super::ir_edit::*;
+use super::pass_manager::FunctionPass;
+
+pub struct StrengthReduce;
+
+impl FunctionPass for StrengthReduce {
+    fn name(&self) -> &'static str {
+        "strength"
+    }
+
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData, analyses: &mut FunctionAnalyses) {
+        let insts = data.layout().bbs().nodes().flat_map(|node| node.insts().keys().copied()).collect::<Vec<_>>();
+        let reduced = insts.into_iter()
+            .filter_map(|inst| reduce_binary(data, inst).map(|result| (inst, result)))
+            .collect::<HashMap<_, _>>();
+        // All at once: a long chain of users is rebuilt once, not once per multiply
+        replace_uses(data.dfg_mut(), &reduced);
+        for &inst in reduced.keys() {
+            remove_inst(data, inst);
+        }
+
+        let (cfg, loops) = (analyses.cfg(data), analyses.loops(data));
+        for lp in loops.loops().iter().rev() {
+            reduce_loop(data, &cfg, lp);
+        }
+    }
+}
+
+fn constant(data: &FunctionData, value: Value) -> Option<i32> {
+    match data.dfg().value(value).kind() {
+        ValueKind::Integer(int) => Some(int.value()),
+        _ => None,
+    }
+}
+
+// log2 of a positive power of two
+fn exact_log2(c: i32) -> Option<i32> {
+    (c > 0 && c & (c - 1) == 0).then(|| c.trailing_zeros() as i32)
+}
+
+// The value replacing `inst`, a shift inserted before it
+fn reduce_binary(data: &mut FunctionData, inst: Value) -> Option<Value> {
+    let ValueKind::Binary(bin) = data.dfg().value(inst).kind() else {
+        return None;
+    };
+    if bin.op() != BinaryOp::Mul {
+        return None;
+    }
+    let (x, k) = match (constant(data, bin.lhs()), constant(data, bin.rhs())) {
+        (Some(c), None) => (bin.rhs(), exact_log2(c)),
+        (None, Some(c)) => (bin.lhs(), exact_log2(c)),
+        _ => return None,
+    };
+    let result = match k {
+        None => return None,
+        Some(0) => x,
+        Some(k) => {
+            let k = data.dfg_mut().new_value().integer(k);
+            let shl = data.dfg_mut().new_value().binary(BinaryOp::Shl, x, k);
+            let bb = data.layout().parent_bb(inst).unwrap();
+            data.layout_mut().bb_mut(bb).insts_mut().cursor_mut(inst).insert_key_before(shl).unwrap();
+            shl
+        },
+    };
+    return Some(result);
+}
+
+// A header parameter: its value on entry and its step on the latch
+struct Induction {
+    init: Value,
+    step: i32,
+}
+
+fn reduce_loop(data: &mut FunctionData, cfg: &Cfg, lp: &Loop) {
+    let (Some(preheader), &[latch]) = (lp.preheader(cfg), lp.latches.as_slice()) else {
+        return;
+    };
+    if !matches!(data.dfg().value(terminator(data, preheader)).kind(), ValueKind::Jump(_)) {
+        return;
+    }
+    let header = lp.header;
+    let params = data.dfg().bb(header).params().to_vec();
+    let entry_args = edge_args(data.dfg().value(terminator(data, preheader)).kind(), header)[0].to_vec();
+    let latch_args = match edge_args(data.dfg().value(terminator(data, latch)).kind(), header).as_slice() {
+        &[args] => args.to_vec(),
+        _ => return,
+    };
+
+    let mut ivs = HashMap::new();
+    for (index, &param) in params.iter().enumerate() {
+        let ValueKind::Binary(bin) = data.dfg().value(latch_args[index]).kind() else {
+            continue;
+        };
+        let step = match (bin.op(), bin.lhs() == param, bin.rhs() == param) {
+            (BinaryOp::Add, true, false) => constant(data, bin.rhs()),
+            (BinaryOp::Add, false, true) => constant(data, bin.lhs()),
+            (BinaryOp::Sub, true, false) => constant(data, bin.rhs()).map(i32::wrapping_neg),
+            _ => None,
+        };
+        if let Some(step) = step {
+            ivs.insert(param, Induction{ init: entry_args[index], step });
+        }
+    }
+    if ivs.is_empty() {
+        return;
+    }
+
+    let mut in_loop = HashSet::new();
+    for &bb in &lp.blocks {
+        in_loop.extend(data.dfg().bb(bb).params().iter().copied());
+        in_loop.extend(data.layout().bbs().node(&bb).unwrap().insts().keys().copied());
+    }
+    for &bb in &lp.blocks {
+        let insts = data.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect::<Vec<_>>();
+        for inst in insts {
+            reduce_derived(data, &ivs, &mut in_loop, inst, [preheader, header, latch]);
+        }
+    }
+}
+
+// Gives `inst` an induction variable of its own if it is a multiple of, or
+// an address indexed by, one of `ivs`. The new header parameter and its step
+// join `in_loop`: an address based on them is not invariant.
+fn reduce_derived(data: &mut FunctionData, ivs: &HashMap<Value, Induction>, in_loop: &mut HashSet<Value>, inst: Value, [preheader, header, latch]: [BasicBlock; 3]) {
+    enum Derived {
+        Mul(i32),
+        ElemPtr(Value),
+        Ptr(Value),
+    }
+    let (iv, derived) = match data.dfg().value(inst).kind() {
+        ValueKind::Binary(bin) if bin.op() == BinaryOp::Mul => {
+            match (constant(data, bin.lhs()), constant(data, bin.rhs())) {
+                (None, Some(c)) => (bin.lhs(), Derived::Mul(c)),
+                (Some(c), None) => (bin.rhs(), Derived::Mul(c)),
+                _ => return,
+            }
+        },
+        ValueKind::GetElemPtr(gep) if !in_loop.contains(&gep.src()) => (gep.index(), Derived::ElemPtr(gep.src())),
+        ValueKind::GetPtr(gp) if !in_loop.contains(&gp.src()) => (gp.index(), Derived::Ptr(gp.src())),
+        _ => return,
+    };
+    let Some(Induction{ init, step }) = ivs.get(&iv) else {
+        return;
+    };
+    let (init, step) = (*init, *step);
+
+    let ty = data.dfg().value(inst).ty().clone();
+    let param = add_block_param(data.dfg_mut(), header, ty);
+    let step = match derived {
+        Derived::Mul(c) => data.dfg_mut().new_value().integer(c.wrapping_mul(step)),
+        _ => data.dfg_mut().new_value().integer(step),
+    };
+    let (start, next) = match derived {
+        Derived::Mul(c) => {
+            let c = data.dfg_mut().new_value().integer(c);
+            let start = data.dfg_mut().new_value().binary(BinaryOp::Mul, init, c);
+            (start, data.dfg_mut().new_value().binary(BinaryOp::Add, param, step))
+        },
+        Derived::ElemPtr(base) => (data.dfg_mut().new_value().get_elem_ptr(base, init), data.dfg_mut().new_value().get_ptr(param, step)),
+        Derived::Ptr(base) => (data.dfg_mut().new_value().get_ptr(base, init), data.dfg_mut().new_value().get_ptr(param, step)),
+    };
+    in_loop.extend([param, next]);
+    for (bb, value) in [(preheader, start), (latch, next)] {
+        insert_before_terminator(data, bb, value);
+        let term = terminator(data, bb);
+        push_edge_arg(data.dfg_mut(), term, header, value);
+    }
+    replace_all_uses(data.dfg_mut(), inst, param);
+    remove_inst(data, inst);
+}
@@ -0,0 +1,124 @@
+/*
+    Tail-recursion elimination:
+        A self-call is a tail call when nothing follows it in its block but
+        a jump carrying its result to a block that only returns it (the
+        `%end` block once mem2reg has promoted `%ret`), or a direct `ret`.
+    The body of the entry block moves to a new loop header taking the
+    function parameters, and each tail call becomes a jump back to it
+    with the call arguments. Allocs stay in the entry block, so every
+    iteration shares them: a call passing a pointer into one is no tail call.
+*/
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, Function, FunctionData, TypeKind, Value, ValueKind};
+use super::analysis::FunctionAnalyses;
+use super::ir_edit::*;
+use super::pass_manager::FunctionPass;
+
+pub struct Tre;
+
+impl FunctionPass for Tre {
+    fn name(&self) -> &'static str {
+        "tre"
+    }
+
+    fn run_on(&mut self, func: Function, data: &mut FunctionData, _analyses: &mut FunctionAnalyses) {
+        let calls = data.layout().bbs().iter()
+            .flat_map(|(_, node)| node.insts().keys().copied())
+            .filter(|&inst| is_tail_call(data, func, inst))
+            .collect::<Vec<_>>();
+        if calls.is_empty() {
+            return;
+        }
+
+        let header = loop_header(data);
+        for call in calls {
+            let bb = data.layout().parent_bb(call).unwrap();
+            let term = terminator(data, bb);
+            let args = match data.dfg().value(call).kind() {
+                ValueKind::Call(call) => call.args().to_vec(),
+                _ => unreachable!(),
+            };
+            data.dfg_mut().replace_value_with(term).jump_with_args(header, args);
+            remove_inst(data, call);
+        }
+    }
+}
+
+fn is_tail_call(data: &FunctionData, func: Function, inst: Value) -> bool {
+    match data.dfg().value(inst).kind() {
+        ValueKind::Call(call) if call.callee() == func => {
+            if call.args().iter().any(|&arg| may_point_to_local(data, arg)) {
+                return false;
+            }
+        },
+        _ => return false,
+    }
+    let bb = data.layout().parent_bb(inst).unwrap();
+    let insts = data.layout().bbs().node(&bb).unwrap().insts();
+    if insts.cursor(inst).next_key() != insts.back_key() {
+        return false;
+    }
+    // Follow the result through blocks doing nothing else with it
+    let is_unit = data.dfg().value(inst).ty().is_unit();
+    let (mut result, mut term) = (Some(inst).filter(|_| !is_unit), terminator(data, bb));
+    for _ in 0..data.layout().bbs().len() {
+        let target = match data.dfg().value(term).kind() {
+            ValueKind::Return(ret) => return ret.value() == result,
+            ValueKind::Jump(jump) => {
+                if let Some(value) = result {
+                    let Some(index) = jump.args().iter().position(|&arg| arg == value) else {
+                        return false;
+                    };
+                    result = Some(data.dfg().bb(jump.target()).params()[index]);
+                }
+                jump.target()
+            },
+            _ => return false,
+        };
+        if data.layout().bbs().node(&target).unwrap().insts().len() != 1 {
+            return false;
+        }
+        term = terminator(data, target);
+    }
+    return false;
+}
+
+// Whether `value` may be an address in a local alloc: only addresses based on
+// a global or a parameter of the function are known not to be
+fn may_point_to_local(data: &FunctionData, value: Value) -> bool {
+    if value.is_global() {
+        return false;
+    }
+    let value = data.dfg().value(value);
+    match value.kind() {
+        ValueKind::GetElemPtr(gep) => may_point_to_local(data, gep.src()),
+        ValueKind::GetPtr(gp) => may_point_to_local(data, gp.src()),
+        ValueKind::FuncArgRef(_) => false,
+        _ => matches!(value.ty().kind(), TypeKind::Pointer(_)),
+    }
+}
+
+// Moves the entry block, allocs aside, to a new block taking the function
+// parameters, which replace them everywhere else
+fn loop_header(data: &mut FunctionData) -> BasicBlock {
+    let entry = data.layout().entry_bb().unwrap();
+    let params = data.params().to_vec();
+    let tys = params.iter().map(|&param| data.dfg().value(param).ty().clone()).collect();
+    let header = data.dfg_mut().new_bb().basic_block_with_params(Some("%tail_entry".into()), tys);
+    data.layout_mut().bbs_mut().cursor_mut(entry).insert_key_after(header).unwrap();
+
+    let insts = data.layout().bbs().node(&entry).unwrap().insts().keys().copied().collect::<Vec<_>>();
+    for inst in insts {
+        if !matches!(data.dfg().value(inst).kind(), ValueKind::Alloc(_)) {
+            data.layout_mut().bb_mut(entry).insts_mut().remove(&inst);
+            data.layout_mut().bb_mut(header).insts_mut().push_key_back(inst).unwrap();
+        }
+    }
+    let header_params = data.dfg().bb(header).params().to_vec();
+    for (&param, &header_param) in params.iter().zip(&header_params) {
+        replace_all_uses(data.dfg_mut(), param, header_param);
+    }
+    let jump = data.dfg_mut().new_value().jump_with_args(header, params);
+    data.layout_mut().bb_mut(entry).insts_mut().push_key_back(jump).unwrap();
+    return header;
+}
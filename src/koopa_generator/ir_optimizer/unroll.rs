@@ -0,0 +1,229 @@
+/*
+    Loop unrolling for counted innermost loops:
+        The header must be the only exit and end with `br i < n` (or <=, >,
+        >=), `i` a header parameter stepped by a constant on the single latch
+        and `n` invariant.
+    A loop running a small constant number of times is replaced by that
+    many copies of its body; the original stays behind them, where sccp
+    sees it can only exit. Otherwise `factor` copies are chained and only
+    the first one tests whether `factor` more iterations remain; when they
+    don't, the original loop runs the remainder. `i + (factor - 1) * step`
+    is assumed not to overflow.
+*/
+use koopa::ir::builder_traits::*;
+use koopa::ir::{BasicBlock, BinaryOp, Function, FunctionData, Value, ValueKind};
+use std::collections::HashSet;
+use super::analysis::{Cfg, FunctionAnalyses, Loop};
+use super::ir_edit::*;
+use super::pass_manager::FunctionPass;
+
+pub const DEFAULT_FACTOR: usize = 4;
+const MAX_UNROLLED_SIZE: usize = 200; // Instructions in all the copies together
+const MAX_FULL_TRIPS: usize = 32;
+
+pub struct Unroll {
+    pub factor: usize,
+}
+
+impl FunctionPass for Unroll {
+    fn name(&self) -> &'static str {
+        "unroll"
+    }
+
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData, analyses: &mut FunctionAnalyses) {
+        let loops = analyses.loops(data);
+        let parents = loops.loops().iter().filter_map(|lp| lp.parent).collect::<HashSet<_>>();
+        let headers = loops.loops().iter().enumerate()
+            .filter(|(index, _)| !parents.contains(index))
+            .map(|(_, lp)| lp.header)
+            .collect::<Vec<_>>();
+        for header in headers {
+            // Earlier loops changed the control flow: look them up again
+            let (cfg, loops) = (analyses.cfg(data), analyses.loops(data));
+            let lp = loops.loops().iter().find(|lp| lp.header == header).unwrap();
+            let Some(counted) = CountedLoop::of(data, &cfg, lp) else {
+                continue;
+            };
+            let size = lp.blocks.iter().map(|bb| data.layout().bbs().node(bb).unwrap().insts().len()).sum::<usize>();
+            match counted.trip_count(data) {
+                Some(trips) if trips > 0 && trips <= MAX_FULL_TRIPS && trips * size <= MAX_UNROLLED_SIZE => {
+                    unroll_fully(data, lp, &counted, trips);
+                },
+                _ if self.factor > 1 && self.factor * size <= MAX_UNROLLED_SIZE => {
+                    unroll_by(data, lp, &counted, self.factor);
+                },
+                _ => {},
+            }
+        }
+    }
+}
+
+struct CountedLoop {
+    header: BasicBlock,
+    preheader: BasicBlock,
+    latch: BasicBlock,
+    iv: usize,        // Index of `i` among the header parameters
+    step: i32,
+    op: BinaryOp,     // `i op bound` keeps the loop running
+    bound: Value,
+}
+
+impl CountedLoop {
+    fn of(data: &FunctionData, cfg: &Cfg, lp: &Loop) -> Option<Self> {
+        let header = lp.header;
+        let (preheader, &[latch]) = (lp.preheader(cfg)?, lp.latches.as_slice()) else {
+            return None;
+        };
+        let preheader_jumps = matches!(data.dfg().value(terminator(data, preheader)).kind(), ValueKind::Jump(_));
+        if !preheader_jumps || lp.exits(cfg).iter().any(|&(from, _)| from != header) {
+            return None;
+        }
+        let ValueKind::Branch(br) = data.dfg().value(terminator(data, header)).kind() else {
+            return None;
+        };
+        if !lp.contains(br.true_bb()) || lp.contains(br.false_bb()) {
+            return None;
+        }
+        let ValueKind::Binary(cmp) = data.dfg().value(br.cond()).kind() else {
+            return None;
+        };
+
+        let params = data.dfg().bb(header).params();
+        let in_loop = |value: Value| {
+            params.contains(&value) || data.layout().parent_bb(value).is_some_and(|bb| lp.contains(bb))
+        };
+        let (op, i, bound) = match cmp.op() {
+            op @ (BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge) if params.contains(&cmp.lhs()) => (op, cmp.lhs(), cmp.rhs()),
+            BinaryOp::Lt if params.contains(&cmp.rhs()) => (BinaryOp::Gt, cmp.rhs(), cmp.lhs()),
+            BinaryOp::Le if params.contains(&cmp.rhs()) => (BinaryOp::Ge, cmp.rhs(), cmp.lhs()),
+            BinaryOp::Gt if params.contains(&cmp.rhs()) => (BinaryOp::Lt, cmp.rhs(), cmp.lhs()),
+            BinaryOp::Ge if params.contains(&cmp.rhs()) => (BinaryOp::Le, cmp.rhs(), cmp.lhs()),
+            _ => return None,
+        };
+        if !bound.is_global() && in_loop(bound) {
+            return None;
+        }
+        let iv = params.iter().position(|&param| param == i).unwrap();
+        let latch_args = edge_args(data.dfg().value(terminator(data, latch)).kind(), header);
+        let &[latch_args] = latch_args.as_slice() else {
+            return None;
+        };
+        let ValueKind::Binary(next) = data.dfg().value(latch_args[iv]).kind() else {
+            return None;
+        };
+        let step = match (next.op(), next.lhs() == i, next.rhs() == i) {
+            (BinaryOp::Add, true, false) => integer(data, next.rhs())?,
+            (BinaryOp::Add, false, true) => integer(data, next.lhs())?,
+            (BinaryOp::Sub, true, false) => integer(data, next.rhs())?.wrapping_neg(),
+            _ => return None,
+        };
+        // Counting toward the bound, so `i + (factor - 1) * step` also only moves toward it
+        let counts_up = matches!(op, BinaryOp::Lt | BinaryOp::Le);
+        if step == 0 || (step > 0) != counts_up {
+            return None;
+        }
+        Some(Self{ header, preheader, latch, iv, step, op, bound })
+    }
+
+    // Iterations run, if `i` starts at and is compared to constants
+    fn trip_count(&self, data: &FunctionData) -> Option<usize> {
+        let init = edge_args(data.dfg().value(terminator(data, self.preheader)).kind(), self.header)[0][self.iv];
+        if self.bound.is_global() {
+            return None;
+        }
+        let (mut i, bound) = (integer(data, init)?, integer(data, self.bound)?);
+        let mut trips = 0;
+        while compare(self.op, i, bound) {
+            trips += 1;
+            if trips > MAX_FULL_TRIPS {
+                return None;
+            }
+            i = i.checked_add(self.step)?;
+        }
+        Some(trips)
+    }
+}
+
+fn integer(data: &FunctionData, value: Value) -> Option<i32> {
+    match data.dfg().value(value).kind() {
+        ValueKind::Integer(int) => Some(int.value()),
+        _ => None,
+    }
+}
+
+fn compare(op: BinaryOp, a: i32, b: i32) -> bool {
+    match op {
+        BinaryOp::Lt => a < b,
+        BinaryOp::Le => a <= b,
+        BinaryOp::Gt => a > b,
+        BinaryOp::Ge => a >= b,
+        _ => unreachable!(),
+    }
+}
+
+// Makes `count` copies of the loop, placed before it: the preheader jumps to the
+// first copy, and each copy's latch to the next copy or, for the last one, to the
+// original header. Returns the headers and latches of the copies.
+fn chain_copies(data: &mut FunctionData, lp: &Loop, counted: &CountedLoop, count: usize) -> (Vec<BasicBlock>, Vec<BasicBlock>) {
+    let mut headers = Vec::new();
+    let mut latches = Vec::new();
+    for _ in 0..count {
+        let (bbs, _) = clone_blocks(data, &lp.blocks, lp.header);
+        headers.push(bbs[&lp.header]);
+        latches.push(bbs[&counted.latch]);
+    }
+    for (index, &latch) in latches.iter().enumerate() {
+        let next = headers.get(index + 1).copied().unwrap_or(lp.header);
+        jump_elsewhere(data, latch, headers[index], next);
+    }
+    jump_elsewhere(data, counted.preheader, lp.header, headers[0]);
+    return (headers, latches);
+}
+
+// Makes the edge from `bb` to `old` go to `new` instead, with the same arguments
+fn jump_elsewhere(data: &mut FunctionData, bb: BasicBlock, old: BasicBlock, new: BasicBlock) {
+    let term = terminator(data, bb);
+    let args = edge_args(data.dfg().value(term).kind(), old)[0].to_vec();
+    retarget(data.dfg_mut(), term, old, new, &args);
+}
+
+// A copied header always goes on into its body
+fn drop_exit_test(data: &mut FunctionData, header: BasicBlock) {
+    let term = terminator(data, header);
+    let ValueKind::Branch(br) = data.dfg().value(term).kind() else {
+        unreachable!();
+    };
+    let (body, args) = (br.true_bb(), br.true_args().to_vec());
+    data.dfg_mut().replace_value_with(term).jump_with_args(body, args);
+}
+
+fn unroll_fully(data: &mut FunctionData, lp: &Loop, counted: &CountedLoop, trips: usize) {
+    let (headers, _) = chain_copies(data, lp, counted, trips);
+    for header in headers {
+        drop_exit_test(data, header);
+    }
+}
+
+fn unroll_by(data: &mut FunctionData, lp: &Loop, counted: &CountedLoop, factor: usize) {
+    let (headers, latches) = chain_copies(data, lp, counted, factor);
+    for &header in &headers[1..] {
+        drop_exit_test(data, header);
+    }
+    let first = headers[0];
+    jump_elsewhere(data, *latches.last().unwrap(), lp.header, first);
+
+    // The first copy goes on only if `factor` iterations remain, and otherwise
+    // leaves for the original loop
+    let params = data.dfg().bb(first).params().to_vec();
+    let offset = data.dfg_mut().new_value().integer(counted.step.wrapping_mul(factor as i32 - 1));
+    let last = data.dfg_mut().new_value().binary(BinaryOp::Add, params[counted.iv], offset);
+    let cond = data.dfg_mut().new_value().binary(counted.op, last, counted.bound);
+    insert_before_terminator(data, first, last);
+    insert_before_terminator(data, first, cond);
+    let term = terminator(data, first);
+    let ValueKind::Branch(br) = data.dfg().value(term).kind() else {
+        unreachable!();
+    };
+    let (body, body_args) = (br.true_bb(), br.true_args().to_vec());
+    data.dfg_mut().replace_value_with(term).branch_with_args(cond, body, lp.header, body_args, params);
+}
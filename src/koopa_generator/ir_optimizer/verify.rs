@@ -0,0 +1,185 @@
+use koopa::ir::{BasicBlock, Function, FunctionData, Value, ValueKind};
+use std::collections::HashMap;
+use super::analysis::FunctionAnalyses;
+use super::ir_edit::edge_args;
+use super::pass_manager::FunctionPass;
+
+// Sanity check for pass authors: every block ends with its only terminator,
+// every operand is still alive and defined where it dominates its use, and
+// jumps pass their targets the parameters they take. Panics, a broken IR is
+// a compiler bug.
+pub struct Verify;
+
+// Where a local value is defined: its block, and its position there
+// (block parameters come before every instruction)
+fn definitions(data: &FunctionData) -> HashMap<Value, (BasicBlock, Option<usize>)> {
+    let mut defs = HashMap::new();
+    for (&bb, node) in data.layout().bbs() {
+        defs.extend(data.dfg().bb(bb).params().iter().map(|&param| (param, (bb, None))));
+        defs.extend(node.insts().keys().enumerate().map(|(index, &inst)| (inst, (bb, Some(index)))));
+    }
+    return defs;
+}
+
+fn is_terminator(kind: &ValueKind) -> bool {
+    matches!(kind, ValueKind::Jump(_) | ValueKind::Branch(_) | ValueKind::Return(_))
+}
+
+impl FunctionPass for Verify {
+    fn name(&self) -> &'static str {
+        "verify"
+    }
+
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData, analyses: &mut FunctionAnalyses) {
+        let func_name = data.name().to_owned();
+        let (cfg, dom) = (analyses.cfg(data), analyses.dom_tree(data));
+        let defs = definitions(data);
+        for (&bb, node) in data.layout().bbs() {
+            let insts = node.insts().keys().copied().collect::<Vec<_>>();
+            let last = insts.last().copied();
+            assert!(last.is_some_and(|inst| is_terminator(data.dfg().value(inst).kind())),
+                "{}: basic block {:?} does not end with a terminator", func_name, bb);
+            for inst in insts {
+                let value = data.dfg().value(inst);
+                assert!(Some(inst) == last || !is_terminator(value.kind()),
+                    "{}: terminator in the middle of basic block {:?}", func_name, bb);
+                for operand in value.kind().value_uses() {
+                    if operand.is_global() {
+                        continue;
+                    }
+                    let operand_data = data.dfg().values().get(&operand);
+                    assert!(operand_data.is_some(), "{}: {:?} uses the removed value {:?}", func_name, inst, operand);
+                    assert!(operand_data.unwrap().used_by().contains(&inst),
+                        "{}: {:?} is missing from the users of {:?}", func_name, inst, operand);
+                    let operand_kind = operand_data.unwrap().kind();
+                    if matches!(operand_kind, ValueKind::Integer(_) | ValueKind::ZeroInit(_) | ValueKind::Undef(_)
+                        | ValueKind::Aggregate(_) | ValueKind::FuncArgRef(_)) {
+                        continue;
+                    }
+                    let Some(&(def_bb, def_index)) = defs.get(&operand) else {
+                        panic!("{}: {:?} uses {:?}, which is not in the layout", func_name, inst, operand);
+                    };
+                    // Dominance means nothing in a block control never reaches
+                    let dominated = match def_bb == bb {
+                        true => def_index < defs[&inst].1,
+                        false => dom.dominates(def_bb, bb),
+                    };
+                    assert!(dominated || !cfg.is_reachable(bb),
+                        "{}: {:?} uses {:?} before its definition", func_name, inst, operand);
+                }
+                for target in value.kind().bb_uses() {
+                    assert!(data.layout().bbs().contains_key(&target),
+                        "{}: {:?} jumps to {:?}, which is not in the layout", func_name, inst, target);
+                    let params = data.dfg().bb(target).params();
+                    for args in edge_args(value.kind(), target) {
+                        let tys_match = args.iter().zip(params).all(|(&arg, &param)| data.dfg().value(arg).ty() == data.dfg().value(param).ty());
+                        assert!(args.len() == params.len() && tys_match,
+                            "{}: {:?} does not pass {:?} the parameters it takes", func_name, inst, target);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use koopa::ir::builder_traits::*;
+    use koopa::ir::{BinaryOp, Program, Type};
+
+    // Verifies `@f(): i32` after `build` filled its blocks, the first being the entry
+    fn verify(build: impl FnOnce(&mut FunctionData, &[BasicBlock])) {
+        let mut program = Program::new();
+        let func = program.new_func(FunctionData::new("@f".into(), Vec::new(), Type::get_i32()));
+        let data = program.func_mut(func);
+        let bbs = [("%entry", 0), ("%left", 0), ("%right", 0), ("%end", 1)].map(|(name, params)| {
+            let bb = data.dfg_mut().new_bb().basic_block_with_params(Some(name.into()), vec![Type::get_i32(); params]);
+            data.layout_mut().bbs_mut().push_key_back(bb).unwrap();
+            bb
+        });
+        build(data, &bbs);
+        Verify.run_on(func, data, &mut FunctionAnalyses::default());
+    }
+
+    fn push(data: &mut FunctionData, bb: BasicBlock, inst: Value) {
+        data.layout_mut().bb_mut(bb).insts_mut().push_key_back(inst).unwrap();
+    }
+
+    // entry: x = 1 + 1; br x, left, right
+    // left: y = x + x; jump end(y)   right: jump end(x)
+    // end(p): ret p
+    // Only `ret` is placed, the rest is left to the test
+    fn diamond(data: &mut FunctionData, bbs: &[BasicBlock]) -> [Value; 5] {
+        let one = data.dfg_mut().new_value().integer(1);
+        let x = data.dfg_mut().new_value().binary(BinaryOp::Add, one, one);
+        let br = data.dfg_mut().new_value().branch(x, bbs[1], bbs[2]);
+        let y = data.dfg_mut().new_value().binary(BinaryOp::Add, x, x);
+        let left_jump = data.dfg_mut().new_value().jump_with_args(bbs[3], vec![y]);
+        let right_jump = data.dfg_mut().new_value().jump_with_args(bbs[3], vec![x]);
+        let param = data.dfg().bb(bbs[3]).params()[0];
+        let ret = data.dfg_mut().new_value().ret(Some(param));
+        push(data, bbs[3], ret);
+        return [x, br, y, left_jump, right_jump];
+    }
+
+    #[test]
+    fn accepts_well_formed_function() {
+        verify(|data, bbs| {
+            let [x, br, y, left_jump, right_jump] = diamond(data, bbs);
+            push(data, bbs[0], x);
+            push(data, bbs[0], br);
+            push(data, bbs[1], y);
+            push(data, bbs[1], left_jump);
+            push(data, bbs[2], right_jump);
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "before its definition")]
+    fn rejects_use_before_def_in_block() {
+        verify(|data, bbs| {
+            let [x, _, y, left_jump, right_jump] = diamond(data, bbs);
+            let jump = data.dfg_mut().new_value().jump(bbs[1]);
+            push(data, bbs[0], jump);
+            push(data, bbs[1], y);
+            push(data, bbs[1], x);
+            push(data, bbs[1], left_jump);
+            push(data, bbs[2], right_jump);
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "before its definition")]
+    fn rejects_use_not_dominated() {
+        verify(|data, bbs| {
+            let [x, br, y, left_jump, _] = diamond(data, bbs);
+            push(data, bbs[0], x);
+            push(data, bbs[0], br);
+            push(data, bbs[1], y);
+            push(data, bbs[1], left_jump);
+            // `y` is only defined on the left
+            let right_jump = data.dfg_mut().new_value().jump_with_args(bbs[3], vec![y]);
+            push(data, bbs[2], right_jump);
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "the parameters it takes")]
+    fn rejects_missing_block_argument() {
+        verify(|data, bbs| {
+            let [x, br, y, left_jump, right_jump] = diamond(data, bbs);
+            push(data, bbs[0], x);
+            push(data, bbs[0], br);
+            push(data, bbs[1], y);
+            push(data, bbs[1], left_jump);
+            // The builders check the arguments, a pass editing values raw does not
+            let mut jump = data.dfg().value(right_jump).clone();
+            if let ValueKind::Jump(jump) = jump.kind_mut() {
+                jump.args_mut().clear();
+            }
+            data.dfg_mut().replace_value_with(right_jump).raw(jump);
+            push(data, bbs[2], right_jump);
+        });
+    }
+}
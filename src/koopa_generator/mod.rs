@@ -1,7 +1,7 @@
+pub mod ir_optimizer;
 mod namespace;
 mod generator;
 mod function_interface;
-mod const_evaluator;
 
 use generator::GenerateKoopa;
 use crate::ast::ast_def::*;
@@ -9,6 +9,7 @@ use namespace::Namesp;
 use koopa::ir::{Program, Type};
 use std::fmt;
 
+// Expects an AST that passed semantic::check, which also annotated it
 pub fn generate_program(comp_unit: &CompileInit) -> CResult<Program> {
     let mut program = Program::new();
     let mut namesp = Namesp::new();
@@ -18,15 +19,72 @@ pub fn generate_program(comp_unit: &CompileInit) -> CResult<Program> {
 
 #[derive(Debug)]
 pub enum CompileError{
-    InvalidReturn(String),
-    InvalidIdentifier(String),
-    InvalidType(String),
-    InvalidInit(String),
-    InvalidFunccall(String),
-    DuplicateIdentifier(String),
-    VarNotDeclared(String),
-    FuncNotDeclared(String),
-    InvalidArrayDeref(String),
+    InvalidReturn(String, Span),
+    InvalidIdentifier(String, Span),
+    InvalidType(String, Span),
+    InvalidInit(String, Span),
+    InvalidFunccall(String, Span),
+    DuplicateIdentifier(String, Span),
+    VarNotDeclared(String, Span),
+    FuncNotDeclared(String, Span),
+    InvalidArrayDeref(String, Span),
+    NotInLoop(String, Span),
+}
+
+impl CompileError {
+    pub fn span(&self) -> Span {
+        match self {
+            Self::InvalidReturn(_, span)
+            | Self::InvalidIdentifier(_, span)
+            | Self::InvalidType(_, span)
+            | Self::InvalidInit(_, span)
+            | Self::InvalidFunccall(_, span)
+            | Self::DuplicateIdentifier(_, span)
+            | Self::VarNotDeclared(_, span)
+            | Self::FuncNotDeclared(_, span)
+            | Self::InvalidArrayDeref(_, span)
+            | Self::NotInLoop(_, span) => *span,
+        }
+    }
+
+    // Errors raised deep inside helpers (init lists, dims) know no location:
+    // the enclosing statement or definition fills in its own span
+    pub fn or_span(mut self, outer: Span) -> Self {
+        match &mut self {
+            Self::InvalidReturn(_, span)
+            | Self::InvalidIdentifier(_, span)
+            | Self::InvalidType(_, span)
+            | Self::InvalidInit(_, span)
+            | Self::InvalidFunccall(_, span)
+            | Self::DuplicateIdentifier(_, span)
+            | Self::VarNotDeclared(_, span)
+            | Self::FuncNotDeclared(_, span)
+            | Self::InvalidArrayDeref(_, span)
+            | Self::NotInLoop(_, span) => {
+                if span.is_unknown() {
+                    *span = outer;
+                }
+            }
+        }
+        self
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidReturn(msg, _) => write!(f, "invalid return: {}", msg),
+            Self::InvalidIdentifier(msg, _) => write!(f, "invalid use of identifier: {}", msg),
+            Self::InvalidType(msg, _) => write!(f, "type mismatch: {}", msg),
+            Self::InvalidInit(msg, _) => write!(f, "invalid initializer: {}", msg),
+            Self::InvalidFunccall(msg, _) => write!(f, "invalid function call: {}", msg),
+            Self::DuplicateIdentifier(id, _) => write!(f, "redefinition of `{}`", id),
+            Self::VarNotDeclared(id, _) => write!(f, "use of undeclared variable `{}`", id),
+            Self::FuncNotDeclared(id, _) => write!(f, "call to undeclared function `{}`", id),
+            Self::InvalidArrayDeref(msg, _) => write!(f, "invalid array subscript: {}", msg),
+            Self::NotInLoop(stmt, _) => write!(f, "`{}` statement not within a loop", stmt),
+        }
+    }
 }
 // CResult
 pub type CResult<T> = std::result::Result<T, CompileError>;
\ No newline at end of file
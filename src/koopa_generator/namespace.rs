@@ -53,71 +53,72 @@ impl Namesp{
     }
 
     // Create New: Value or Function
-    pub fn new_value(&mut self, var_id:&str, var_value: NamespValue, if_const: bool) -> CResult<()> {
+    pub fn new_value(&mut self, var_id:&str, var_value: NamespValue, if_const: bool, span: Span) -> CResult<()> {
         let global_var: bool = self.value_maps.len() == 1;
         let cur_layer: &mut HashMap<String, NamespValue> = self.value_maps.last_mut().unwrap();
         if cur_layer.contains_key(var_id) {
-            return Err(CompileError::DuplicateIdentifier(var_id.to_owned()));
+            return Err(CompileError::DuplicateIdentifier(var_id.to_owned(), span));
         }
         if global_var && self.funcs.contains_key(var_id) {
-            return Err(CompileError::DuplicateIdentifier(var_id.to_owned()));
+            return Err(CompileError::DuplicateIdentifier(var_id.to_owned(), span));
         }
         cur_layer.insert(var_id.to_string(), var_value);
         self.is_const.last_mut().unwrap().insert(var_id.to_string(), if_const);
         return Ok(());
     }
 
-    pub fn new_func(&mut self, func_id: &str, func_def: Function) -> CResult<()> {
+    pub fn new_func(&mut self, func_id: &str, func_def: Function, span: Span) -> CResult<()> {
         if self.funcs.contains_key(func_id) || self.value_maps[0].contains_key(func_id) {
-            return Err(CompileError::DuplicateIdentifier(func_id.to_owned()));
+            return Err(CompileError::DuplicateIdentifier(func_id.to_owned(), span));
         }
         self.funcs.insert(func_id.to_string(), func_def);
         //self.cur_function = Some(function_interface);
         return Ok(());
     }
     // Get Value: Value or Function
-    pub fn get_value(&self, var_id: &str) -> CResult<&NamespValue> {
+    pub fn get_value(&self, var_id: &str, span: Span) -> CResult<&NamespValue> {
         for layer in self.value_maps.iter().rev() {
             if let Some(value) = layer.get(var_id) {
                 return Ok(value);
             }
         }
-        return Err(CompileError::VarNotDeclared(var_id.to_owned()));
+        return Err(CompileError::VarNotDeclared(var_id.to_owned(), span));
     }
 
-    pub fn get_func(&self, func_id: &str) -> CResult<&Function> {
+    pub fn get_func(&self, func_id: &str, span: Span) -> CResult<&Function> {
         if let Some(func) = self.funcs.get(func_id) {
             return Ok(func);
         }
-        return Err(CompileError::FuncNotDeclared(func_id.to_owned()));
+        return Err(CompileError::FuncNotDeclared(func_id.to_owned(), span));
     }
 
+    // Only global initializers run without a current function, so they must be constant
     pub fn get_cur_func_interf(&self) -> CResult<&FunctionInterface> {
         if let Some(func) = &self.cur_function {
             return Ok(func);
         }
-        return Err(CompileError::FuncNotDeclared("".to_owned())); // TODO: Error Message
+        return Err(CompileError::InvalidInit("expression outside of a function must be constant".to_owned(), Span::default()));
     }
 
     pub fn get_cur_func_interf_mut(&mut self) -> CResult<&mut FunctionInterface> {
         if let Some(func) = &mut self.cur_function {
             return Ok(func);
         }
-        return Err(CompileError::FuncNotDeclared("".to_owned())); // TODO: Error Message
+        return Err(CompileError::InvalidInit("expression outside of a function must be constant".to_owned(), Span::default()));
     }
 
-    pub fn get_continue_to(&self) -> CResult<BasicBlock> {
+    pub fn get_continue_to(&self, span: Span) -> CResult<BasicBlock> {
         if let Some((bb, _)) = self.continue_break_stack.last() {
             return Ok(*bb);
         }
-        return Err(CompileError::InvalidType("".to_owned())); // TODO: Error Message
+        return Err(CompileError::NotInLoop("continue".to_owned(), span));
     }
 
-    pub fn get_break_to(&self) -> CResult<BasicBlock> {
+    pub fn get_break_to(&self, span: Span) -> CResult<BasicBlock> {
         if let Some((_, bb)) = self.continue_break_stack.last() {
             return Ok(*bb);
         }
-        return Err(CompileError::InvalidType("".to_owned())); // TODO: Error Message
+        return Err(CompileError::NotInLoop("break".to_owned(), span));
     }
 
     pub fn set_loop_continue_break(&mut self, continue_to: BasicBlock, break_to: BasicBlock) {
@@ -179,7 +180,7 @@ impl InitValue {
             Self::List(init_vals) => {
                 for init_val in init_vals {
                     if init_num >= init_needed {
-                        return Err(CompileError::InvalidInit("".to_owned()));
+                        return Err(CompileError::InvalidInit("too many elements in initializer list".to_owned(), Span::default()));
                     }
                     match init_val {
                         Self::Const(value) => {
@@ -209,7 +210,7 @@ impl InitValue {
                                 }
                             }
                             if !flag {
-                                return Err(CompileError::InvalidInit("".to_owned()));
+                                return Err(CompileError::InvalidInit("nested initializer list is not aligned to a sub-array boundary".to_owned(), Span::default()));
                             }
                         }
                     }
@@ -227,6 +228,11 @@ impl InitValue {
     }
 
     pub fn init_rebuild(&self, ty: &Type) -> CResult<InitValue> {
+        match (self, ty.is_i32()) {
+            (Self::List(_), true) => return Err(CompileError::InvalidInit("braced initializer for a scalar".to_owned(), Span::default())),
+            (Self::Const(_) | Self::Var(_), false) => return Err(CompileError::InvalidInit("array must be initialized with a braced list".to_owned(), Span::default())),
+            _ => {},
+        }
         let result = match self {
             Self::Const(value) => Ok(Self::Const(*value)),
             Self::Var(value) => Ok(Self::Var(*value)),
@@ -258,7 +264,7 @@ impl InitValue {
                 if namespace.is_global()
                 {program.new_value().integer(value)} 
                 else {namespace.get_cur_func_interf_mut()?.value_builder(program).integer(value)}),
-            Self::Var(_) => Err(CompileError::InvalidType("".to_owned())),
+            Self::Var(_) => Err(CompileError::InvalidInit("initializer is not a compile-time constant".to_owned(), Span::default())),
 
             Self::List(init_vals) => {
                 let values = init_vals.into_iter().map(|init_val| init_val.into_const(program, namespace)).collect::<CResult<Vec<Value>>>()?;
@@ -302,7 +308,7 @@ impl InitValue {
 impl ExprValue{
     pub fn into_value_or_ptr(self, program: &mut Program, namespace: &mut Namesp) -> CResult<Value>{
         match self {
-            Self::Void => Err(CompileError::InvalidType("".to_owned())),
+            Self::Void => Err(CompileError::InvalidType("void value used as an argument".to_owned(), Span::default())),
             Self::VarInt(value) => Ok(value),
             Self::VarPtr(ptr) => {
                 let mut func_interface = namespace.get_cur_func_interf()?;
@@ -323,14 +329,15 @@ impl ExprValue{
                 func_interface.push_inst_to_bb(program, func_interface.current_bb(), load_inst);
                 Ok(load_inst)
             },
-            _ => Err(CompileError::InvalidType("".to_owned())),
+            Self::Void => Err(CompileError::InvalidType("void value used in an expression".to_owned(), Span::default())),
+            Self::ArrPtr(_) => Err(CompileError::InvalidType("array used where an int is expected".to_owned(), Span::default())),
         }
     }
 
     pub fn into_lvptr(self)->CResult<Value>{
         match self{
             Self::VarPtr(value) => Ok(value),
-            _ => Err(CompileError::InvalidType("".to_owned())),
+            _ => Err(CompileError::InvalidType("left-hand side of assignment is not an int variable".to_owned(), Span::default())),
         }
     }
 
@@ -0,0 +1,90 @@
+/*
+    SysY compiler pipeline:
+        source --parse--> annotated AST --generate_program--> Koopa IR
+               --optimize--> Koopa IR --generate_asm--> RISC-V assembly
+*/
+pub mod ast;
+pub mod diagnostics;
+pub mod koopa_generator;
+mod risc_v_generator;
+pub mod semantic;
+
+use std::io::{self, Write};
+
+use ast::ast_def::CompileInit;
+use diagnostics::{Diagnostic, WarningConfig};
+pub use koopa::ir::Program;
+pub use koopa_generator::CompileError;
+pub use koopa_generator::ir_optimizer::{is_known_pass, OptLevel, OptOptions};
+pub use risc_v_generator::RegAlloc;
+
+// Parse and check `source` with the default warning switches, warnings are dropped
+pub fn parse(source: &str) -> Result<CompileInit, Vec<Diagnostic>> {
+    parse_with_warnings(source, &WarningConfig::new()).map(|(comp_init, _)| comp_init)
+}
+
+// Syntax errors are recovered, so the partial AST is still checked: Err holds every
+// diagnostic found, Ok the annotated AST ready for generate_program and its warnings
+pub fn parse_with_warnings(source: &str, warnings: &WarningConfig) -> Result<(CompileInit, Vec<Diagnostic>), Vec<Diagnostic>> {
+    let mut parse_errors = Vec::new();
+    let parsed = ast::grammar::CompileInitParser::new().parse(&mut parse_errors, source);
+    let mut diags = parse_errors.iter().map(|(recovery, _)| Diagnostic::from_parse_error(&recovery.error)).collect::<Vec<_>>();
+    let mut comp_init = match parsed {
+        Ok(comp_init) => comp_init,
+        Err(err) => {
+            diags.push(Diagnostic::from_parse_error(&err));
+            return Err(diags);
+        },
+    };
+
+    // The source a recovery skipped may have declared anything: a name it mentions
+    // is not reported undeclared, and warnings would only guess
+    let recovered = parse_errors.iter().map(|&(_, span)| &source[span.start..span.end]).collect::<Vec<_>>();
+    let report = semantic::check(&mut comp_init);
+    diags.extend(report.errors.iter().filter(|err| match err {
+        CompileError::VarNotDeclared(name, _) | CompileError::FuncNotDeclared(name, _) => !recovered.iter().any(|text| mentions(text, name)),
+        _ => true,
+    }).map(Diagnostic::from_compile_error));
+    if recovered.is_empty() {
+        diags.extend(report.warnings.iter().filter_map(|warning| {
+            warnings.severity(warning).map(|severity| Diagnostic::from_compile_warning(warning, severity))
+        }));
+    }
+    if diags.iter().any(Diagnostic::is_error) {
+        return Err(diags);
+    }
+    return Ok((comp_init, diags));
+}
+
+// Whether `name` is one of the words of `text`
+fn mentions(text: &str, name: &str) -> bool {
+    text.split(|c: char| !c.is_ascii_alphanumeric() && c != '_').any(|word| word == name)
+}
+
+// Expects an AST returned by parse
+pub fn generate_program(comp_init: &CompileInit) -> Result<Program, CompileError> {
+    koopa_generator::generate_program(comp_init)
+}
+
+pub fn optimize(program: &mut Program, level: OptLevel) {
+    koopa_generator::ir_optimizer::optimize(program, level);
+}
+
+// Pipeline of `options.level` adjusted by the per-pass switches
+pub fn optimize_with(program: &mut Program, options: &OptOptions) {
+    koopa_generator::ir_optimizer::optimize_with(program, options);
+}
+
+// Koopa IR in text form
+pub fn generate_koopa(program: &Program, out: impl Write) -> io::Result<()> {
+    koopa::back::KoopaGenerator::new(out).generate_on(program)
+}
+
+pub fn generate_asm(program: &Program, mut out: impl Write) -> io::Result<()> {
+    generate_asm_with(program, RegAlloc::LinearScan, &mut out)
+}
+
+pub fn generate_asm_with(program: &Program, regalloc: RegAlloc, mut out: impl Write) -> io::Result<()> {
+    risc_v_generator::generate_asm(program, regalloc, &mut out)?;
+    out.flush()
+}
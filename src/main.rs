@@ -1,45 +1,89 @@
 use std::env;
-use std::fs::read_to_string;
-use std::io::Result;
-use std::{fmt, io};
-
-mod ast;
-mod koopa_generator;
-mod risc_v_generator;
-use koopa::back::KoopaGenerator;
-
-fn main() -> Result<()> {
-    // Arguments Praser: mode input -o output
-
-    let mut args = env::args();
-    args.next();
-    let mode = args.next().unwrap();
-    println!("{}", mode);
-    let input = args.next().unwrap();
-    println!("{}", input);
-
-    assert_eq!(args.next(), Some("-o".to_owned()));
-    let output = args.next().unwrap(); 
-    println!("{}", output);
-    let input = read_to_string(input)?;
-    let comp_init = ast::grammar::CompileInitParser::new().parse(&input);
-    let comp_init = comp_init.unwrap();
-    // println!("{:?}", comp_init);
-    let program = koopa_generator::generate_program(&comp_init).unwrap();
-
-    let mode = match mode.as_str() {
-        "-koopa" => {
-            KoopaGenerator::from_path(output.as_str())?.generate_on(&program)?;
+use std::fs::{read_to_string, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process;
+
+mod cli;
+use cli::{Command, EmitKind, Options};
+use compiler::diagnostics::{self, Diagnostic};
+
+fn main() {
+    let options = match cli::parse_args(env::args().skip(1)) {
+        Ok(Command::Compile(options)) => options,
+        Ok(Command::Help) => {
+            print!("{}", cli::USAGE);
+            return;
+        },
+        Err(msg) => {
+            eprintln!("error: {}", msg);
+            eprintln!("Try `compiler --help` for more information.");
+            process::exit(2);
         },
-        "-riscv" => {
-            risc_v_generator::generate_asm(&program, output.as_str())?;
+    };
+    if let Err(err) = compile(&options) {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+// Diagnostics are printed here, a failed compilation exits with status 1
+fn compile(options: &Options) -> io::Result<()> {
+    let (file_name, input) = match &options.input {
+        Some(path) => (path.clone(), read_to_string(path).map_err(|err| io::Error::new(err.kind(), format!("cannot read `{}`: {}", path, err)))?),
+        None => {
+            let mut input = String::new();
+            io::stdin().read_to_string(&mut input)?;
+            ("<stdin>".to_owned(), input)
+        },
+    };
+
+    let comp_init = match compiler::parse_with_warnings(&input, &options.warnings) {
+        Ok((comp_init, warnings)) => {
+            diagnostics::emit(&warnings, &file_name, &input);
+            comp_init
         },
-        "-perf" => {
-            risc_v_generator::generate_asm(&program, output.as_str())?;
+        Err(diags) => {
+            diagnostics::emit(&diags, &file_name, &input);
+            process::exit(1);
         },
-        _ => unreachable!("Invalid Args"),
     };
-    
+
+    if options.emit.contains(&EmitKind::Ast) {
+        writeln!(open_output(options, EmitKind::Ast)?, "{:#?}", comp_init)?;
+    }
+    if !options.emit.iter().any(|kind| matches!(kind, EmitKind::Koopa | EmitKind::Asm)) {
+        return Ok(());
+    }
+
+    let mut program = match compiler::generate_program(&comp_init) {
+        Ok(program) => program,
+        Err(err) => {
+            diagnostics::emit(&[Diagnostic::from_compile_error(&err)], &file_name, &input);
+            process::exit(1);
+        },
+    };
+    compiler::optimize_with(&mut program, &options.opt);
+
+    if options.emit.contains(&EmitKind::Koopa) {
+        compiler::generate_koopa(&program, open_output(options, EmitKind::Koopa)?)?;
+    }
+    if options.emit.contains(&EmitKind::Asm) {
+        compiler::generate_asm_with(&program, options.regalloc, open_output(options, EmitKind::Asm)?)?;
+    }
     return Ok(());
 }
 
+// -o names the file itself for a single emit kind, and the stem for several
+fn open_output(options: &Options, kind: EmitKind) -> io::Result<Box<dyn Write>> {
+    let Some(path) = &options.output else {
+        return Ok(Box::new(io::stdout()));
+    };
+    let path = if options.emit.len() > 1 {
+        Path::new(path).with_extension(kind.extension())
+    } else {
+        Path::new(path).to_path_buf()
+    };
+    let file = File::create(&path).map_err(|err| io::Error::new(err.kind(), format!("cannot write `{}`: {}", path.display(), err)))?;
+    return Ok(Box::new(io::BufWriter::new(file)));
+}
@@ -1,141 +1,107 @@
-use std::fs::File;
-use std::io::{Write, Result};
-use super::program_manager::*;
-
-pub struct Writer<'file> {
-    pub f: &'file mut File,
-    pub reg_temp: &'static str,
-    
+use std::io::Write;
+use super::machine::*;
+
+// Assembly text sink: a file, stdout or an in-memory buffer. Instruction
+// selection builds the machine IR of the current function through it.
+pub struct Writer<'file, W: Write> {
+    pub f: &'file mut W,
+    pub reg_temp: Reg,
+    func: Option<MachineFunction>,
 }
 
-impl<'file> Writer<'file> {
-    pub fn new(f: &'file mut File) -> Self {
+impl<'file, W: Write> Writer<'file, W> {
+    pub fn new(f: &'file mut W) -> Self {
         Self {
             f,
-            reg_temp: "t0",
+            reg_temp: T0,
+            func: None,
         }
     }
 
-    pub fn update_temp_reg(&mut self, reg: &'static str) {
+    pub fn update_temp_reg(&mut self, reg: Reg) {
         self.reg_temp = reg;
     }
 
-    pub fn func_entry(&mut self, func_name: &str, func_interface: &FunctionInterface) -> Result<()> {
-        writeln!(self.f, "  .globl {}", &func_name[1..])?;
-        writeln!(self.f, "{}:", &func_name[1..])?;
+    pub fn begin_function(&mut self, name: &str) {
+        self.func = Some(MachineFunction::new(name));
+    }
 
-        let offset = func_interface.sp_offset();
-        let offset = offset as i32;
-        if offset != 0 {
-            self.addi("sp", "sp", -offset)?;
+    pub fn end_function(&mut self) -> MachineFunction {
+        self.func.take().unwrap()
+    }
 
-            if !func_interface.neednt_restore_ra(){
-                self.sw("ra", "sp", offset - 4)?;
-            }
-        }   
-        return Ok(());
+    pub fn frame_mut(&mut self) -> &mut Frame {
+        &mut self.func.as_mut().unwrap().frame
     }
 
-    pub fn func_end(&mut self, func_interface: &FunctionInterface) -> Result<()> {
-        let offset = func_interface.sp_offset();
-        let offset = offset as i32;
-        if offset != 0 {
-            if !func_interface.neednt_restore_ra(){
-                self.lw("ra", "sp", offset - 4)?;
-            }
-            self.addi("sp", "sp", offset)?;
-        }
-        self.ret()?;
-        return Ok(());
+    // Following instructions go to a new block
+    pub fn start_block(&mut self, label: &str) {
+        let func = self.func.as_mut().unwrap();
+        func.blocks.push(MachineBlock{ label: label.to_string(), insts: Vec::new() });
+    }
+
+    fn push(&mut self, inst: MachineInst) {
+        let func = self.func.as_mut().unwrap();
+        func.blocks.last_mut().unwrap().insts.push(inst);
     }
 
-    pub fn beqz(&mut self, cond: &str, label: &str) -> Result<()>  {
-        writeln!(self.f, "  beqz {}, {}", cond, label)?;
-        // self.f.write_fmt(format_args!("beqz {}, {}\n", cond, label))?;
-        return Ok(());
+    pub fn beqz(&mut self, cond: Reg, label: &str) {
+        self.push(MachineInst::Branch{ op: "beqz", rs: cond, label: label.to_string() });
     }
 
-    pub fn bnez(&mut self, cond: &str, label: &str) -> Result<()>  {
-        writeln!(self.f, "  bnez {}, {}", cond, label)?;
-        // self.f.write_fmt(format_args!("bnez {}, {}\n", cond, label))?;
-        return Ok(());
+    pub fn bnez(&mut self, cond: Reg, label: &str) {
+        self.push(MachineInst::Branch{ op: "bnez", rs: cond, label: label.to_string() });
     }
 
-    pub fn j(&mut self, label: &str) -> Result<()>  {
-        writeln!(self.f, "  j {}", label)?;
-        // self.f.write_fmt(format_args!("j {}\n", label))?;
-        return Ok(());
+    pub fn j(&mut self, label: &str) {
+        self.push(MachineInst::J{ label: label.to_string() });
     }
 
-    pub fn call(&mut self, func: &str) -> Result<()>  {
-        writeln!(self.f, "  call {}", func)?;
-        // self.f.write_fmt(format_args!("call {}\n", func))?;
-        return Ok(());
+    pub fn call(&mut self, func: &str) {
+        self.push(MachineInst::Call{ func: func.to_string() });
     }
 
-    pub fn ret(&mut self) -> Result<()>  {
-        writeln!(self.f, "  ret")?;
-        // self.f.write_fmt(format_args!("ret\n"))?;
-        return Ok(());
+    pub fn ret(&mut self) {
+        self.push(MachineInst::Ret);
     }
 
     // lw rd imm12(rs)  read from rs+imm12 to rd
-    pub fn lw(&mut self, rd: &str, rs: &str, offset: i32) -> Result<()>  {
-        if offset <= 2047 && offset >= -2048 {
-            writeln!(self.f, "  lw {}, {}({})", rd, offset, rs)?;
-        }
-        else {
-            self.addi(&self.reg_temp, rs, offset)?;
-            writeln!(self.f, "  lw {}, 0({})", rd, &self.reg_temp)?;
-        }
-        // self.f.write_fmt(format_args!("lw {}, {}({})\n", rd, offset, rs))?;
-        return Ok(());
+    // Offsets out of range are split once the frame is laid out
+    pub fn lw(&mut self, rd: Reg, base: impl Into<Base>, offset: i32) {
+        self.push(MachineInst::Lw{ rd, base: base.into(), offset });
     }
 
     // sw rs imm12(rd)  store rs into imm12(rd)
-    pub fn sw(&mut self, rs: &str, rd: &str, offset: i32) -> Result<()>  {
-        if offset <= 2047 && offset >= -2048 {
-            writeln!(self.f, "  sw {}, {}({})", rs, offset, rd)?;
-        }
-        else {
-            self.addi(&self.reg_temp, rd, offset)?;
-            writeln!(self.f, "  sw {}, 0({})", rs, &self.reg_temp)?;
-        }
-        // self.f.write_fmt(format_args!("sw {}, {}({})\n", rs, offset, rd))?;
-        return Ok(());
+    pub fn sw(&mut self, rs: Reg, base: impl Into<Base>, offset: i32) {
+        self.push(MachineInst::Sw{ rs, base: base.into(), offset });
     }
 
-    // op rd rs1 rs2: add\sub\slt\sgt\xor\or\and\sll\srl\sra\mul\div\rem
-    pub fn op2(&mut self, op: &str, rd: &str, rs1: &str, rs2: &str) -> Result<()>  {
-        writeln!(self.f, "  {} {}, {}, {}", op, rd, rs1, rs2)?;
-        // self.f.write_fmt(format_args!("{} {}, {}, {}\n", op, rd, rs1, rs2))?;
-        return Ok(());
+    // rd = address of a frame object
+    pub fn addr(&mut self, rd: Reg, base: Base) {
+        self.push(MachineInst::Addr{ rd, base });
     }
 
-    // op2i: Only support add\sub\xor\or\and
-    // op rd rs imm
-    pub fn op2i(&mut self, op: &str, rd: &str, rs1: &str, imm: i32) -> Result<()>  {
-
-        if imm <= 2047 && imm >= -2048 {
-            writeln!(self.f, "  {}i {}, {}, {}", op, rd, rs1, imm)?;
-        }
-        else {
-            self.li(&self.reg_temp, imm)?;
-            self.op2(op, rd, rs1, &self.reg_temp)?;
+    // Registers written as if all at once
+    pub fn copy(&mut self, moves: Vec<(Reg, Operand)>) {
+        if !moves.is_empty() {
+            self.push(MachineInst::Copy{ moves });
         }
-        // self.f.write_fmt(format_args!("{} {}, {}, {}\n", op, rd, rs1, imm))?;
-        return Ok(());
     }
 
-    pub fn addi(&mut self, rd: &str, rs1: &str, imm: i32) -> Result<()>  {
-        self.op2i("add", rd, rs1, imm)?;
-        // self.f.write_fmt(format_args!("addi {}, {}, {}\n", rd, rs1, imm))?;
-        return Ok(());
+    // op rd rs1 rs2: add\sub\slt\sgt\xor\or\and\sll\srl\sra\mul\div\rem
+    pub fn op2(&mut self, op: &'static str, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.push(MachineInst::Op{ op, rd, rs1, rs2 });
+    }
+
+    // op2i: Only support add\sub\xor\or\and
+    // op rd rs imm, an immediate out of range is loaded once the frame is laid out
+    pub fn op2i(&mut self, op: &'static str, rd: Reg, rs1: Reg, imm: i32) {
+        self.push(MachineInst::OpImm{ op, rd, rs1, imm });
     }
 
-    pub fn muli(&mut self, rd: &str, rs1: &str, imm: i32) -> Result<()>  {
+    pub fn muli(&mut self, rd: Reg, rs1: Reg, imm: i32) {
         if imm == 0 {
-            self.mv(rd, "x0")?;
+            self.mv(rd, ZERO);
         }
         else if imm > 0 && (imm & (imm-1)) == 0{
             let mut shift = 0;
@@ -144,44 +110,187 @@ impl<'file> Writer<'file> {
                 imm = imm>>1;
                 shift += 1;
             }
-            self.op2i("sll", rd, rs1, shift)?;
+            self.op2i("sll", rd, rs1, shift);
         }
         else {
-            self.li(&self.reg_temp, imm)?;
-            self.op2("mul", rd, rs1, &self.reg_temp)?;
+            self.li(self.reg_temp, imm);
+            self.op2("mul", rd, rs1, self.reg_temp);
         }
-        return Ok(());
+    }
+
+    // Signed division by a constant: shifts for a power of two, otherwise a
+    // multiply-high by its magic number (Hacker's Delight 10-4).
+    // `scratch` must differ from rs1 and the temp register.
+    pub fn divi(&mut self, rd: Reg, rs1: Reg, scratch: Reg, imm: i32) {
+        let abs = imm.unsigned_abs();
+        if abs == 1 {
+            if imm == 1 { self.mv(rd, rs1); } else { self.op2("sub", rd, ZERO, rs1); }
+        }
+        else if abs.is_power_of_two() {
+            let shift = abs.trailing_zeros() as i32;
+            self.pow2_bias(scratch, rs1, shift);
+            self.op2i("sra", rd, scratch, shift);
+            if imm < 0 {
+                self.op2("sub", rd, ZERO, rd);
+            }
+        }
+        else {
+            let (magic, shift) = signed_magic(imm);
+            self.li(self.reg_temp, magic);
+            self.op2("mulh", scratch, rs1, self.reg_temp);
+            if imm > 0 && magic < 0 {
+                self.op2("add", scratch, scratch, rs1);
+            }
+            else if imm < 0 && magic > 0 {
+                self.op2("sub", scratch, scratch, rs1);
+            }
+            if shift > 0 {
+                self.op2i("sra", scratch, scratch, shift);
+            }
+            // Round toward zero: add one to a negative quotient
+            self.op2i("srl", self.reg_temp, scratch, 31);
+            self.op2("add", rd, scratch, self.reg_temp);
+        }
+    }
+
+    // Signed remainder by a constant: rs1 - (rs1 / imm) * imm
+    pub fn remi(&mut self, rd: Reg, rs1: Reg, scratch: Reg, imm: i32) {
+        let abs = imm.unsigned_abs();
+        if abs.is_power_of_two() && abs != 1 {
+            // The sign follows the dividend, so only |imm| matters
+            self.pow2_bias(scratch, rs1, abs.trailing_zeros() as i32);
+            self.op2i("and", scratch, scratch, abs.wrapping_neg() as i32);
+        }
+        else {
+            self.divi(scratch, rs1, scratch, imm);
+            self.muli(scratch, scratch, imm);
+        }
+        self.op2("sub", rd, rs1, scratch);
+    }
+
+    // rd = rs1 + (rs1 < 0 ? 2^shift - 1 : 0), so an arithmetic shift rounds toward zero
+    fn pow2_bias(&mut self, rd: Reg, rs1: Reg, shift: i32) {
+        if shift > 1 {
+            self.op2i("sra", rd, rs1, 31);
+            self.op2i("srl", rd, rd, 32 - shift);
+        }
+        else {
+            self.op2i("srl", rd, rs1, 31);
+        }
+        self.op2("add", rd, rs1, rd);
     }
 
     // op1 rd rs1: seqz/snez
-    pub fn op1(&mut self, op: &str, rd: &str, rs1: &str) -> Result<()>  {
-        writeln!(self.f, "  {} {}, {}", op, rd, rs1)?;
-        // self.f.write_fmt(format_args!("{} {}, {}\n", op, rd, rs1))?;
-        return Ok(());
+    pub fn op1(&mut self, op: &'static str, rd: Reg, rs1: Reg) {
+        self.push(MachineInst::Op1{ op, rd, rs: rs1 });
     }
 
     // li rd imm
-    pub fn li(&mut self, rd: &str, imm: i32) -> Result<()>  {
-        writeln!(self.f, "  li {}, {}", rd, imm)?;
-        // self.f.write_fmt(format_args!("li {}, {}\n", rd, imm))?;
-        return Ok(());
+    pub fn li(&mut self, rd: Reg, imm: i32) {
+        self.push(MachineInst::Li{ rd, imm });
     }
 
     // la rd label
-    pub fn la(&mut self, rd: &str, label: &str) -> Result<()>  {
-        writeln!(self.f, "  la {}, {}", rd, label)?;
-        // self.f.write_fmt(format_args!("la {}, {}\n", rd, label))?;
-        return Ok(());
+    pub fn la(&mut self, rd: Reg, label: &str) {
+        self.push(MachineInst::La{ rd, label: label.to_string() });
     }
 
     // mv rd rs
-    pub fn mv(&mut self, rd: &str, rs: &str) -> Result<()>  {
-        writeln!(self.f, "  mv {}, {}", rd, rs)?;
-        // self.f.write_fmt(format_args!("mv {}, {}\n", rd, rs))?;
-        return Ok(());
+    pub fn mv(&mut self, rd: Reg, rs: Reg) {
+        self.push(MachineInst::Mv{ rd, rs });
     }
 
-    pub fn file_mut(&mut self) -> &mut File {
+    pub fn file_mut(&mut self) -> &mut W {
         self.f
     }
 }
+
+// Magic number and shift for signed division by `d`, 2 <= |d| < 2^31
+fn signed_magic(d: i32) -> (i32, i32) {
+    let two31 = 1u32 << 31;
+    let ad = d.unsigned_abs();
+    let t = two31 + ((d as u32) >> 31);
+    let anc = t - 1 - t % ad;
+    let mut p = 31;
+    let (mut q1, mut r1) = (two31 / anc, two31 - two31 / anc * anc);
+    let (mut q2, mut r2) = (two31 / ad, two31 - two31 / ad * ad);
+    loop {
+        p += 1;
+        q1 = q1.wrapping_mul(2);
+        r1 = r1.wrapping_mul(2);
+        if r1 >= anc {
+            q1 = q1.wrapping_add(1);
+            r1 = r1.wrapping_sub(anc);
+        }
+        q2 = q2.wrapping_mul(2);
+        r2 = r2.wrapping_mul(2);
+        if r2 >= ad {
+            q2 = q2.wrapping_add(1);
+            r2 = r2.wrapping_sub(ad);
+        }
+        let delta = ad - r2;
+        if !(q1 < delta || (q1 == delta && r1 == 0)) {
+            break;
+        }
+    }
+    let magic = q2.wrapping_add(1) as i32;
+    let magic = if d < 0 { magic.wrapping_neg() } else { magic };
+    return (magic, p - 32);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::risc_v_generator::peephole::eval;
+    use std::collections::HashMap;
+
+    const DIVISORS: [i32; 11] = [2, -2, 3, -3, 7, -7, 1 << 30, -(1 << 30), 5, i32::MAX, -i32::MAX];
+    const DIVIDENDS: [i32; 16] = [
+        i32::MIN, i32::MIN + 1, -1_000_000_007, -(1 << 30), -21, -7, -3, -1,
+        0, 1, 2, 6, 21, 1 << 30, 1_000_000_007, i32::MAX,
+    ];
+    const RD: Reg = Reg::Phys("a0");
+    const RS1: Reg = Reg::Phys("a1");
+
+    // Runs the straight-line code `emit` selects with `n` in RS1, returns RD
+    fn run(emit: impl FnOnce(&mut Writer<Vec<u8>>), n: i32) -> i32 {
+        let mut out = Vec::new();
+        let mut f = Writer::new(&mut out);
+        f.begin_function("test");
+        f.start_block("entry");
+        emit(&mut f);
+        let func = f.end_function();
+        let mut regs = HashMap::from([(ZERO, 0), (RS1, n)]);
+        for inst in &func.blocks[0].insts {
+            let (rd, value) = match *inst {
+                MachineInst::Li{ rd, imm } => (rd, imm),
+                MachineInst::Mv{ rd, rs } => (rd, regs[&rs]),
+                MachineInst::Op{ op, rd, rs1, rs2 } => (rd, eval(op, regs[&rs1], regs[&rs2]).unwrap()),
+                MachineInst::OpImm{ op, rd, rs1, imm } => (rd, eval(op, regs[&rs1], imm).unwrap()),
+                ref inst => panic!("unexpected `{}`", inst),
+            };
+            regs.insert(rd, value);
+        }
+        return regs[&RD];
+    }
+
+    #[test]
+    fn signed_magic_numbers() {
+        // Hacker's Delight, table 10-1
+        assert_eq!(signed_magic(3), (0x55555556, 0));
+        assert_eq!(signed_magic(5), (0x66666667, 1));
+        assert_eq!(signed_magic(7), (0x92492493u32 as i32, 2));
+        assert_eq!(signed_magic(-5), (0x99999999u32 as i32, 1));
+        assert_eq!(signed_magic(-7), (0x6DB6DB6D, 2));
+    }
+
+    #[test]
+    fn division_by_constants() {
+        for d in DIVISORS {
+            for n in DIVIDENDS {
+                assert_eq!(run(|f| f.divi(RD, RS1, T1, d), n), n / d, "{} / {}", n, d);
+                assert_eq!(run(|f| f.remi(RD, RS1, T1, d), n), n % d, "{} % {}", n, d);
+            }
+        }
+    }
+}
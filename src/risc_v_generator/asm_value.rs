@@ -1,5 +1,5 @@
-use super::{program_manager::*, asm_generator::Writer};
-use std::io::Result;
+use super::{program_manager::*, asm_generator::Writer, machine::*};
+use std::io::Write;
 pub enum AsmValue {
     Global(String),
     LocalVar(ValueSlot),
@@ -8,7 +8,7 @@ pub enum AsmValue {
     Void,
 }
 
-impl<'file> AsmValue {
+impl AsmValue {
     pub fn is_ptr(&self) -> bool {
         match self {
             Self::Global(_) => false,
@@ -19,92 +19,101 @@ impl<'file> AsmValue {
         }
     }
 
-    pub fn normal_to_reg(&self, f: &'file mut Writer, reg: &'static str) -> Result<()> {
-        let res = f.reg_temp;
-        f.update_temp_reg(reg);
+    pub fn normal_to_reg<W: Write>(&self, f: &mut Writer<W>, reg: Reg) {
         match self{
             Self::Global(name) => {
                 f.la(reg, name.as_str());
                 f.lw(reg, reg, 0);
             },
+            Self::LocalVar(ValueSlot{ reg: Some(slot), .. }) => {
+                if slot.reg != reg {
+                    f.mv(reg, slot.reg);
+                }
+            }
             Self::LocalVar(slot) => {
-                assert!(slot.stack.is_some());
-                f.lw(reg, "sp", slot.stackslot_offset().unwrap() as i32);
+                f.lw(reg, Base::Frame(slot.frame_index().unwrap()), 0);
             }
             Self::Const(val) => {
                 f.li(reg, *val);
             }
+            Self::FuncArg(index) => {
+                if *index < 8 {
+                    f.mv(reg, Reg::arg(*index));
+                }
+                else {
+                    f.lw(reg, Base::Incoming, ((index - 8) * 4) as i32); // load args from stack
+                }
+            }
             _ => unreachable!()
         };
-        f.update_temp_reg(res);
-        Ok(())
     }
 
-    pub fn load_addr_to_reg(&self, f: &'file mut Writer, reg: &'static str) -> Result<()> {
-        let res = f.reg_temp;
-        f.update_temp_reg(reg);
+    // The register holding the value: its own one, or `reg` once loaded into it
+    pub fn to_reg<W: Write>(&self, f: &mut Writer<W>, reg: Reg) -> Reg {
+        match self {
+            Self::LocalVar(ValueSlot{ reg: Some(slot), .. }) => slot.reg,
+            Self::FuncArg(index) if *index < 8 => Reg::arg(*index),
+            _ => {
+                self.normal_to_reg(f, reg);
+                reg
+            }
+        }
+    }
+
+    // The register to compute the value in: its own one, or `reg` to store it from
+    pub fn target_reg(&self, reg: Reg) -> Reg {
+        match self {
+            Self::LocalVar(ValueSlot{ reg: Some(slot), .. }) => slot.reg,
+            _ => reg,
+        }
+    }
+
+    // The value as the source of a copy
+    pub fn operand(&self) -> Operand {
+        match self {
+            Self::LocalVar(ValueSlot{ reg: Some(slot), .. }) => Operand::Reg(slot.reg),
+            Self::Const(val) => Operand::Imm(*val),
+            Self::FuncArg(index) if *index < 8 => Operand::Reg(Reg::arg(*index)),
+            _ => unreachable!()
+        }
+    }
 
+    pub fn load_addr_to_reg<W: Write>(&self, f: &mut Writer<W>, reg: Reg) {
         match self{
             Self::Global(name) => {
                 f.la(reg, name.as_str());
             },
             Self::LocalVar(slot) => {
-                assert!(slot.stack.is_some());
-                f.addi(reg, "sp", slot.stackslot_offset().unwrap() as i32);
+                f.addr(reg, Base::Frame(slot.frame_index().unwrap()));
             }
             _ => unreachable!()
         };
-        f.update_temp_reg(res);
-        Ok(())
     }
 
-    pub fn arg_to_reg(&self, f: &'file mut Writer, reg: &'static str, spoff:usize) -> Result<()> {
-        let res = f.reg_temp;
-        f.update_temp_reg(reg);
-
+    pub fn reload_value_from_reg<W: Write>(&self, f: &mut Writer<W>, reg: Reg, temp_reg: Reg) {
         match self{
-            Self::FuncArg(index) => {
-                if *index < 8 {
-                    f.mv(reg, format!("a{}", index).as_str());
-                }
-                else {
-                    f.lw(reg, "sp", (spoff + (index - 8) * 4) as i32); // load args from stack
-                
-                }
-            },
-            _ => unreachable!()
-        };
-        f.update_temp_reg(res);
-        Ok(())
-    }
-
-    pub fn reload_value_from_reg(&self, f: &'file mut Writer, reg: &str, temp_reg: &'static str) -> Result<()> {
-        let res = f.reg_temp;
-        f.update_temp_reg(temp_reg);
-        match self{
-
-
             Self::Global(name) => {
                 f.la(temp_reg, name);
                 f.sw(reg, temp_reg, 0);
             },
+            Self::LocalVar(ValueSlot{ reg: Some(slot), .. }) => {
+                if slot.reg != reg {
+                    f.mv(slot.reg, reg);
+                }
+            }
             Self::LocalVar(slot) => {
-                assert!(slot.stack.is_some());
-                f.sw(reg, "sp", slot.stackslot_offset().unwrap() as i32);
+                f.sw(reg, Base::Frame(slot.frame_index().unwrap()), 0);
             }
             Self::FuncArg(index) => {
                 if *index < 8 {
-                    f.mv(format!("a{}", index).as_str(), reg);
+                    f.mv(Reg::arg(*index), reg);
                 }
                 else {
-                    f.sw(reg, "sp", ((index - 8)*4) as i32); // write args to stack
+                    f.sw(reg, SP, ((index - 8)*4) as i32); // write args to stack
                 }
             }
             Self::Void => {}
             _ => unreachable!()
         };
-        f.update_temp_reg(res);
-        Ok(())
     }
 }
-
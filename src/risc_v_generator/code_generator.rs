@@ -5,33 +5,38 @@
 use super::asm_generator::*;
 use super::program_manager::*;
 use super::asm_value::*;
+use super::machine::*;
+use super::frame::lay_out_frame;
+use super::peephole::peephole;
 use super::reg_manager::*;
+use super::coloring::graph_coloring;
+use super::RegAlloc;
 
 use koopa::ir::entities::*;
 use koopa::ir::*;
 use koopa::ir::ValueKind;
 use koopa::ir::values::*;
-use std::fs::File;
+use std::collections::HashMap;
 use std::io::{Write, Result};
 
 // * trait AsmGenerator - 递归生成汇编代码
 pub trait AsmGenerator<'prog, 'file> {
     type Out;
 
-    fn generate(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file>) -> Result<Self::Out>;
+    fn generate<W: Write>(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file, W>) -> Result<Self::Out>;
 }
 
 // * trait AsmValueGenerator - 上一trait的扩展，用于对IR设计的value各个类型传递其Data
 pub trait AsmValueGenerator<'prog, 'file> {
     type Out;
 
-    fn generate(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file>,  value: &ValueData) -> Result<Self::Out>;
+    fn generate<W: Write>(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file, W>,  value: &ValueData) -> Result<Self::Out>;
 }
 
 impl<'prog, 'file> AsmGenerator<'prog, 'file> for Program{
     type Out = ();
     
-    fn generate(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file>) -> Result<Self::Out>{
+    fn generate<W: Write>(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file, W>) -> Result<Self::Out>{
         Type::set_ptr_size(4);
         if !self.inst_layout().is_empty()
         {
@@ -74,50 +79,97 @@ impl<'prog, 'file> AsmGenerator<'prog, 'file> for Program{
 impl<'prog, 'file> AsmGenerator<'prog, 'file> for FunctionData {
     type Out = ();
 
-    fn generate(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file>) -> Result<Self::Out> {
+    fn generate<W: Write>(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file, W>) -> Result<Self::Out> {
         if self.layout().entry_bb().is_none() {
             return Ok(());
         }
 
 
+        // Arguments only copied into an alloc are read straight from a0-a7 or the caller's frame
+        let needs_slot = |value: Value| {
+            let data = self.dfg().value(value);
+            let needed = match data.kind() {
+                ValueKind::BlockArgRef(_) => true,
+                ValueKind::FuncArgRef(_) => data.used_by().iter().any(|&user| !matches!(self.dfg().value(user).kind(), ValueKind::Store(_))),
+                kind => kind.is_local_inst() && !matches!(kind, ValueKind::Alloc(_)),
+            };
+            needed && !data.used_by().is_empty()
+        };
+        let kept = self.params().iter().copied()
+            .chain(self.layout().bbs().iter().flat_map(|(&bb, node)| {
+                self.dfg().bb(bb).params().iter().chain(node.insts().keys()).copied()
+            }))
+            .filter(|&value| needs_slot(value))
+            .collect::<Vec<_>>();
+        let allocation = match program.regalloc() {
+            RegAlloc::Stack => Allocation{ regs: HashMap::new() },
+            RegAlloc::LinearScan => linear_scan(self, &kept),
+            RegAlloc::GraphColoring => graph_coloring(self, &kept),
+        };
+
+        f.begin_function(&self.name()[1..]);
         let func_interface = program.cur_func_mut().unwrap();
-        for value in self.dfg().values().values(){
-            if value.kind().is_local_inst() && !value.used_by().is_empty(){
-                func_interface.alloc_new_slot(value);
-            }
-            if let ValueKind::Call(val) = value.kind(){
-                func_interface.update_max_arg_num(val.args().len());
+        for &value in &kept {
+            func_interface.alloc_reg(self.dfg().value(value), Reg::Virt(value));
+        }
+        for node in self.layout().bbs().nodes() {
+            for &inst in node.insts().keys() {
+                let value = self.dfg().value(inst);
+                if let (ValueKind::Alloc(_), TypeKind::Pointer(unit)) = (value.kind(), value.ty().kind()) {
+                    if !value.used_by().is_empty() {
+                        let index = f.frame_mut().new_object(unit.size());
+                        func_interface.alloc_frame_object(value, index);
+                    }
+                }
+                if let ValueKind::Call(val) = value.kind(){
+                    f.frame_mut().reserve_args(val.args().len());
+                }
             }
         }
 
-        writeln!(f.file_mut(), "  # Function {}, arg_num: {}", &self.name().to_string()[1..], func_interface.get_arg_num().unwrap_or_default())?;
-
         for (bb, bb_data) in self.dfg().bbs() {
             func_interface.set_bb_name(*bb, bb_data.name());
         }
 
-        f.func_entry(self.name(), func_interface);
-
-        let func_interface = program.cur_func().unwrap();
-
         for (bb, bb_node) in self.layout().bbs() {
             let bb_name = bb.generate(program, f)?;
 
-            writeln!(f.file_mut(), "{}:", &bb_name.as_str())?;
+            f.start_block(&bb_name);
+            if Some(*bb) == self.layout().entry_bb() {
+                generate_params(program, f, self);
+            }
             for (&val_handle, _) in bb_node.insts() {
                 self.dfg().value(val_handle).generate(program, f)?;
             }
 
         }
-        writeln!(f.file_mut());
+
+        let mut func = f.end_function();
+        allocation.apply(&mut func);
+        lay_out_frame(&mut func);
+        peephole(&mut func);
+        func.print(f.file_mut())?;
 
         return Ok(());
     }
 }
 
+// Arguments used directly (after mem2reg) are copied out of a0-a7 before any call
+// clobbers them, and the ones passed on the stack loaded after
+fn generate_params<'prog, 'file, W: Write>(program: &mut ProgramManager<'prog>, f: &mut Writer<'file, W>, data: &FunctionData) {
+    let func_interface = program.cur_func().unwrap();
+    let params = data.params().iter().enumerate()
+        .filter_map(|(index, &param)| Some((index, func_interface.slot(data.dfg().value(param))?.reg?.reg)))
+        .collect::<Vec<_>>();
+    f.copy(params.iter().filter(|(index, _)| *index < 8).map(|&(index, reg)| (reg, Operand::Reg(Reg::arg(index)))).collect());
+    for &(index, reg) in params.iter().filter(|(index, _)| *index >= 8) {
+        AsmValue::FuncArg(index).normal_to_reg(f, reg);
+    }
+}
+
 impl<'prog, 'file> AsmGenerator<'prog, 'file> for BasicBlock{
     type Out = String;
-    fn generate(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file>) -> Result<Self::Out> {
+    fn generate<W: Write>(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file, W>) -> Result<Self::Out> {
         Ok(program.cur_func().unwrap().get_bb_name(*self).to_string())
     }
 }
@@ -125,7 +177,7 @@ impl<'prog, 'file> AsmGenerator<'prog, 'file> for BasicBlock{
 impl<'prog, 'file> AsmGenerator<'prog, 'file> for Value{
     type Out = AsmValue;
 
-    fn generate(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file>) -> Result<Self::Out> {
+    fn generate<W: Write>(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file, W>) -> Result<Self::Out> {
         if self.is_global() {
             Ok(AsmValue::Global(program.value_name(*self).clone()))
         }
@@ -134,9 +186,13 @@ impl<'prog, 'file> AsmGenerator<'prog, 'file> for Value{
             let val_data = program.program().func(func_interface.get_func()).dfg().value(*self);
             let ret = match val_data.kind() {
                 ValueKind::Integer(v) => AsmValue::Const(v.value()),
-                ValueKind::FuncArgRef(v) => AsmValue::FuncArg(v.index()),
+                ValueKind::Undef(_) => AsmValue::Const(0),
+                ValueKind::FuncArgRef(v) => match func_interface.slot(val_data) {
+                    Some(slot) => AsmValue::LocalVar(slot),
+                    None => AsmValue::FuncArg(v.index()),
+                },
                 _ => {
-                    let new_slot = func_interface.stack_offset_resize(val_data);
+                    let new_slot = func_interface.slot(val_data);
                     match new_slot {
                         Some(slot) => AsmValue::LocalVar(slot),
                         None => AsmValue::Void,
@@ -151,7 +207,7 @@ impl<'prog, 'file> AsmGenerator<'prog, 'file> for Value{
 impl<'prog, 'file> AsmGenerator<'prog, 'file> for ValueData {
     type Out = ();
 
-    fn generate(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file>) -> Result<Self::Out> {
+    fn generate<W: Write>(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file, W>) -> Result<Self::Out> {
         
         match self.kind() {
             ValueKind::ZeroInit(v) => v.generate(program, f, self),
@@ -175,7 +231,7 @@ impl<'prog, 'file> AsmGenerator<'prog, 'file> for ValueData {
 impl<'prog, 'file> AsmGenerator<'prog, 'file> for Integer {
     type Out = ();
 
-    fn generate(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file>) -> Result<Self::Out> {
+    fn generate<W: Write>(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file, W>) -> Result<Self::Out> {
         writeln!(f.file_mut(), "  .word {}", self.value())
     }
 }
@@ -183,7 +239,7 @@ impl<'prog, 'file> AsmGenerator<'prog, 'file> for Integer {
 impl<'prog, 'file> AsmGenerator<'prog, 'file> for Aggregate {
     type Out = ();
 
-    fn generate(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file>) -> Result<Self::Out> {
+    fn generate<W: Write>(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file, W>) -> Result<Self::Out> {
         for &val in self.elems(){
             program.program().borrow_value(val).generate(program, f)?;
         }
@@ -194,7 +250,7 @@ impl<'prog, 'file> AsmGenerator<'prog, 'file> for Aggregate {
 impl<'prog, 'file> AsmGenerator<'prog, 'file> for GlobalAlloc {
     type Out = ();
 
-    fn generate(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file>) -> Result<Self::Out> {
+    fn generate<W: Write>(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file, W>) -> Result<Self::Out> {
         program.program().borrow_value(self.init()).generate(program, f)?;
         Ok(())
     }
@@ -203,26 +259,15 @@ impl<'prog, 'file> AsmGenerator<'prog, 'file> for GlobalAlloc {
 impl<'prog, 'file> AsmGenerator<'prog, 'file> for Store {
     type Out = ();
 
-    fn generate(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file>) -> Result<Self::Out> {
-        writeln!(f.file_mut(), "    # Store");
-        let spoff = program.cur_func().unwrap().sp_offset();
-        let val = self.value().generate(program, f)?;
-        match val {
-            AsmValue::FuncArg(v) => val.arg_to_reg(f, "t0", spoff)?,
-            _ => val.normal_to_reg(f, "t0")?,
-        }
+    fn generate<W: Write>(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file, W>) -> Result<Self::Out> {
+        let src = self.value().generate(program, f)?.to_reg(f, T0);
         let dst = self.dest().generate(program, f)?;
         if dst.is_ptr() {
-            f.update_temp_reg("t2");
-            dst.normal_to_reg(f, "t1")?;
-
-            f.sw("t0", "t1", 0);
-            f.update_temp_reg("t0");
+            let addr = dst.to_reg(f, T1);
+            f.sw(src, addr, 0);
         }
         else {
-            f.update_temp_reg("t2");
-            dst.reload_value_from_reg(f, "t0", "t1");
-            f.update_temp_reg("t0");
+            dst.reload_value_from_reg(f, src, T1);
         }
         Ok(())
     }
@@ -231,13 +276,25 @@ impl<'prog, 'file> AsmGenerator<'prog, 'file> for Store {
 impl<'prog, 'file> AsmGenerator<'prog, 'file> for Branch {
     type Out = ();
 
-    fn generate(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file>) -> Result<Self::Out> {
-        self.cond().generate(program, f)?.normal_to_reg(f, "t0");
+    fn generate<W: Write>(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file, W>) -> Result<Self::Out> {
+        let cond = self.cond().generate(program, f)?.to_reg(f, T0);
         let func_interface = program.cur_func().unwrap();
-        let tto_name = func_interface.get_bb_name(self.true_bb());
-        let fto_name = func_interface.get_bb_name(self.false_bb());
-        f.bnez("t0", &tto_name);
-        f.j(&fto_name);
+        let tto_name = func_interface.get_bb_name(self.true_bb()).to_string();
+        let fto_name = func_interface.get_bb_name(self.false_bb()).to_string();
+        if self.true_args().is_empty() && self.false_args().is_empty() {
+            f.bnez(cond, &tto_name);
+            f.j(&fto_name);
+        }
+        else {
+            // Each edge copies its own arguments
+            let else_name = func_interface.new_label();
+            f.beqz(cond, &else_name);
+            generate_block_args(program, f, self.true_bb(), self.true_args())?;
+            f.j(&tto_name);
+            f.start_block(&else_name);
+            generate_block_args(program, f, self.false_bb(), self.false_args())?;
+            f.j(&fto_name);
+        }
         Ok(())
     }
 }
@@ -245,22 +302,37 @@ impl<'prog, 'file> AsmGenerator<'prog, 'file> for Branch {
 impl<'prog, 'file> AsmGenerator<'prog, 'file> for Jump {
     type Out = ();
 
-    fn generate(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file>) -> Result<Self::Out>{
+    fn generate<W: Write>(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file, W>) -> Result<Self::Out>{
+        generate_block_args(program, f, self.target(), self.args())?;
         let func_interface = program.cur_func().unwrap();
         let to_name = func_interface.get_bb_name(self.target());
-        f.j(&to_name);
+        f.j(to_name);
         Ok(())
     }
 }
 
+// Block arguments are a parallel copy into the target's parameters
+fn generate_block_args<'prog, 'file, W: Write>(program: &mut ProgramManager<'prog>, f: &mut Writer<'file, W>, target: BasicBlock, args: &[Value]) -> Result<()> {
+    let func = program.cur_func().unwrap().get_func();
+    let params = program.program().func(func).dfg().bb(target).params().to_vec();
+    let mut moves = Vec::new();
+    for (param, arg) in params.into_iter().zip(args.iter().copied()) {
+        if let AsmValue::LocalVar(ValueSlot{ reg: Some(slot), .. }) = param.generate(program, f)? {
+            moves.push((slot.reg, arg.generate(program, f)?.operand()));
+        }
+    }
+    f.copy(moves);
+    Ok(())
+}
+
 impl<'prog, 'file> AsmGenerator<'prog, 'file> for Return {
     type Out = ();
 
-    fn generate(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file>) -> Result<Self::Out> {
+    fn generate<W: Write>(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file, W>) -> Result<Self::Out> {
         if let Some(val) = self.value() {
-            val.clone().generate(program, f)?.normal_to_reg(f, "a0");
+            val.generate(program, f)?.normal_to_reg(f, A0);
         }
-        f.func_end(program.cur_func().unwrap());
+        f.ret();
         Ok(())
     }
 }
@@ -268,7 +340,7 @@ impl<'prog, 'file> AsmGenerator<'prog, 'file> for Return {
 impl<'prog, 'file> AsmValueGenerator<'prog, 'file> for ZeroInit {
     type Out = ();
 
-    fn generate(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file>, val: &ValueData) -> Result<Self::Out> {
+    fn generate<W: Write>(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file, W>, val: &ValueData) -> Result<Self::Out> {
         let valname = val.name();
         writeln!(f.file_mut(), "  .zero {}", val.ty().size())
     }
@@ -277,128 +349,126 @@ impl<'prog, 'file> AsmValueGenerator<'prog, 'file> for ZeroInit {
 impl<'prog, 'file> AsmValueGenerator<'prog, 'file> for Load {
     type Out = ();
 
-    fn generate(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file>,  value: &ValueData) -> Result<Self::Out> {
+    fn generate<W: Write>(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file, W>,  value: &ValueData) -> Result<Self::Out> {
         let src = self.src().generate(program, f)?;
-        src.normal_to_reg(f, "t0")?;
+        let dest = result_of(program, value);
+        let reg = dest.target_reg(T0);
         if src.is_ptr(){
-            f.update_temp_reg("t1");
-            f.lw("t0", "t0", 0)?;
-            f.update_temp_reg("t0");
+            let addr = src.to_reg(f, T0);
+            f.lw(reg, addr, 0);
         }
-        match program.cur_func().unwrap().stack_offset_resize(value) {
-            Some(v) => AsmValue::LocalVar(v).reload_value_from_reg(f, "t0", "t1"),
-            None => AsmValue::Void.reload_value_from_reg(f, "t0", "t1")
-        };
+        else {
+            src.normal_to_reg(f, reg);
+        }
+        dest.reload_value_from_reg(f, reg, T1);
         Ok(())
     }
 }
 
-impl<'prog, 'file> AsmValueGenerator<'prog, 'file> for GetPtr {
-    type Out = ();
+// Where the result of `value` goes
+fn result_of(program: &ProgramManager, value: &ValueData) -> AsmValue {
+    match program.cur_func().unwrap().slot(value) {
+        Some(slot) => AsmValue::LocalVar(slot),
+        None => AsmValue::Void,
+    }
+}
 
-    fn generate(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file>,  value: &ValueData) -> Result<Self::Out> {
-        writeln!(f.file_mut(), "    # Ptr");
-        let src = self.src().generate(program, f)?;
-        if src.is_ptr(){
-            src.normal_to_reg(f, "t0");
-        }
-        else {
-            src.load_addr_to_reg(f, "t0");
-        }
-        self.index().generate(program, f)?.normal_to_reg(f, "t1")?;
-        let size = match value.ty().kind() {
-            TypeKind::Pointer(b) => b.size(),
-            _ => unreachable!()
-        };
-        f.update_temp_reg("t2");
-        f.muli("t1", "t1", size as i32);
-        f.op2("add", "t0", "t0", "t1");
+// base + index * size, the size being that of the pointee of the result
+fn generate_ptr<'prog, 'file, W: Write>(program: &mut ProgramManager<'prog>, f: &mut Writer<'file, W>, src: Value, index: Value, value: &ValueData) -> Result<()> {
+    let src = src.generate(program, f)?;
+    let base = if src.is_ptr(){
+        src.to_reg(f, T0)
+    }
+    else {
+        src.load_addr_to_reg(f, T0);
+        T0
+    };
+    let index = index.generate(program, f)?.to_reg(f, T1);
+    let size = match value.ty().kind() {
+        TypeKind::Pointer(b) => b.size(),
+        _ => unreachable!()
+    };
+    f.update_temp_reg(T2);
+    f.muli(T1, index, size as i32);
+    let dest = result_of(program, value);
+    let reg = dest.target_reg(T0);
+    f.op2("add", reg, base, T1);
+
+    dest.reload_value_from_reg(f, reg, T1);
+    f.update_temp_reg(T0);
+    Ok(())
+}
 
+impl<'prog, 'file> AsmValueGenerator<'prog, 'file> for GetPtr {
+    type Out = ();
 
-        match program.cur_func().unwrap().stack_offset_resize(value) {
-            Some(v) => AsmValue::LocalVar(v).reload_value_from_reg(f, "t0", "t1"),
-            None => AsmValue::Void.reload_value_from_reg(f, "t0", "t1")
-        };
-        f.update_temp_reg("t0");
-        Ok(())
+    fn generate<W: Write>(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file, W>,  value: &ValueData) -> Result<Self::Out> {
+        generate_ptr(program, f, self.src(), self.index(), value)
     }
 }
 
 impl<'prog, 'file> AsmValueGenerator<'prog, 'file> for GetElemPtr {
     type Out = ();
 
-    fn generate(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file>,  value: &ValueData) -> Result<Self::Out> {
-        writeln!(f.file_mut(), "    # Elemptr");
-        let src = self.src().generate(program, f)?;
-        if src.is_ptr(){
-            src.normal_to_reg(f, "t0");
-        }
-        else {
-            src.load_addr_to_reg(f, "t0");
-        }
-        self.index().generate(program, f)?.normal_to_reg(f, "t1")?;
-        let size = match value.ty().kind() {
-            TypeKind::Pointer(b) => b.size(),
-            _ => unreachable!()
-        };
-        f.update_temp_reg("t2");
-        f.muli("t1", "t1", size as i32);
-        f.op2("add", "t0", "t0", "t1");
-
-
-        match program.cur_func().unwrap().stack_offset_resize(value) {
-            Some(v) => AsmValue::LocalVar(v).reload_value_from_reg(f, "t0", "t1"),
-            None => AsmValue::Void.reload_value_from_reg(f, "t0", "t1")
-        };
-        f.update_temp_reg("t0");
-        Ok(())
+    fn generate<W: Write>(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file, W>,  value: &ValueData) -> Result<Self::Out> {
+        generate_ptr(program, f, self.src(), self.index(), value)
     }
 }
 
 impl<'prog, 'file> AsmValueGenerator<'prog, 'file> for Binary {
     type Out = ();
 
-    fn generate(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file>,  value: &ValueData) -> Result<Self::Out> {
-        self.lhs().generate(program, f)?.normal_to_reg(f, "t0")?;
-        self.rhs().generate(program, f)?.normal_to_reg(f, "t1")?;
-        f.update_temp_reg("t2");
+    fn generate<W: Write>(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file, W>,  value: &ValueData) -> Result<Self::Out> {
+        let lhs = self.lhs().generate(program, f)?.to_reg(f, T0);
+        let rhs = self.rhs().generate(program, f)?;
+        // Division by a constant needs no divide instruction
+        let divisor = match (self.op(), &rhs) {
+            (BinaryOp::Div | BinaryOp::Mod, &AsmValue::Const(d)) if d != 0 && d != i32::MIN => Some(d),
+            _ => None,
+        };
+        let rhs = match divisor {
+            Some(_) => T1,
+            None => rhs.to_reg(f, T1),
+        };
+        let dest = result_of(program, value);
+        let rd = dest.target_reg(T0);
+        f.update_temp_reg(T2);
         match self.op() {
-            BinaryOp::Add => f.op2("add", "t0", "t0", "t1")?,
-            BinaryOp::Sub => f.op2("sub", "t0", "t0", "t1")?,
-            BinaryOp::Mul => f.op2("mul", "t0", "t0", "t1")?,
-            BinaryOp::Div => f.op2("div", "t0", "t0", "t1")?,
-            BinaryOp::Mod => f.op2("rem", "t0", "t0", "t1")?,
-            BinaryOp::And => f.op2("and", "t0", "t0", "t1")?,
-            BinaryOp::Or => f.op2("or", "t0", "t0", "t1")?,
-            BinaryOp::Xor => f.op2("xor", "t0", "t0", "t1")?,
-            BinaryOp::Shl => f.op2("sll", "t0", "t0", "t1")?,
-            BinaryOp::Shr => f.op2("srl", "t0", "t0", "t1")?,
-            BinaryOp::Sar => f.op2("sra", "t0", "t0", "t1")?,
+            BinaryOp::Div if divisor.is_some() => f.divi(rd, lhs, T1, divisor.unwrap()),
+            BinaryOp::Mod if divisor.is_some() => f.remi(rd, lhs, T1, divisor.unwrap()),
+            BinaryOp::Add => f.op2("add", rd, lhs, rhs),
+            BinaryOp::Sub => f.op2("sub", rd, lhs, rhs),
+            BinaryOp::Mul => f.op2("mul", rd, lhs, rhs),
+            BinaryOp::Div => f.op2("div", rd, lhs, rhs),
+            BinaryOp::Mod => f.op2("rem", rd, lhs, rhs),
+            BinaryOp::And => f.op2("and", rd, lhs, rhs),
+            BinaryOp::Or => f.op2("or", rd, lhs, rhs),
+            BinaryOp::Xor => f.op2("xor", rd, lhs, rhs),
+            BinaryOp::Shl => f.op2("sll", rd, lhs, rhs),
+            BinaryOp::Shr => f.op2("srl", rd, lhs, rhs),
+            BinaryOp::Sar => f.op2("sra", rd, lhs, rhs),
 
             BinaryOp::NotEq => {
-                f.op2("xor", "t0", "t0", "t1")?;
-                f.op1("snez", "t0", "t0")?;
+                f.op2("xor", rd, lhs, rhs);
+                f.op1("snez", rd, rd);
             },
             BinaryOp::Eq => {
-                f.op2("xor", "t0", "t0", "t1")?;
-                f.op1("seqz", "t0", "t0")?;
+                f.op2("xor", rd, lhs, rhs);
+                f.op1("seqz", rd, rd);
             },
-            BinaryOp::Gt => f.op2("sgt", "t0", "t0", "t1")?,
-            BinaryOp::Lt => f.op2("slt", "t0", "t0", "t1")?,
+            BinaryOp::Gt => f.op2("sgt", rd, lhs, rhs),
+            BinaryOp::Lt => f.op2("slt", rd, lhs, rhs),
             BinaryOp::Ge => {
-                f.op2("slt", "t0", "t0", "t1")?;
-                f.op1("seqz", "t0", "t0")?;
+                f.op2("slt", rd, lhs, rhs);
+                f.op1("seqz", rd, rd);
             },
             BinaryOp::Le => {
-                f.op2("sgt", "t0", "t0", "t1")?;
-                f.op1("seqz", "t0", "t0")?;
+                f.op2("sgt", rd, lhs, rhs);
+                f.op1("seqz", rd, rd);
             },
         }
-        f.update_temp_reg("t0");
-        match program.cur_func().unwrap().stack_offset_resize(value) {
-            Some(v) => AsmValue::LocalVar(v).reload_value_from_reg(f, "t0", "t1"),
-            None => AsmValue::Void.reload_value_from_reg(f, "t0", "t1")
-        };
+        f.update_temp_reg(T0);
+        dest.reload_value_from_reg(f, rd, T1);
         Ok(())
     }
 }
@@ -406,28 +476,24 @@ impl<'prog, 'file> AsmValueGenerator<'prog, 'file> for Binary {
 impl<'prog, 'file> AsmValueGenerator<'prog, 'file> for Call {
     type Out = ();
 
-    fn generate(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file>,  value: &ValueData) -> Result<Self::Out> {
+    fn generate<W: Write>(&self, program: &mut ProgramManager<'prog>, f: &mut Writer<'file, W>,  value: &ValueData) -> Result<Self::Out> {
         let mut arglist = Vec::new();
         for arg in self.args() {
-            arglist.push(arg.clone().generate(program, f)?);
+            arglist.push(arg.generate(program, f)?);
         }
 
-        for (i, arg) in arglist.iter().enumerate() {
-            arg.normal_to_reg(f, "t0");
-            AsmValue::FuncArg(i).reload_value_from_reg(f, "t0", "t1");
+        // The stack arguments first, then a parallel copy into a0-a7
+        for (i, arg) in arglist.iter().enumerate().skip(8) {
+            let reg = arg.to_reg(f, T0);
+            AsmValue::FuncArg(i).reload_value_from_reg(f, reg, T1);
         }
+        f.copy(arglist.iter().take(8).enumerate().map(|(i, arg)| (Reg::arg(i), arg.operand())).collect());
 
         let callee_name = &program.program().func(self.callee()).name()[1..];
         f.call(callee_name);
         if !value.used_by().is_empty() {
-            match program.cur_func().unwrap().stack_offset_resize(value) {
-                Some(v) => AsmValue::LocalVar(v).reload_value_from_reg(f, "a0", "t1"),
-                None => AsmValue::Void.reload_value_from_reg(f, "a0", "t1")
-            };
-            Ok(())
-        }
-        else {
-            Ok(())
+            result_of(program, value).reload_value_from_reg(f, A0, T1);
         }
+        Ok(())
     }
-}
\ No newline at end of file
+}
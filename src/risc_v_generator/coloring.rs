@@ -0,0 +1,476 @@
+/*
+    Graph-coloring register allocation:
+        Iterated register coalescing (George and Appel). The registers are
+        precolored nodes and the kept values the others; a value live
+        across a call interferes with every register a call clobbers.
+    Moves are the copies the code generator makes anyway: block arguments
+    into parameters, call arguments into a0-a7, the result out of a0 and
+    the returned value into it, and the function parameters out of a0-a7.
+    Coalescing the two ends of a move (Briggs' test between values,
+    George's against a register) removes it.
+    A spill keeps the value in its stack slot, which the code generator
+    reaches through its scratch registers, so one round suffices. The
+    value spilled first is the one with the least uses per neighbour,
+    each use counting ten times more per enclosing loop.
+*/
+use koopa::ir::{BasicBlock, FunctionData, Value, ValueKind};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use crate::koopa_generator::ir_optimizer::analysis::{Cfg, DomTree, LoopForest};
+use super::liveness::{defs, live_out, uses};
+use super::reg_manager::{Allocation, RegClass, REGISTERS};
+
+const REG_NUM: usize = REGISTERS.len();
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum NodeState {
+    Precolored,
+    Initial,
+    Simplify,
+    Freeze,
+    Spill,
+    Coalesced,
+    Selected,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MoveState {
+    Worklist,
+    Active,
+    Coalesced,
+    Constrained,
+    Frozen,
+}
+
+// Nodes below REG_NUM are the registers, in the order of REGISTERS
+struct Coloring {
+    k: usize,                 // Registers available for values
+    available: Vec<bool>,     // By register
+    adj_set: HashSet<(usize, usize)>,
+    adj_list: Vec<Vec<usize>>,
+    degree: Vec<usize>,
+    cost: Vec<f64>,
+    state: Vec<NodeState>,
+    alias: Vec<usize>,
+    color: Vec<Option<usize>>,
+    moves: Vec<(usize, usize)>,
+    move_state: Vec<MoveState>,
+    move_list: Vec<Vec<usize>>,
+    simplify_worklist: BTreeSet<usize>,
+    freeze_worklist: BTreeSet<usize>,
+    spill_worklist: BTreeSet<usize>,
+    worklist_moves: BTreeSet<usize>,
+    select_stack: Vec<usize>,
+}
+
+// `kept` lists the values in layout order, which keeps the result deterministic
+pub fn graph_coloring(data: &FunctionData, kept: &[Value]) -> Allocation {
+    let kept_set = kept.iter().copied().collect::<HashSet<_>>();
+    let node = kept.iter().enumerate().map(|(index, &value)| (value, REG_NUM + index)).collect::<HashMap<_, _>>();
+    // A parameter read from its argument register wherever it is used keeps that register
+    let mut available = vec![true; REG_NUM];
+    for (index, param) in data.params().iter().enumerate().take(8) {
+        if !kept_set.contains(param) {
+            available[arg_reg(index)] = false;
+        }
+    }
+    let mut coloring = Coloring::new(REG_NUM + kept.len(), available);
+    coloring.build(data, &kept_set, &node);
+    coloring.allocate();
+
+    let regs = kept.iter()
+        .filter_map(|value| coloring.color[node[value]].map(|color| (*value, REGISTERS[color].name)))
+        .collect();
+    return Allocation{ regs };
+}
+
+fn arg_reg(index: usize) -> usize {
+    REGISTERS.iter().position(|reg| reg.name == format!("a{}", index)).unwrap()
+}
+
+impl Coloring {
+    fn new(nodes: usize, available: Vec<bool>) -> Self {
+        let mut state = vec![NodeState::Initial; nodes];
+        let mut degree = vec![0; nodes];
+        let mut color = vec![None; nodes];
+        for reg in 0..REG_NUM {
+            state[reg] = NodeState::Precolored;
+            degree[reg] = usize::MAX / 2;
+            color[reg] = Some(reg);
+        }
+        Self{
+            k: available.iter().filter(|&&free| free).count(),
+            available,
+            adj_set: HashSet::new(),
+            adj_list: vec![Vec::new(); nodes],
+            degree,
+            cost: vec![0.0; nodes],
+            state,
+            alias: (0..nodes).collect(),
+            color,
+            moves: Vec::new(),
+            move_state: Vec::new(),
+            move_list: vec![Vec::new(); nodes],
+            simplify_worklist: BTreeSet::new(),
+            freeze_worklist: BTreeSet::new(),
+            spill_worklist: BTreeSet::new(),
+            worklist_moves: BTreeSet::new(),
+            select_stack: Vec::new(),
+        }
+    }
+
+    fn is_precolored(&self, n: usize) -> bool {
+        n < REG_NUM
+    }
+
+    fn add_edge(&mut self, u: usize, v: usize) {
+        if u == v || self.adj_set.contains(&(u, v)) {
+            return;
+        }
+        self.adj_set.insert((u, v));
+        self.adj_set.insert((v, u));
+        for (a, b) in [(u, v), (v, u)] {
+            if !self.is_precolored(a) {
+                self.adj_list[a].push(b);
+                self.degree[a] += 1;
+            }
+        }
+    }
+
+    fn add_move(&mut self, dst: usize, src: usize) {
+        let unavailable = |n: usize| n < REG_NUM && !self.available[n];
+        if dst == src || unavailable(dst) || unavailable(src) {
+            return;
+        }
+        let index = self.moves.len();
+        self.moves.push((dst, src));
+        self.move_state.push(MoveState::Worklist);
+        self.worklist_moves.insert(index);
+        self.move_list[dst].push(index);
+        self.move_list[src].push(index);
+    }
+
+    // Interference, moves and spill costs, block by block backward from what is live out
+    fn build(&mut self, data: &FunctionData, kept: &HashSet<Value>, node: &HashMap<Value, usize>) {
+        let cfg = Cfg::new(data);
+        let loops = LoopForest::new(&cfg, &DomTree::new(&cfg));
+        let caller_saved = (0..REG_NUM).filter(|&reg| REGISTERS[reg].class != RegClass::Saved).collect::<Vec<_>>();
+        let live_out = live_out(data, kept);
+
+        for (&bb, block) in data.layout().bbs() {
+            let weight = 10f64.powi(loops.depth(bb).min(8) as i32);
+            let mut live = live_out[&bb].iter().map(|value| node[value]).collect::<BTreeSet<_>>();
+            let insts = block.insts().keys().copied().collect::<Vec<_>>();
+            for &inst in insts.iter().rev() {
+                let inst_defs = defs(data, kept, inst).iter().map(|value| node[value]).collect::<Vec<_>>();
+                let inst_uses = uses(data, kept, inst).iter().map(|value| node[value]).collect::<Vec<_>>();
+                // Own sources of the defined values, which may share their register
+                let mut sources = HashMap::new();
+                match data.dfg().value(inst).kind() {
+                    ValueKind::Jump(_) | ValueKind::Branch(_) => {
+                        // A branch with both edges to one block may pass a parameter two
+                        // different arguments: it then has no single source
+                        let mut passed = HashMap::new();
+                        for (target, args) in edges(data, inst) {
+                            for (param, arg) in data.dfg().bb(target).params().iter().zip(args) {
+                                let (Some(&param), arg) = (node.get(param), node.get(&arg).copied()) else {
+                                    continue;
+                                };
+                                if let Some(arg) = arg {
+                                    self.add_move(param, arg);
+                                }
+                                passed.entry(param).or_insert_with(Vec::new).push(arg);
+                            }
+                        }
+                        for (param, args) in passed {
+                            match args[0] {
+                                Some(arg) if args.iter().all(|&other| other == args[0]) => {
+                                    sources.insert(param, arg);
+                                },
+                                _ => {},
+                            }
+                        }
+                        // Parameters are written one by one while the arguments are read
+                        for &def in &inst_defs {
+                            for &other in inst_defs.iter().chain(&inst_uses) {
+                                if sources.get(&def) != Some(&other) {
+                                    self.add_edge(def, other);
+                                }
+                            }
+                        }
+                    },
+                    ValueKind::Call(call) => {
+                        for &value in live.iter().filter(|&&value| Some(&value) != node.get(&inst)) {
+                            for &reg in &caller_saved {
+                                self.add_edge(value, reg);
+                            }
+                        }
+                        for (index, arg) in call.args().iter().enumerate().take(8) {
+                            if let Some(&arg) = node.get(arg) {
+                                self.add_move(arg_reg(index), arg);
+                            }
+                        }
+                        if let Some(&result) = node.get(&inst) {
+                            self.add_move(result, arg_reg(0));
+                        }
+                    },
+                    ValueKind::Return(ret) => {
+                        if let Some(&value) = ret.value().and_then(|value| node.get(&value)) {
+                            self.add_move(arg_reg(0), value);
+                        }
+                    },
+                    _ => {},
+                }
+                for &def in &inst_defs {
+                    for &value in &live {
+                        if sources.get(&def) != Some(&value) {
+                            self.add_edge(def, value);
+                        }
+                    }
+                    self.cost[def] += weight;
+                    live.remove(&def);
+                }
+                for &value in &inst_uses {
+                    self.cost[value] += weight;
+                    live.insert(value);
+                }
+            }
+        }
+
+        // The function parameters are all written on entry
+        let params = data.params().iter().filter(|param| kept.contains(param)).map(|param| node[param]).collect::<Vec<_>>();
+        for (index, &param) in params.iter().enumerate() {
+            for &other in &params[index + 1..] {
+                self.add_edge(param, other);
+            }
+        }
+        for (index, param) in data.params().iter().enumerate().take(8) {
+            if let Some(&param) = node.get(param) {
+                self.add_move(param, arg_reg(index));
+            }
+        }
+    }
+
+    fn allocate(&mut self) {
+        for n in REG_NUM..self.state.len() {
+            if self.degree[n] >= self.k {
+                self.spill_worklist.insert(n);
+                self.state[n] = NodeState::Spill;
+            }
+            else if self.is_move_related(n) {
+                self.freeze_worklist.insert(n);
+                self.state[n] = NodeState::Freeze;
+            }
+            else {
+                self.simplify_worklist.insert(n);
+                self.state[n] = NodeState::Simplify;
+            }
+        }
+        loop {
+            if let Some(n) = self.simplify_worklist.pop_first() {
+                self.simplify(n);
+            }
+            else if let Some(m) = self.worklist_moves.pop_first() {
+                self.coalesce(m);
+            }
+            else if let Some(n) = self.freeze_worklist.pop_first() {
+                self.freeze(n);
+            }
+            else if !self.spill_worklist.is_empty() {
+                self.select_spill();
+            }
+            else {
+                break;
+            }
+        }
+        self.assign_colors();
+    }
+
+    fn adjacent(&self, n: usize) -> Vec<usize> {
+        self.adj_list[n].iter().copied()
+            .filter(|&m| !matches!(self.state[m], NodeState::Selected | NodeState::Coalesced))
+            .collect()
+    }
+
+    fn node_moves(&self, n: usize) -> Vec<usize> {
+        self.move_list[n].iter().copied()
+            .filter(|&m| matches!(self.move_state[m], MoveState::Active | MoveState::Worklist))
+            .collect()
+    }
+
+    fn is_move_related(&self, n: usize) -> bool {
+        !self.node_moves(n).is_empty()
+    }
+
+    fn simplify(&mut self, n: usize) {
+        self.state[n] = NodeState::Selected;
+        self.select_stack.push(n);
+        for m in self.adjacent(n) {
+            self.decrement_degree(m);
+        }
+    }
+
+    fn decrement_degree(&mut self, m: usize) {
+        if self.is_precolored(m) {
+            return;
+        }
+        let degree = self.degree[m];
+        self.degree[m] -= 1;
+        if degree == self.k {
+            let mut nodes = self.adjacent(m);
+            nodes.push(m);
+            self.enable_moves(&nodes);
+            self.spill_worklist.remove(&m);
+            if self.is_move_related(m) {
+                self.freeze_worklist.insert(m);
+                self.state[m] = NodeState::Freeze;
+            }
+            else {
+                self.simplify_worklist.insert(m);
+                self.state[m] = NodeState::Simplify;
+            }
+        }
+    }
+
+    fn enable_moves(&mut self, nodes: &[usize]) {
+        for &n in nodes {
+            for m in self.node_moves(n) {
+                if self.move_state[m] == MoveState::Active {
+                    self.move_state[m] = MoveState::Worklist;
+                    self.worklist_moves.insert(m);
+                }
+            }
+        }
+    }
+
+    fn get_alias(&self, n: usize) -> usize {
+        match self.state[n] {
+            NodeState::Coalesced => self.get_alias(self.alias[n]),
+            _ => n,
+        }
+    }
+
+    fn add_worklist(&mut self, u: usize) {
+        if !self.is_precolored(u) && !self.is_move_related(u) && self.degree[u] < self.k {
+            self.freeze_worklist.remove(&u);
+            self.simplify_worklist.insert(u);
+            self.state[u] = NodeState::Simplify;
+        }
+    }
+
+    // George: every neighbour of the value is harmless to the register `r`
+    fn george(&self, t: usize, r: usize) -> bool {
+        self.degree[t] < self.k || self.is_precolored(t) || self.adj_set.contains(&(t, r))
+    }
+
+    // Briggs: fewer than k significant neighbours after merging
+    fn briggs(&self, u: usize, v: usize) -> bool {
+        let mut nodes = self.adjacent(u);
+        nodes.extend(self.adjacent(v));
+        nodes.sort_unstable();
+        nodes.dedup();
+        nodes.iter().filter(|&&n| self.degree[n] >= self.k).count() < self.k
+    }
+
+    fn coalesce(&mut self, m: usize) {
+        let (x, y) = self.moves[m];
+        let (x, y) = (self.get_alias(x), self.get_alias(y));
+        let (u, v) = if self.is_precolored(y) { (y, x) } else { (x, y) };
+        if u == v {
+            self.move_state[m] = MoveState::Coalesced;
+            self.add_worklist(u);
+        }
+        else if self.is_precolored(v) || self.adj_set.contains(&(u, v)) {
+            self.move_state[m] = MoveState::Constrained;
+            self.add_worklist(u);
+            self.add_worklist(v);
+        }
+        else if (self.is_precolored(u) && self.adjacent(v).iter().all(|&t| self.george(t, u)))
+            || (!self.is_precolored(u) && self.briggs(u, v)) {
+            self.move_state[m] = MoveState::Coalesced;
+            self.combine(u, v);
+            self.add_worklist(u);
+        }
+        else {
+            self.move_state[m] = MoveState::Active;
+        }
+    }
+
+    fn combine(&mut self, u: usize, v: usize) {
+        if !self.freeze_worklist.remove(&v) {
+            self.spill_worklist.remove(&v);
+        }
+        self.state[v] = NodeState::Coalesced;
+        self.alias[v] = u;
+        let moves = self.move_list[v].clone();
+        self.move_list[u].extend(moves);
+        self.cost[u] += self.cost[v];
+        self.enable_moves(&[v]);
+        for t in self.adjacent(v) {
+            self.add_edge(t, u);
+            self.decrement_degree(t);
+        }
+        if self.degree[u] >= self.k && self.freeze_worklist.remove(&u) {
+            self.spill_worklist.insert(u);
+            self.state[u] = NodeState::Spill;
+        }
+    }
+
+    fn freeze(&mut self, u: usize) {
+        self.simplify_worklist.insert(u);
+        self.state[u] = NodeState::Simplify;
+        self.freeze_moves(u);
+    }
+
+    fn freeze_moves(&mut self, u: usize) {
+        for m in self.node_moves(u) {
+            let (x, y) = self.moves[m];
+            let v = if self.get_alias(y) == self.get_alias(u) { self.get_alias(x) } else { self.get_alias(y) };
+            if self.move_state[m] == MoveState::Worklist {
+                self.worklist_moves.remove(&m);
+            }
+            self.move_state[m] = MoveState::Frozen;
+            if self.state[v] == NodeState::Freeze && !self.is_move_related(v) && self.degree[v] < self.k {
+                self.freeze_worklist.remove(&v);
+                self.simplify_worklist.insert(v);
+                self.state[v] = NodeState::Simplify;
+            }
+        }
+    }
+
+    fn select_spill(&mut self) {
+        let priority = |n: usize| self.cost[n] / self.degree[n] as f64;
+        let m = *self.spill_worklist.iter().min_by(|&&a, &&b| priority(a).total_cmp(&priority(b))).unwrap();
+        self.spill_worklist.remove(&m);
+        self.simplify_worklist.insert(m);
+        self.state[m] = NodeState::Simplify;
+        self.freeze_moves(m);
+    }
+
+    // Colors popped in order of preference; a node left without one is spilled
+    fn assign_colors(&mut self) {
+        while let Some(n) = self.select_stack.pop() {
+            let mut ok_colors = self.available.clone();
+            for &w in &self.adj_list[n] {
+                let w = self.get_alias(w);
+                if let Some(color) = self.color[w] {
+                    ok_colors[color] = false;
+                }
+            }
+            self.color[n] = ok_colors.iter().position(|&ok| ok);
+        }
+        for n in REG_NUM..self.state.len() {
+            if self.state[n] == NodeState::Coalesced {
+                self.color[n] = self.color[self.get_alias(n)];
+            }
+        }
+    }
+}
+
+// Targets of a terminator with the arguments passed along each edge
+fn edges(data: &FunctionData, term: Value) -> Vec<(BasicBlock, Vec<Value>)> {
+    match data.dfg().value(term).kind() {
+        ValueKind::Jump(jump) => vec![(jump.target(), jump.args().to_vec())],
+        ValueKind::Branch(br) => vec![(br.true_bb(), br.true_args().to_vec()), (br.false_bb(), br.false_args().to_vec())],
+        _ => Vec::new(),
+    }
+}
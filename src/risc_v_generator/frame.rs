@@ -0,0 +1,126 @@
+/*
+    Frame Layout:
+        sp + size   | the caller's stack arguments
+                    | ra, if the function calls
+                    | frame objects: allocs, spill slots, saved s registers
+        sp          | stack arguments of the largest call
+    The size is rounded up to 16. The prologue and epilogue are added once
+    the s registers in use are known, frame addresses become offsets from
+    sp, and an offset or immediate out of the 12-bit range is built in a
+    scratch register free at that point.
+*/
+use super::machine::*;
+
+pub fn lay_out_frame(func: &mut MachineFunction) {
+    let mut saved = Vec::new();
+    for inst in func.blocks.iter().flat_map(|block| &block.insts) {
+        for reg in inst.defs() {
+            if let Reg::Phys(name) = reg {
+                if name.starts_with('s') && name != "sp" && !saved.iter().any(|&(saved, _)| saved == reg) {
+                    saved.push((reg, 0));
+                }
+            }
+        }
+    }
+    for (_, index) in &mut saved {
+        *index = func.frame.new_object(4);
+    }
+    let calls = func.blocks.iter().flat_map(|block| &block.insts).any(|inst| matches!(inst, MachineInst::Call{ .. }));
+
+    let mut offsets = Vec::new();
+    let mut offset = func.frame.outgoing;
+    for &object in &func.frame.objects {
+        offsets.push(offset as i32);
+        offset += object;
+    }
+    let ra_size = if calls {4} else {0};
+    let size = ((offset + ra_size).div_ceil(16) * 16) as i32;
+
+    if size != 0 {
+        let mut prologue = vec![MachineInst::OpImm{ op: "add", rd: SP, rs1: SP, imm: -size }];
+        let mut epilogue = Vec::new();
+        if calls {
+            prologue.push(MachineInst::Sw{ rs: RA, base: Base::Reg(SP), offset: size - 4 });
+        }
+        for &(reg, index) in &saved {
+            prologue.push(MachineInst::Sw{ rs: reg, base: Base::Frame(index), offset: 0 });
+            epilogue.push(MachineInst::Lw{ rd: reg, base: Base::Frame(index), offset: 0 });
+        }
+        if calls {
+            epilogue.push(MachineInst::Lw{ rd: RA, base: Base::Reg(SP), offset: size - 4 });
+        }
+        epilogue.push(MachineInst::OpImm{ op: "add", rd: SP, rs1: SP, imm: size });
+
+        func.blocks[0].insts.splice(0..0, prologue);
+        for block in &mut func.blocks {
+            let insts = std::mem::take(&mut block.insts);
+            for inst in insts {
+                if inst == MachineInst::Ret {
+                    block.insts.extend(epilogue.iter().cloned());
+                }
+                block.insts.push(inst);
+            }
+        }
+    }
+
+    let resolve = |base: Base, offset: i32| match base {
+        Base::Reg(reg) => (reg, offset),
+        Base::Frame(index) => (SP, offsets[index] + offset),
+        Base::Incoming => (SP, size + offset),
+    };
+    let fits = |imm: i32| (-2048..=2047).contains(&imm);
+    for block in &mut func.blocks {
+        let live = scratch_live_after(block);
+        let insts = std::mem::take(&mut block.insts);
+        for (inst, live) in insts.into_iter().zip(live) {
+            let free = free_scratch(&inst, &live);
+            match inst {
+                MachineInst::Lw{ rd, base, offset } => {
+                    let (base, offset) = resolve(base, offset);
+                    if fits(offset) {
+                        block.insts.push(MachineInst::Lw{ rd, base: Base::Reg(base), offset });
+                    }
+                    else {
+                        // The loaded register holds the address first, unless it is the base
+                        let addr = if rd != base { rd } else { free[0] };
+                        block.insts.push(MachineInst::Li{ rd: addr, imm: offset });
+                        block.insts.push(MachineInst::Op{ op: "add", rd: addr, rs1: base, rs2: addr });
+                        block.insts.push(MachineInst::Lw{ rd, base: Base::Reg(addr), offset: 0 });
+                    }
+                },
+                MachineInst::Sw{ rs, base, offset } => {
+                    let (base, offset) = resolve(base, offset);
+                    if fits(offset) {
+                        block.insts.push(MachineInst::Sw{ rs, base: Base::Reg(base), offset });
+                    }
+                    else {
+                        let addr = free[0];
+                        block.insts.push(MachineInst::Li{ rd: addr, imm: offset });
+                        block.insts.push(MachineInst::Op{ op: "add", rd: addr, rs1: base, rs2: addr });
+                        block.insts.push(MachineInst::Sw{ rs, base: Base::Reg(addr), offset: 0 });
+                    }
+                },
+                MachineInst::Addr{ rd, base } => {
+                    let (base, offset) = resolve(base, 0);
+                    block.insts.push(MachineInst::OpImm{ op: "add", rd, rs1: base, imm: offset });
+                    if !fits(offset) {
+                        let inst = block.insts.pop().unwrap();
+                        legalize_imm(&mut block.insts, inst, rd);
+                    }
+                },
+                MachineInst::OpImm{ imm, .. } if !fits(imm) => {
+                    legalize_imm(&mut block.insts, inst, free[0]);
+                },
+                inst => block.insts.push(inst),
+            }
+        }
+    }
+}
+
+// An immediate out of range is loaded into `temp` for the register form
+fn legalize_imm(out: &mut Vec<MachineInst>, inst: MachineInst, temp: Reg) {
+    if let MachineInst::OpImm{ op, rd, rs1, imm } = inst {
+        out.push(MachineInst::Li{ rd: temp, imm });
+        out.push(MachineInst::Op{ op, rd, rs1, rs2: temp });
+    }
+}
@@ -0,0 +1,119 @@
+/*
+    Liveness of the values the code generator keeps:
+        Instructions are numbered in layout order after position 0, where
+        the function parameters are copied out of a0-a7. Block parameters
+        are written by the jumps to their block, so they are defined at
+        every predecessor's terminator, alongside the arguments it reads.
+    A live interval is the hull of the positions where a value is live,
+    defined or used, so a value live around a loop covers all of it.
+*/
+use koopa::ir::{BasicBlock, FunctionData, Value, ValueKind};
+use std::collections::{HashMap, HashSet};
+
+pub struct LiveIntervals {
+    pub intervals: HashMap<Value, (usize, usize)>, // Inclusive start and end
+    pub calls: Vec<usize>,
+}
+
+impl LiveIntervals {
+    pub fn new(data: &FunctionData, kept: &HashSet<Value>) -> Self {
+        let live_out = live_out(data, kept);
+        let mut intervals = HashMap::new();
+        let mut extend = |value: Value, pos: usize| {
+            let (start, end) = intervals.entry(value).or_insert((pos, pos));
+            *start = pos.min(*start);
+            *end = pos.max(*end);
+        };
+        for &param in data.params() {
+            if kept.contains(&param) {
+                extend(param, 0);
+            }
+        }
+
+        let mut calls = Vec::new();
+        let mut pos = 0;
+        for (&bb, node) in data.layout().bbs() {
+            let start = pos + 1;
+            pos += node.insts().len();
+            let mut live = live_out[&bb].clone();
+            for &value in &live {
+                extend(value, pos);
+            }
+            let insts = node.insts().keys().copied().collect::<Vec<_>>();
+            for (index, &inst) in insts.iter().rev().enumerate() {
+                let at = pos - index;
+                if matches!(data.dfg().value(inst).kind(), ValueKind::Call(_)) {
+                    calls.push(at);
+                }
+                for value in defs(data, kept, inst) {
+                    extend(value, at);
+                    live.remove(&value);
+                }
+                for value in uses(data, kept, inst) {
+                    extend(value, at);
+                    live.insert(value);
+                }
+            }
+            for value in live {
+                extend(value, start);
+            }
+        }
+        return Self{ intervals, calls };
+    }
+}
+
+// Kept values written by `inst`: its result, or the parameters its jumps pass
+pub fn defs(data: &FunctionData, kept: &HashSet<Value>, inst: Value) -> Vec<Value> {
+    let targets = match data.dfg().value(inst).kind() {
+        ValueKind::Jump(jump) => vec![jump.target()],
+        ValueKind::Branch(br) => vec![br.true_bb(), br.false_bb()],
+        _ => return Some(inst).filter(|inst| kept.contains(inst)).into_iter().collect(),
+    };
+    targets.into_iter()
+        .flat_map(|bb| data.dfg().bb(bb).params().iter().copied())
+        .filter(|param| kept.contains(param))
+        .collect()
+}
+
+pub fn uses(data: &FunctionData, kept: &HashSet<Value>, inst: Value) -> Vec<Value> {
+    data.dfg().value(inst).kind().value_uses().filter(|value| kept.contains(value)).collect()
+}
+
+// Values live at the end of each block, found backward until nothing changes
+pub fn live_out(data: &FunctionData, kept: &HashSet<Value>) -> HashMap<BasicBlock, HashSet<Value>> {
+    let bbs = data.layout().bbs().keys().copied().collect::<Vec<_>>();
+    let mut live_in = bbs.iter().map(|&bb| (bb, HashSet::new())).collect::<HashMap<_, HashSet<Value>>>();
+    let mut live_out = live_in.clone();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &bb in bbs.iter().rev() {
+            let node = data.layout().bbs().node(&bb).unwrap();
+            let term = *node.insts().back_key().unwrap();
+            let mut live = successors(data, term).into_iter()
+                .flat_map(|succ| live_in[&succ].iter().copied())
+                .collect::<HashSet<_>>();
+            live_out.insert(bb, live.clone());
+            let insts = node.insts().keys().copied().collect::<Vec<_>>();
+            for &inst in insts.iter().rev() {
+                for value in defs(data, kept, inst) {
+                    live.remove(&value);
+                }
+                live.extend(uses(data, kept, inst));
+            }
+            if live != live_in[&bb] {
+                live_in.insert(bb, live);
+                changed = true;
+            }
+        }
+    }
+    return live_out;
+}
+
+fn successors(data: &FunctionData, term: Value) -> Vec<BasicBlock> {
+    match data.dfg().value(term).kind() {
+        ValueKind::Jump(jump) => vec![jump.target()],
+        ValueKind::Branch(br) => vec![br.true_bb(), br.false_bb()],
+        _ => Vec::new(),
+    }
+}
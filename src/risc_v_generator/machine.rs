@@ -0,0 +1,283 @@
+/*
+    Machine IR:
+        RISC-V instructions whose register operands are physical, or
+        virtual ones standing for the Koopa values kept in registers,
+        grouped into labelled blocks. Stack memory is addressed through
+        frame objects until the frame is laid out.
+    Instruction selection builds a function through Writer, register
+    allocation replaces its virtual registers, the frame layout adds the
+    prologue and epilogue, the peephole optimizer cleans it up, and the
+    printer writes it out as assembly.
+*/
+use koopa::ir::Value;
+use std::fmt;
+use std::io::{Result, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Reg {
+    Phys(&'static str),
+    Virt(Value), // A kept Koopa value, until register allocation
+}
+
+pub const ZERO: Reg = Reg::Phys("x0");
+pub const SP: Reg = Reg::Phys("sp");
+pub const RA: Reg = Reg::Phys("ra");
+pub const A0: Reg = Reg::Phys("a0");
+pub const T0: Reg = Reg::Phys("t0");
+pub const T1: Reg = Reg::Phys("t1");
+pub const T2: Reg = Reg::Phys("t2");
+pub const T3: Reg = Reg::Phys("t3");
+
+// Registers instruction selection computes in, never given to a value
+pub const SCRATCH: [Reg; 4] = [T0, T1, T2, T3];
+
+const ARGS: [&str; 8] = ["a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7"];
+const CALLER_SAVED: [&str; 16] = [
+    "t0", "t1", "t2", "t3", "t4", "t5", "t6",
+    "a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7", "ra",
+];
+
+impl Reg {
+    pub fn arg(index: usize) -> Self {
+        Reg::Phys(ARGS[index])
+    }
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Reg::Phys(name) => write!(f, "{}", name),
+            Reg::Virt(value) => write!(f, "{:?}", value),
+        }
+    }
+}
+
+pub type FrameIndex = usize;
+
+// What a load or store offset is relative to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Base {
+    Reg(Reg),
+    Frame(FrameIndex),
+    Incoming, // The caller's stack arguments, right above the frame
+}
+
+impl From<Reg> for Base {
+    fn from(reg: Reg) -> Self {
+        Base::Reg(reg)
+    }
+}
+
+impl fmt::Display for Base {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Base::Reg(reg) => write!(f, "{}", reg),
+            Base::Frame(index) => write!(f, "frame{}", index),
+            Base::Incoming => write!(f, "incoming"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Reg(Reg),
+    Imm(i32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MachineInst {
+    Li{ rd: Reg, imm: i32 },
+    La{ rd: Reg, label: String },
+    Mv{ rd: Reg, rs: Reg },
+    Op{ op: &'static str, rd: Reg, rs1: Reg, rs2: Reg },
+    OpImm{ op: &'static str, rd: Reg, rs1: Reg, imm: i32 }, // `op` without its `i`
+    Op1{ op: &'static str, rd: Reg, rs: Reg },              // seqz, snez
+    Lw{ rd: Reg, base: Base, offset: i32 },
+    Sw{ rs: Reg, base: Base, offset: i32 },
+    Addr{ rd: Reg, base: Base },                            // Address of a frame object
+    Branch{ op: &'static str, rs: Reg, label: String },     // beqz, bnez
+    J{ label: String },
+    Call{ func: String },
+    Ret,
+    Copy{ moves: Vec<(Reg, Operand)> },                     // Parallel copy, until register allocation
+}
+
+impl MachineInst {
+    pub fn defs(&self) -> Vec<Reg> {
+        match self {
+            Self::Li{ rd, .. } | Self::La{ rd, .. } | Self::Mv{ rd, .. } | Self::Op{ rd, .. }
+            | Self::OpImm{ rd, .. } | Self::Op1{ rd, .. } | Self::Lw{ rd, .. } | Self::Addr{ rd, .. } => vec![*rd],
+            Self::Call{ .. } => CALLER_SAVED.iter().map(|&name| Reg::Phys(name)).collect(),
+            Self::Copy{ moves } => moves.iter().map(|&(dst, _)| dst).collect(),
+            Self::Sw{ .. } | Self::Branch{ .. } | Self::J{ .. } | Self::Ret => Vec::new(),
+        }
+    }
+
+    pub fn uses(&self) -> Vec<Reg> {
+        let base = |base: &Base| match base {
+            Base::Reg(reg) => Some(*reg),
+            _ => None,
+        };
+        match self {
+            Self::Li{ .. } | Self::La{ .. } | Self::J{ .. } => Vec::new(),
+            Self::Mv{ rs, .. } | Self::Op1{ rs, .. } | Self::Branch{ rs, .. } => vec![*rs],
+            Self::Op{ rs1, rs2, .. } => vec![*rs1, *rs2],
+            Self::OpImm{ rs1, .. } => vec![*rs1],
+            Self::Lw{ base: b, .. } | Self::Addr{ base: b, .. } => base(b).into_iter().collect(),
+            Self::Sw{ rs, base: b, .. } => Some(*rs).into_iter().chain(base(b)).collect(),
+            // The arguments of a call are not known here, so it reads all of a0-a7
+            Self::Call{ .. } => ARGS.iter().map(|&name| Reg::Phys(name)).collect(),
+            Self::Ret => vec![A0],
+            Self::Copy{ moves } => moves.iter().filter_map(|&(_, src)| match src {
+                Operand::Reg(reg) => Some(reg),
+                Operand::Imm(_) => None,
+            }).collect(),
+        }
+    }
+
+    // Replaces every register operand, read or written
+    pub fn map_regs(&mut self, mut f: impl FnMut(Reg) -> Reg) {
+        match self {
+            Self::Li{ rd, .. } | Self::La{ rd, .. } => *rd = f(*rd),
+            Self::Mv{ rd, rs } | Self::Op1{ rd, rs, .. } => {
+                *rd = f(*rd);
+                *rs = f(*rs);
+            },
+            Self::Op{ rd, rs1, rs2, .. } => {
+                *rd = f(*rd);
+                *rs1 = f(*rs1);
+                *rs2 = f(*rs2);
+            },
+            Self::OpImm{ rd, rs1, .. } => {
+                *rd = f(*rd);
+                *rs1 = f(*rs1);
+            },
+            Self::Lw{ rd, base: b, .. } | Self::Addr{ rd, base: b } => {
+                *rd = f(*rd);
+                if let Base::Reg(reg) = b {
+                    *reg = f(*reg);
+                }
+            },
+            Self::Sw{ rs, base: b, .. } => {
+                *rs = f(*rs);
+                if let Base::Reg(reg) = b {
+                    *reg = f(*reg);
+                }
+            },
+            Self::Branch{ rs, .. } => *rs = f(*rs),
+            Self::Copy{ moves } => {
+                for (dst, src) in moves {
+                    *dst = f(*dst);
+                    if let Operand::Reg(reg) = src {
+                        *reg = f(*reg);
+                    }
+                }
+            },
+            Self::J{ .. } | Self::Call{ .. } | Self::Ret => {},
+        }
+    }
+}
+
+impl fmt::Display for MachineInst {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Li{ rd, imm } => write!(f, "li {}, {}", rd, imm),
+            Self::La{ rd, label } => write!(f, "la {}, {}", rd, label),
+            Self::Mv{ rd, rs } => write!(f, "mv {}, {}", rd, rs),
+            Self::Op{ op, rd, rs1, rs2 } => write!(f, "{} {}, {}, {}", op, rd, rs1, rs2),
+            Self::OpImm{ op, rd, rs1, imm } => write!(f, "{}i {}, {}, {}", op, rd, rs1, imm),
+            Self::Op1{ op, rd, rs } => write!(f, "{} {}, {}", op, rd, rs),
+            Self::Lw{ rd, base, offset } => write!(f, "lw {}, {}({})", rd, offset, base),
+            Self::Sw{ rs, base, offset } => write!(f, "sw {}, {}({})", rs, offset, base),
+            Self::Addr{ rd, base } => write!(f, "addr {}, {}", rd, base),
+            Self::Branch{ op, rs, label } => write!(f, "{} {}, {}", op, rs, label),
+            Self::J{ label } => write!(f, "j {}", label),
+            Self::Call{ func } => write!(f, "call {}", func),
+            Self::Ret => write!(f, "ret"),
+            Self::Copy{ moves } => {
+                write!(f, "copy")?;
+                for (dst, src) in moves {
+                    match src {
+                        Operand::Reg(reg) => write!(f, " {} <- {};", dst, reg)?,
+                        Operand::Imm(imm) => write!(f, " {} <- {};", dst, imm)?,
+                    }
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+pub struct MachineBlock {
+    pub label: String,
+    pub insts: Vec<MachineInst>,
+}
+
+// Stack memory of a function, apart from its saved ra
+#[derive(Default)]
+pub struct Frame {
+    pub objects: Vec<usize>, // Sizes in bytes
+    pub outgoing: usize,     // Bytes of arguments past a7 for the largest call
+}
+
+impl Frame {
+    pub fn new_object(&mut self, size: usize) -> FrameIndex {
+        self.objects.push(size);
+        return self.objects.len() - 1;
+    }
+
+    pub fn reserve_args(&mut self, arg_num: usize) {
+        self.outgoing = self.outgoing.max(arg_num.saturating_sub(8) * 4);
+    }
+}
+
+pub struct MachineFunction {
+    pub name: String,
+    pub blocks: Vec<MachineBlock>,
+    pub frame: Frame,
+}
+
+impl MachineFunction {
+    pub fn new(name: &str) -> Self {
+        Self{ name: name.to_string(), blocks: Vec::new(), frame: Frame::default() }
+    }
+
+    pub fn print<W: Write>(&self, f: &mut W) -> Result<()> {
+        writeln!(f, "  .globl {}", self.name)?;
+        writeln!(f, "{}:", self.name)?;
+        for block in &self.blocks {
+            writeln!(f, "{}:", block.label)?;
+            for inst in &block.insts {
+                writeln!(f, "  {}", inst)?;
+            }
+        }
+        writeln!(f)?;
+        return Ok(());
+    }
+}
+
+// Scratch registers live right after each instruction of `block`: their
+// values never outlive the block that computes them
+pub fn scratch_live_after(block: &MachineBlock) -> Vec<Vec<Reg>> {
+    let mut live = Vec::new();
+    let mut result = vec![Vec::new(); block.insts.len()];
+    for (index, inst) in block.insts.iter().enumerate().rev() {
+        result[index] = live.clone();
+        let defs = inst.defs();
+        live.retain(|reg| !defs.contains(reg));
+        for reg in inst.uses() {
+            if SCRATCH.contains(&reg) && !live.contains(&reg) {
+                live.push(reg);
+            }
+        }
+    }
+    return result;
+}
+
+// Scratch registers `inst` neither reads nor writes, and free after it
+pub fn free_scratch(inst: &MachineInst, live_after: &[Reg]) -> Vec<Reg> {
+    let (defs, uses) = (inst.defs(), inst.uses());
+    SCRATCH.iter().copied()
+        .filter(|reg| !live_after.contains(reg) && !defs.contains(reg) && !uses.contains(reg))
+        .collect()
+}
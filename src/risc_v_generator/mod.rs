@@ -2,19 +2,30 @@ pub mod code_generator;
 mod program_manager;
 mod asm_generator;
 mod asm_value;
+mod coloring;
+mod frame;
+mod liveness;
+mod machine;
+mod peephole;
 mod reg_manager;
 
 use koopa::ir::{Program, Type};
-use std::fs::File;
-use std::io::Result;
+use std::io::{Result, Write};
 use code_generator::AsmGenerator;
 use program_manager::ProgramManager;
 use asm_generator::Writer;
 
-pub fn generate_asm(program: &Program, path: &str) -> Result<()> {
-    let mut file = File::create(path)?;
-    let mut writer = Writer::new(&mut file);
-    let mut program_manager = ProgramManager::new(program);
+// Where the values of a function are kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegAlloc {
+    Stack,         // Every value in a stack slot
+    LinearScan,
+    GraphColoring, // Iterated register coalescing
+}
+
+pub fn generate_asm<W: Write>(program: &Program, regalloc: RegAlloc, out: &mut W) -> Result<()> {
+    let mut writer = Writer::new(out);
+    let mut program_manager = ProgramManager::new(program, regalloc);
     program.generate(&mut program_manager, &mut writer)?;
     return Ok(());
 }
\ No newline at end of file
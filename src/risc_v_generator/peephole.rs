@@ -0,0 +1,174 @@
+/*
+    Peephole Optimizer:
+        Runs on a function once its frame is laid out, one block at a time,
+        forward, knowing the constants held in registers and the stack words
+        registers still hold since they were stored or loaded:
+          - a move of a register to itself goes,
+          - a load of a word a register still holds becomes a move,
+          - a small constant operand becomes an immediate, and an
+            instruction on constants becomes a li.
+        A scratch register written but never read again then goes, and the
+        block repeats until nothing changes. Last, a jump to the block laid
+        out next goes.
+*/
+use super::machine::*;
+use std::collections::HashMap;
+
+pub fn peephole(func: &mut MachineFunction) {
+    for block in &mut func.blocks {
+        loop {
+            let before = block.insts.clone();
+            simplify(block);
+            remove_dead(block);
+            if block.insts == before {
+                break;
+            }
+        }
+    }
+    remove_fallthrough(&mut func.blocks);
+}
+
+fn simplify(block: &mut MachineBlock) {
+    let mut consts = HashMap::from([(ZERO, 0)]);
+    let mut words: Vec<(Reg, i32, Reg)> = Vec::new(); // Base, offset, the register holding the word there
+    let insts = std::mem::take(&mut block.insts);
+    for inst in insts {
+        let inst = match inst {
+            MachineInst::Mv{ rd, rs } if rd == rs => continue,
+            MachineInst::Lw{ rd, base: Base::Reg(base), offset } => {
+                match words.iter().find(|&&(b, o, _)| b == base && o == offset) {
+                    Some(&(_, _, rs)) if rs == rd => continue,
+                    Some(&(_, _, rs)) => MachineInst::Mv{ rd, rs },
+                    None => inst,
+                }
+            },
+            MachineInst::Op{ op, rd, rs1, rs2 } => fold_op(op, rd, rs1, rs2, &consts),
+            MachineInst::OpImm{ op, rd, rs1, imm } => {
+                match consts.get(&rs1).and_then(|&c| eval(op, c, imm)) {
+                    Some(imm) => MachineInst::Li{ rd, imm },
+                    None if op == "add" && imm == 0 => MachineInst::Mv{ rd, rs: rs1 },
+                    None => inst,
+                }
+            },
+            MachineInst::Op1{ op, rd, rs } => {
+                match consts.get(&rs) {
+                    Some(&c) if op == "seqz" => MachineInst::Li{ rd, imm: (c == 0) as i32 },
+                    Some(&c) if op == "snez" => MachineInst::Li{ rd, imm: (c != 0) as i32 },
+                    _ => inst,
+                }
+            },
+            inst => inst,
+        };
+        if let MachineInst::Mv{ rd, rs } = inst {
+            if rd == rs {
+                continue;
+            }
+        }
+
+        let defs = inst.defs();
+        let known = match &inst {
+            MachineInst::Li{ imm, .. } => Some(*imm),
+            MachineInst::Mv{ rs, .. } => consts.get(rs).copied(),
+            _ => None,
+        };
+        consts.retain(|reg, _| !defs.contains(reg));
+        words.retain(|(base, _, reg)| !defs.contains(base) && !defs.contains(reg));
+        if let Some(c) = known {
+            consts.insert(defs[0], c);
+        }
+        match inst {
+            // Any store may write a word another base reaches
+            MachineInst::Sw{ rs, base: Base::Reg(base), offset } => {
+                words.clear();
+                words.push((base, offset, rs));
+            },
+            MachineInst::Lw{ rd, base: Base::Reg(base), offset } if rd != base => words.push((base, offset, rd)),
+            MachineInst::Call{ .. } => words.clear(),
+            _ => {},
+        }
+        block.insts.push(inst);
+    }
+}
+
+fn fold_op(op: &'static str, rd: Reg, rs1: Reg, rs2: Reg, consts: &HashMap<Reg, i32>) -> MachineInst {
+    let commutative = matches!(op, "add" | "and" | "or" | "xor");
+    let imm_form = |rs1: Reg, c: i32| match immediate(op, c) {
+        Some(("add", 0)) => Some(MachineInst::Mv{ rd, rs: rs1 }),
+        Some((op, imm)) => Some(MachineInst::OpImm{ op, rd, rs1, imm }),
+        None => None,
+    };
+    let folded = match (consts.get(&rs1), consts.get(&rs2)) {
+        (Some(&a), Some(&b)) => eval(op, a, b).map(|imm| MachineInst::Li{ rd, imm }),
+        (_, Some(&b)) => imm_form(rs1, b),
+        (Some(&a), _) if commutative => imm_form(rs2, a),
+        _ => None,
+    };
+    folded.unwrap_or(MachineInst::Op{ op, rd, rs1, rs2 })
+}
+
+// The immediate form of `op` with `c` as its second operand, if it fits
+fn immediate(op: &'static str, c: i32) -> Option<(&'static str, i32)> {
+    let fits = |imm: i32| (-2048..=2047).contains(&imm);
+    match op {
+        "add" | "and" | "or" | "xor" | "slt" if fits(c) => Some((op, c)),
+        "sub" if c != i32::MIN && fits(-c) => Some(("add", -c)),
+        "sll" | "srl" | "sra" if (0..32).contains(&c) => Some((op, c)),
+        _ => None,
+    }
+}
+
+// `op` on constants, as the target computes it
+pub fn eval(op: &str, a: i32, b: i32) -> Option<i32> {
+    let value = match op {
+        "add" => a.wrapping_add(b),
+        "sub" => a.wrapping_sub(b),
+        "mul" => a.wrapping_mul(b),
+        "mulh" => ((a as i64 * b as i64) >> 32) as i32,
+        "and" => a & b,
+        "or" => a | b,
+        "xor" => a ^ b,
+        "sll" => a.wrapping_shl(b as u32),
+        "srl" => (a as u32).wrapping_shr(b as u32) as i32,
+        "sra" => a.wrapping_shr(b as u32),
+        "slt" => (a < b) as i32,
+        "sgt" => (a > b) as i32,
+        _ => return None,
+    };
+    return Some(value);
+}
+
+// Scratch registers never outlive their block, so one written and not read
+// before the next write or the end of the block holds nothing useful
+fn remove_dead(block: &mut MachineBlock) {
+    let live = scratch_live_after(block);
+    let insts = std::mem::take(&mut block.insts);
+    for (inst, live) in insts.into_iter().zip(live) {
+        let pure = matches!(inst, MachineInst::Li{ .. } | MachineInst::La{ .. } | MachineInst::Mv{ .. } | MachineInst::Op{ .. }
+            | MachineInst::OpImm{ .. } | MachineInst::Op1{ .. } | MachineInst::Lw{ .. } | MachineInst::Addr{ .. });
+        let dead = inst.defs().iter().all(|reg| SCRATCH.contains(reg) && !live.contains(reg));
+        if !(pure && dead) {
+            block.insts.push(inst);
+        }
+    }
+}
+
+// A jump to the next block goes, and a branch to it jumping elsewhere otherwise
+// becomes the opposite branch
+fn remove_fallthrough(blocks: &mut [MachineBlock]) {
+    for index in 0..blocks.len().saturating_sub(1) {
+        let next = blocks[index + 1].label.clone();
+        let insts = &mut blocks[index].insts;
+        match insts.as_slice() {
+            [.., MachineInst::J{ label }] if *label == next => {
+                insts.pop();
+            },
+            [.., MachineInst::Branch{ label, .. }, MachineInst::J{ .. }] if *label == next => {
+                let Some(MachineInst::J{ label: other }) = insts.pop() else { unreachable!() };
+                let Some(MachineInst::Branch{ op, rs, .. }) = insts.pop() else { unreachable!() };
+                let op = if op == "beqz" { "bnez" } else { "beqz" };
+                insts.push(MachineInst::Branch{ op, rs, label: other });
+            },
+            _ => {},
+        }
+    }
+}
@@ -1,11 +1,14 @@
 use koopa::ir::entities::*;
-use koopa::ir::{Function, BasicBlock, TypeKind, ValueKind};
+use koopa::ir::{Function, BasicBlock, TypeKind};
 use std::collections::HashMap;
 use std::cell::Cell;
+use super::RegAlloc;
+use super::machine::{FrameIndex, Reg};
 
 
 pub struct ProgramManager<'prog> {
     program: &'prog Program,
+    regalloc: RegAlloc,
     functions: HashMap<String, Function>,
     values_names: HashMap<Value, String>,
 
@@ -20,9 +23,10 @@ pub struct ProgramManager<'prog> {
 }
 
 impl<'prog> ProgramManager<'prog>{
-    pub fn new(program: &'prog Program) -> Self{
+    pub fn new(program: &'prog Program, regalloc: RegAlloc) -> Self{
         Self{
             program,
+            regalloc,
             functions: HashMap::new(),
             values_names: HashMap::new(),
             current_function: None,
@@ -36,6 +40,10 @@ impl<'prog> ProgramManager<'prog>{
         self.program
     }
     
+    pub fn regalloc(&self) -> RegAlloc{
+        self.regalloc
+    }
+
     pub fn value_name(&self, value: Value) -> &String{
         self.values_names.get(&value).unwrap()
     }
@@ -61,13 +69,10 @@ impl<'prog> ProgramManager<'prog>{
 
 pub struct FunctionInterface{
     func: Function,
-    max_arg_num: Option<usize>,
-    
-    allocated_stacksize: usize,
+
     allocated: HashMap<*const ValueData, ValueSlot>,
 
     bb_names: HashMap<BasicBlock, String>,
-    stackp_offset: Cell<Option<usize>>,
 
     
 }
@@ -82,11 +87,8 @@ impl FunctionInterface{
     pub fn new(func: Function) -> Self{
         Self{
             func,
-            max_arg_num: None,
-            allocated_stacksize: 0,
             allocated: HashMap::new(),
             bb_names: HashMap::new(),
-            stackp_offset: Cell::new(None),
         }
     }
 
@@ -94,40 +96,6 @@ impl FunctionInterface{
         self.func
     }
 
-    pub fn update_max_arg_num(&mut self, num: usize){
-        if self.max_arg_num.is_none() || num > self.max_arg_num.unwrap() {
-            self.max_arg_num = Some(num);
-        }
-    }
-
-    pub fn get_arg_num(&self) -> Option<usize>{
-        self.max_arg_num
-    }
-
-    pub fn neednt_restore_ra(&self) -> bool{
-        self.max_arg_num.is_none()
-    }
-
-    // get the final stack sp offset from the frame base
-    // there the frame end with max num of args of callees
-    pub fn sp_offset(&self) -> usize{
-        if let Some(offset) = self.stackp_offset.get(){
-            offset
-        }
-        else
-        {
-            let return_address_size = if self.neednt_restore_ra() {0} else {4};
-            let arg_size = match self.max_arg_num{
-                Some(num) => if num <= 8 {0} else {(num-8)*4},
-                None => 0,
-            };
-            let offset = return_address_size+arg_size+self.allocated_stacksize;
-            let final_offset = (offset+15) / 16 * 16;
-            self.stackp_offset.set(Some(final_offset));
-            return final_offset;
-        }
-    }
-
     pub fn set_bb_name(&mut self, bb: BasicBlock, name: &Option<String>){
         let id = Self::NEXT_TEMP_LABEL_ID.with(|id| {
             id.replace(id.get()+1)
@@ -140,60 +108,31 @@ impl FunctionInterface{
         
     }
 
+    // Label for code that is not a Koopa block, e.g. one edge of a branch with arguments
+    pub fn new_label(&self) -> String{
+        let id = Self::NEXT_TEMP_LABEL_ID.with(|id| {
+            id.replace(id.get()+1)
+        });
+        format!(".L{}", id)
+    }
+
     pub fn get_bb_name(&self, bb: BasicBlock) -> &str{
         self.bb_names.get(&bb).unwrap()
     }
 
-    pub fn alloc_new_slot(&mut self, value: &ValueData) {
-        match value.kind() {
-            ValueKind::Alloc(_) => {
-
-                let slot = ValueSlot::new_stackslot(self.allocated_stacksize, false);
-                self.allocated_stacksize += match value.ty().kind() {
-                    TypeKind::Pointer(unit) => unit.size(),
-                    _ => unreachable!(),
-                };
-                self.allocated.insert(value, slot);
-            }
-            _ => {
-                let is_ptr = match value.ty().kind() {
-                    TypeKind::Pointer(_) => true,
-                    _ => false,
-                };
-                let slot = ValueSlot::new_stackslot(self.allocated_stacksize, is_ptr);  // TODO: Register Manager to allocate register 如何管理局部变量的寄存器分配？
-                self.allocated_stacksize += value.ty().size();
-                self.allocated.insert(value, slot);
-            }
-        }
+    // An alloc lives in a frame object
+    pub fn alloc_frame_object(&mut self, value: &ValueData, index: FrameIndex) {
+        self.allocated.insert(value, ValueSlot::new_stackslot(index, false));
     }
 
+    // Any other value is a virtual register until register allocation
+    pub fn alloc_reg(&mut self, value: &ValueData, reg: Reg) {
+        let is_ptr = matches!(value.ty().kind(), TypeKind::Pointer(_));
+        self.allocated.insert(value, ValueSlot::new_regslot(reg, is_ptr));
+    }
 
-
-    // * stack_offset_resize - 用于将相对函数入口的栈偏移量转换为相对栈指针的栈偏移量，避免内部变量暴露
-    pub fn stack_offset_resize(&self, value: &ValueData) -> Option<ValueSlot> {
-
-
-        match self.allocated.get(&(value as *const ValueData)) {
-            Some(val) => {
-
-                if val.get_stackslot().is_none(){
-                    return None;
-                }                
-
-                let mut new_slot;
-                if self.neednt_restore_ra(){
-                    new_slot = Some(ValueSlot::new_stackslot(self.sp_offset() + val.stackslot_offset().unwrap() - self.allocated_stacksize , val.is_ptr()));
-                }
-                else {
-                    new_slot = Some(ValueSlot::new_stackslot(self.sp_offset() + val.stackslot_offset().unwrap() - 4 - self.allocated_stacksize, val.is_ptr()));
-                }
-                //dbg!(&val);
-                //dbg!(&new_slot);
-                new_slot
-            }
-            None => None,
-        }
-        
+    pub fn slot(&self, value: &ValueData) -> Option<ValueSlot> {
+        self.allocated.get(&(value as *const ValueData)).cloned()
     }
 }
 
@@ -218,15 +157,15 @@ impl ValueSlot{
         return self.ptr_flag;
     }
 
-    pub fn new_stackslot(offset:usize, is_ptr:bool) -> Self{
+    pub fn new_stackslot(index:FrameIndex, is_ptr:bool) -> Self{
         Self{
             reg: None,
-            stack: Some(StackSlot::new(offset)),
+            stack: Some(StackSlot::new(index)),
             ptr_flag: is_ptr,
         }
     }
 
-    pub fn new_regslot(reg:String, is_ptr:bool) -> Self{
+    pub fn new_regslot(reg:Reg, is_ptr:bool) -> Self{
         Self{
             reg: Some(RegSlot::new(reg)),
             stack: None,
@@ -234,12 +173,12 @@ impl ValueSlot{
         }
     }
 
-    pub fn add_regslot(&mut self, reg:String, is_ptr:bool){
+    pub fn add_regslot(&mut self, reg:Reg, is_ptr:bool){
         self.reg = Some(RegSlot::new(reg));
     }
 
-    pub fn add_stackslot(&mut self, offset:usize, is_ptr:bool){
-        self.stack = Some(StackSlot::new(offset));
+    pub fn add_stackslot(&mut self, index:FrameIndex, is_ptr:bool){
+        self.stack = Some(StackSlot::new(index));
     }
 
     pub fn get_regslot(&self) -> Option<&RegSlot>{
@@ -278,9 +217,9 @@ impl ValueSlot{
         }
     }
 
-    pub fn stackslot_offset(&self) -> Option<usize>{
+    pub fn frame_index(&self) -> Option<FrameIndex>{
         if let Some(stack) = &self.stack{
-            Some(stack.offset)
+            Some(stack.index)
         }
         else {
             None
@@ -289,17 +228,17 @@ impl ValueSlot{
 }
 #[derive(Clone, Debug)]
 pub struct RegSlot{
-    pub reg: String,
+    pub reg: Reg,
 }
 
 impl RegSlot{
-    fn new(reg:String) -> Self{
+    fn new(reg:Reg) -> Self{
         Self{
             reg,
         }
     }
 
-    fn map(self, f: impl FnOnce(String) -> String) -> Self{
+    fn map(self, f: impl FnOnce(Reg) -> Reg) -> Self{
         Self{
             reg: f(self.reg),
         }
@@ -307,19 +246,19 @@ impl RegSlot{
 }
 #[derive(Clone, Debug)]
 pub struct StackSlot{
-    pub offset:usize,
+    pub index:FrameIndex,
 }
 
 impl StackSlot{
-    fn new(offset:usize) -> Self{
+    fn new(index:FrameIndex) -> Self{
         Self{
-            offset,
+            index,
         }
     }
 
-    fn map(self, f: impl FnOnce(usize) -> usize) -> Self{
+    fn map(self, f: impl FnOnce(FrameIndex) -> FrameIndex) -> Self{
         Self{
-            offset: f(self.offset),
+            index: f(self.index),
         }
     }
 }
\ No newline at end of file
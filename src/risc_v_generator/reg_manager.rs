@@ -1,30 +1,233 @@
-use std::collections::HashMap;
-use std::cell::Cell;
-use koopa::ir::entities::*;
-use koopa::ir::{Function, BasicBlock, TypeKind, ValueKind};
+/*
+    Register Manager:
+        Linear-scan allocation over the live intervals, in order of their
+        start: an interval takes the first free register it may use, and
+        when there is none, the interval ending last among it and those
+        holding such a register goes to the stack.
+    t0-t3 stay scratch registers of the code generator. A value live
+    across a call needs an s register, which the function then saves.
+    Arguments are copied into a0-a7 right before a call, so a value
+    read by a call stays out of them, as does a function parameter, which
+    is copied out of them on entry, and an argument register still read
+    by a parameter that has no slot of its own.
+*/
+use koopa::ir::{FunctionData, Value};
+use std::collections::{HashMap, HashSet};
+use super::liveness::LiveIntervals;
+use super::machine::*;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RegClass {
+    Temp,  // t4-t6, clobbered by calls
+    Arg,   // a0-a7, clobbered by calls and written by their arguments
+    Saved, // s0-s11, kept across calls
+}
 
 pub struct Register {
-    name: String,
-    storing: Vec<*const ValueData>,
+    pub name: &'static str,
+    pub class: RegClass,
+}
+
+const fn reg(name: &'static str, class: RegClass) -> Register {
+    Register{ name, class }
+}
+
+// In order of preference: saved registers cost a store and a load in the function
+pub const REGISTERS: [Register; 23] = [
+    reg("t4", RegClass::Temp), reg("t5", RegClass::Temp), reg("t6", RegClass::Temp),
+    reg("a0", RegClass::Arg), reg("a1", RegClass::Arg), reg("a2", RegClass::Arg), reg("a3", RegClass::Arg),
+    reg("a4", RegClass::Arg), reg("a5", RegClass::Arg), reg("a6", RegClass::Arg), reg("a7", RegClass::Arg),
+    reg("s0", RegClass::Saved), reg("s1", RegClass::Saved), reg("s2", RegClass::Saved), reg("s3", RegClass::Saved),
+    reg("s4", RegClass::Saved), reg("s5", RegClass::Saved), reg("s6", RegClass::Saved), reg("s7", RegClass::Saved),
+    reg("s8", RegClass::Saved), reg("s9", RegClass::Saved), reg("s10", RegClass::Saved), reg("s11", RegClass::Saved),
+];
+
+// Values given a register; the others keep a stack slot
+pub struct Allocation {
+    pub regs: HashMap<Value, &'static str>,
+}
+
+// Where a virtual register ends up
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Loc {
+    Reg(Reg),
+    Slot(FrameIndex),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Src {
+    Loc(Loc),
+    Imm(i32),
+}
+
+impl Allocation {
+    // Rewrites the virtual registers of `func` into their registers. A value
+    // without one gets a spill slot, loaded into a free scratch register
+    // before each instruction reading it and stored after the one writing it.
+    // Parallel copies become moves, loads and stores once locations are known.
+    pub fn apply(&self, func: &mut MachineFunction) {
+        let MachineFunction{ blocks, frame, .. } = func;
+        let mut slots = HashMap::new();
+        let mut locate = |reg: Reg| match reg {
+            Reg::Virt(value) => match self.regs.get(&value) {
+                Some(&name) => Loc::Reg(Reg::Phys(name)),
+                None => Loc::Slot(*slots.entry(value).or_insert_with(|| frame.new_object(4))),
+            },
+            reg => Loc::Reg(reg),
+        };
+
+        for block in blocks {
+            let live = scratch_live_after(block);
+            let insts = std::mem::take(&mut block.insts);
+            for (mut inst, live) in insts.into_iter().zip(live) {
+                let mut free = free_scratch(&inst, &live).into_iter();
+                if let MachineInst::Copy{ moves } = inst {
+                    let moves = moves.into_iter().map(|(dst, src)| (locate(dst), match src {
+                        Operand::Reg(reg) => Src::Loc(locate(reg)),
+                        Operand::Imm(imm) => Src::Imm(imm),
+                    })).collect();
+                    let (temp, cycle) = (free.next().unwrap(), free.next().unwrap());
+                    sequentialize(&mut block.insts, moves, temp, cycle);
+                    continue;
+                }
+
+                let (defs, uses) = (inst.defs(), inst.uses());
+                let mut spilled = HashMap::new();
+                for reg in uses.iter().chain(&defs) {
+                    if let (Reg::Virt(_), Loc::Slot(slot)) = (reg, locate(*reg)) {
+                        spilled.entry(*reg).or_insert_with(|| (free.next().unwrap(), slot));
+                    }
+                }
+                for reg in &uses {
+                    if let Some(&(scratch, slot)) = spilled.get(reg) {
+                        block.insts.push(MachineInst::Lw{ rd: scratch, base: Base::Frame(slot), offset: 0 });
+                    }
+                }
+                inst.map_regs(|reg| match (spilled.get(&reg), locate(reg)) {
+                    (Some(&(scratch, _)), _) => scratch,
+                    (None, Loc::Reg(reg)) => reg,
+                    (None, Loc::Slot(_)) => unreachable!(),
+                });
+                block.insts.push(inst);
+                for reg in &defs {
+                    if let Some(&(scratch, slot)) = spilled.get(reg) {
+                        block.insts.push(MachineInst::Sw{ rs: scratch, base: Base::Frame(slot), offset: 0 });
+                    }
+                }
+            }
+        }
+    }
 }
 
-impl Register {
-    pub fn new(name: String) -> Self {
-        Self {
-            name,
-            storing: Vec::new(),
+// A location is written once no pending move still reads it, and a cycle is
+// broken by saving one location in `cycle`; `temp` carries a value between
+// two slots or a constant into one
+fn sequentialize(out: &mut Vec<MachineInst>, moves: Vec<(Loc, Src)>, temp: Reg, cycle: Reg) {
+    let mut pending = moves.into_iter().filter(|&(dst, src)| src != Src::Loc(dst)).collect::<Vec<_>>();
+    while !pending.is_empty() {
+        match pending.iter().position(|&(dst, _)| !pending.iter().any(|&(_, src)| src == Src::Loc(dst))) {
+            Some(index) => {
+                let (dst, src) = pending.remove(index);
+                emit_move(out, dst, src, temp);
+            },
+            None => {
+                let saved = pending[0].0;
+                emit_move(out, Loc::Reg(cycle), Src::Loc(saved), temp);
+                for (_, src) in &mut pending {
+                    if *src == Src::Loc(saved) {
+                        *src = Src::Loc(Loc::Reg(cycle));
+                    }
+                }
+            },
         }
     }
+}
 
-    pub fn store(&mut self, value: *const ValueData) {
-        self.storing.push(value);
+fn emit_move(out: &mut Vec<MachineInst>, dst: Loc, src: Src, temp: Reg) {
+    match (dst, src) {
+        (Loc::Reg(rd), Src::Loc(Loc::Reg(rs))) => out.push(MachineInst::Mv{ rd, rs }),
+        (Loc::Reg(rd), Src::Loc(Loc::Slot(slot))) => out.push(MachineInst::Lw{ rd, base: Base::Frame(slot), offset: 0 }),
+        (Loc::Reg(rd), Src::Imm(imm)) => out.push(MachineInst::Li{ rd, imm }),
+        (Loc::Slot(slot), src) => {
+            let rs = match src {
+                Src::Loc(Loc::Reg(rs)) => rs,
+                _ => {
+                    emit_move(out, Loc::Reg(temp), src, temp);
+                    temp
+                },
+            };
+            out.push(MachineInst::Sw{ rs, base: Base::Frame(slot), offset: 0 });
+        },
     }
+}
 
-    pub fn clear(&mut self) {
-        self.storing.clear();
+// Which registers may hold a value, given its interval
+pub struct Constraints<'a> {
+    intervals: &'a LiveIntervals,
+    params: HashSet<Value>,
+    reserved: HashSet<&'static str>,
+}
+
+impl<'a> Constraints<'a> {
+    // A function parameter outside `kept` is read from its argument register wherever it is used
+    pub fn new(data: &FunctionData, kept: &HashSet<Value>, intervals: &'a LiveIntervals) -> Self {
+        let params = data.params().iter().copied().collect::<HashSet<_>>();
+        let reserved = data.params().iter().enumerate()
+            .filter(|(index, param)| *index < 8 && !kept.contains(param))
+            .map(|(index, _)| REGISTERS[3 + index].name)
+            .collect();
+        Self{ intervals, params, reserved }
     }
 
-    pub fn delete(&mut self, value: *const ValueData) {
-        self.storing.retain(|&x| x != value);
+    pub fn allows(&self, value: Value, reg: &Register) -> bool {
+        let (start, end) = self.intervals.intervals[&value];
+        match reg.class {
+            RegClass::Temp => !self.intervals.calls.iter().any(|&call| start < call && call < end),
+            RegClass::Arg => {
+                !self.params.contains(&value) && !self.reserved.contains(reg.name)
+                    && !self.intervals.calls.iter().any(|&call| start < call && call <= end)
+            },
+            RegClass::Saved => true,
+        }
     }
-}
\ No newline at end of file
+}
+
+// `kept` lists the values in layout order, which breaks ties between intervals
+pub fn linear_scan(data: &FunctionData, kept: &[Value]) -> Allocation {
+    let kept_set = kept.iter().copied().collect::<HashSet<_>>();
+    let intervals = LiveIntervals::new(data, &kept_set);
+    let constraints = Constraints::new(data, &kept_set, &intervals);
+    let mut order = kept.iter().copied().filter(|value| intervals.intervals.contains_key(value)).collect::<Vec<_>>();
+    order.sort_by_key(|value| intervals.intervals[value].0);
+
+    let mut regs = HashMap::new();
+    let mut active: Vec<(Value, usize, usize)> = Vec::new(); // Value, end, register index
+    let mut free = [true; REGISTERS.len()];
+    for value in order {
+        let (start, end) = intervals.intervals[&value];
+        active.retain(|&(_, active_end, index)| {
+            free[index] |= active_end < start;
+            active_end >= start
+        });
+
+        let choice = (0..REGISTERS.len()).find(|&index| free[index] && constraints.allows(value, &REGISTERS[index]));
+        if let Some(index) = choice {
+            free[index] = false;
+            regs.insert(value, REGISTERS[index].name);
+            active.push((value, end, index));
+            continue;
+        }
+        // Spill the interval ending last, which frees its register for longest
+        let victim = active.iter().enumerate()
+            .filter(|(_, (_, _, index))| constraints.allows(value, &REGISTERS[*index]))
+            .max_by_key(|(_, (_, victim_end, _))| *victim_end);
+        if let Some((position, &(victim, victim_end, index))) = victim {
+            if victim_end > end {
+                regs.remove(&victim);
+                regs.insert(value, REGISTERS[index].name);
+                active[position] = (value, end, index);
+            }
+        }
+    }
+    return Allocation{ regs };
+}
@@ -0,0 +1,563 @@
+use crate::ast::ast_def::*;
+use crate::koopa_generator::{CResult, CompileError};
+use super::const_evaluator::*;
+use super::symbol_table::*;
+use super::CompileWarning;
+
+pub trait SemanticCheck {
+    type Out;
+    fn check(&mut self, checker: &mut Checker) -> CResult<Self::Out>;
+}
+
+// Report an error whose location is known, the expression goes on as SemType::Error
+fn poison(checker: &mut Checker, err: CompileError) -> CResult<SemType> {
+    checker.report(err);
+    return Ok(SemType::Error);
+}
+
+impl CompileInit {
+    fn declare_lib_func(&self, checker: &mut Checker) {
+        let mut create_lib_func = |name, params, ret| {
+            let _ = checker.declare_func(name, FuncSig{ ret, params }, Span::default());
+        };
+        create_lib_func("getint", Vec::new(), SemType::Int);
+        create_lib_func("getch", Vec::new(), SemType::Int);
+        create_lib_func("getarray", vec![SemType::Ptr(Vec::new())], SemType::Int);
+        create_lib_func("putint", vec![SemType::Int], SemType::Void);
+        create_lib_func("putch", vec![SemType::Int], SemType::Void);
+        create_lib_func("putarray", vec![SemType::Int, SemType::Ptr(Vec::new())], SemType::Void);
+        create_lib_func("starttime", Vec::new(), SemType::Void);
+        create_lib_func("stoptime", Vec::new(), SemType::Void);
+    }
+}
+
+impl SemanticCheck for CompileInit {
+    type Out = ();
+    fn check(&mut self, checker: &mut Checker) -> CResult<Self::Out> {
+        self.declare_lib_func(checker);
+        for decl_or_func in &mut self.init {
+            let result = match decl_or_func {
+                DeclOrFunc::Decl(decl) => decl.check(checker),
+                DeclOrFunc::Func(func) => func.check(checker),
+                DeclOrFunc::Error(_) => Ok(()),
+            };
+            if let Err(err) = result {
+                checker.report(err);
+            }
+        }
+        checker.finish();
+        return Ok(());
+    }
+}
+
+impl SemanticCheck for Decl {
+    type Out = ();
+    fn check(&mut self, checker: &mut Checker) -> CResult<Self::Out> {
+        match self {
+            Self::Const(const_decl) => {
+                for const_def in &mut const_decl.defs {
+                    if let Err(err) = const_def.check(checker) {
+                        checker.report(err.or_span(const_def.span));
+                    }
+                }
+            },
+            Self::Var(var_decl) => {
+                for var_def in &mut var_decl.defs {
+                    if let Err(err) = var_def.check(checker) {
+                        checker.report(err.or_span(var_def.span));
+                    }
+                }
+            },
+        }
+        return Ok(());
+    }
+}
+
+fn check_dims(dims: &mut [ConstExpr], checker: &mut Checker) -> CResult<Vec<usize>> {
+    let mut shape = Vec::new();
+    for dim in dims {
+        let len = dim.check(checker)?;
+        if len < 1 {
+            return Err(CompileError::InvalidInit("array dimension must be a positive constant".to_owned(), Span::default()));
+        }
+        shape.push(len as usize);
+    }
+    return Ok(shape);
+}
+
+// The shape of a definition, or None once its bad dimension is reported: the
+// caller then declares it as an int anyway so later uses do not report again
+fn def_shape(dims: &mut [ConstExpr], span: Span, checker: &mut Checker) -> Option<Vec<usize>> {
+    match check_dims(dims, checker) {
+        Ok(shape) => Some(shape),
+        Err(err) => {
+            checker.report(err.or_span(span));
+            None
+        },
+    }
+}
+
+// Shared by ConstInitVal and InitVal: only the nesting matters for the shape check
+trait InitShape {
+    fn as_list(&self) -> Option<&[Self]> where Self: Sized;
+}
+
+impl InitShape for ConstInitVal {
+    fn as_list(&self) -> Option<&[Self]> {
+        match self {
+            Self::Expr(_) => None,
+            Self::List(list) => Some(list),
+        }
+    }
+}
+
+impl InitShape for InitVal {
+    fn as_list(&self) -> Option<&[Self]> {
+        match self {
+            Self::Expr(_) => None,
+            Self::List(list) => Some(list),
+        }
+    }
+}
+
+// Same layout rules as InitValue::array_linearize: a nested list fills the
+// largest sub-array aligned at the current position
+fn check_init_shape<T: InitShape>(init: &T, shape: &[usize]) -> CResult<()> {
+    let list = match (init.as_list(), shape.is_empty()) {
+        (Some(_), true) => return Err(CompileError::InvalidInit("braced initializer for a scalar".to_owned(), Span::default())),
+        (None, false) => return Err(CompileError::InvalidInit("array must be initialized with a braced list".to_owned(), Span::default())),
+        (None, true) => return Ok(()),
+        (Some(list), false) => list,
+    };
+    let total: usize = shape.iter().product();
+    let mut init_num = 0;
+    for item in list {
+        if init_num >= total {
+            return Err(CompileError::InvalidInit("too many elements in initializer list".to_owned(), Span::default()));
+        }
+        if item.as_list().is_none() {
+            init_num += 1;
+            continue;
+        }
+        let sub_dim = (1..shape.len()).find(|i| init_num % shape[*i..].iter().product::<usize>() == 0);
+        match sub_dim {
+            Some(i) => {
+                check_init_shape(item, &shape[i..])?;
+                init_num += shape[i..].iter().product::<usize>();
+            },
+            None => return Err(CompileError::InvalidInit("nested initializer list is not aligned to a sub-array boundary".to_owned(), Span::default())),
+        }
+    }
+    return Ok(());
+}
+
+impl SemanticCheck for ConstDef {
+    type Out = ();
+    fn check(&mut self, checker: &mut Checker) -> CResult<Self::Out> {
+        self.shape = match def_shape(&mut self.dims, self.span, checker) {
+            Some(shape) => shape,
+            None => return checker.declare(&self.id, Symbol::constant(0), self.id_span),
+        };
+        let init = self.init_val.check(checker).and_then(|_| check_init_shape(&self.init_val, &self.shape));
+        let symbol = if self.shape.is_empty() {
+            let value = match &self.init_val {
+                ConstInitVal::Expr(expr) => expr.const_eval(),
+                ConstInitVal::List(_) => None,
+            };
+            Symbol::constant(value.unwrap_or_default())
+        }
+        else {
+            Symbol::var(SemType::Array(self.shape.clone()), true)
+        };
+        if let Err(err) = init {
+            checker.report(err.or_span(self.span));
+        }
+        return checker.declare(&self.id, symbol, self.id_span);
+    }
+}
+
+impl SemanticCheck for ConstInitVal {
+    type Out = ();
+    fn check(&mut self, checker: &mut Checker) -> CResult<Self::Out> {
+        match self {
+            Self::Expr(expr) => {
+                expr.check(checker)?;
+            },
+            Self::List(list) => {
+                for init_val in list {
+                    init_val.check(checker)?;
+                }
+            },
+        }
+        return Ok(());
+    }
+}
+
+impl SemanticCheck for VarDef {
+    type Out = ();
+    fn check(&mut self, checker: &mut Checker) -> CResult<Self::Out> {
+        let (shape, init_val) = match def_shape(&mut self.dims, self.span, checker) {
+            Some(shape) => (shape, self.init_val.as_mut()),
+            None => (Vec::new(), None),
+        };
+        if let Some(init) = init_val {
+            let result = init.check(checker).and_then(|_| check_init_shape(init, &shape));
+            if let Err(err) = result {
+                checker.report(err.or_span(self.span));
+            }
+        }
+        let ty = if shape.is_empty() { SemType::Int } else { SemType::Array(shape.clone()) };
+        self.shape = shape;
+        return checker.declare(&self.id, Symbol::var(ty, false), self.id_span);
+    }
+}
+
+impl SemanticCheck for InitVal {
+    type Out = ();
+    fn check(&mut self, checker: &mut Checker) -> CResult<Self::Out> {
+        match self {
+            Self::Expr(expr) => {
+                expr.check(checker)?.expect_int()?;
+                if checker.is_global() && expr.const_eval().is_none() {
+                    return Err(CompileError::InvalidInit("global initializer must be a compile-time constant".to_owned(), Span::default()));
+                }
+            },
+            Self::List(list) => {
+                for init_val in list {
+                    init_val.check(checker)?;
+                }
+            },
+        }
+        return Ok(());
+    }
+}
+
+impl SemanticCheck for FuncDef {
+    type Out = ();
+    fn check(&mut self, checker: &mut Checker) -> CResult<Self::Out> {
+        let ret = match self.func_type {
+            FuncType::Void => SemType::Void,
+            FuncType::Int => SemType::Int,
+        };
+        let mut params = Vec::new();
+        for param in &mut self.func_params {
+            let ty = param.check(checker).unwrap_or_else(|err| {
+                checker.report(err.or_span(param.span));
+                SemType::Error
+            });
+            params.push(ty);
+        }
+
+        checker.enter_scope();
+        for (param, ty) in self.func_params.iter().zip(params.iter()) {
+            if let Err(err) = checker.declare_param(&param.param_id, Symbol::var(ty.clone(), false), param.id_span) {
+                checker.report(err);
+            }
+        }
+        // Declared before the body so recursive calls resolve
+        if let Err(err) = checker.declare_func(&self.func_name, FuncSig{ ret: ret.clone(), params }, self.span) {
+            checker.report(err);
+        }
+        let int_func = ret == SemType::Int;
+        checker.cur_func = Some(self.func_name.clone());
+        checker.cur_ret = Some(ret);
+        self.func_body.check(checker)?;
+        checker.cur_func = None;
+        checker.cur_ret = None;
+        checker.exit_scope();
+        // %ret is never stored on such a path, the caller reads garbage
+        if int_func && !self.func_body.always_returns() {
+            checker.warn(CompileWarning::MissingReturn(self.func_name.clone(), self.span));
+        }
+        return Ok(());
+    }
+}
+
+impl SemanticCheck for Param {
+    type Out = SemType;
+    fn check(&mut self, checker: &mut Checker) -> CResult<Self::Out> {
+        match &mut self.param_dims {
+            None => Ok(SemType::Int),
+            Some(dims) => {
+                self.shape = check_dims(dims, checker)?;
+                Ok(SemType::Ptr(self.shape.clone()))
+            },
+        }
+    }
+}
+
+impl SemanticCheck for Block {
+    type Out = ();
+    fn check(&mut self, checker: &mut Checker) -> CResult<Self::Out> {
+        checker.enter_scope();
+        let mut diverged = false;
+        for block_item in &mut self.items {
+            // Only the first statement after return/break/continue is reported
+            if std::mem::take(&mut diverged) {
+                checker.warn(CompileWarning::UnreachableCode(block_item.span()));
+            }
+            match block_item {
+                BlockItem::Decl(decl) => decl.check(checker)?,
+                BlockItem::Stmt(stmt) => {
+                    stmt.check(checker)?;
+                    diverged = stmt.diverges();
+                },
+            }
+        }
+        checker.exit_scope();
+        return Ok(());
+    }
+}
+
+impl SemanticCheck for Stmt {
+    type Out = ();
+    fn check(&mut self, checker: &mut Checker) -> CResult<Self::Out> {
+        // Recovery point: report and go on with the next statement
+        if let Err(err) = self.check_stmt(checker) {
+            checker.report(err.or_span(self.span()));
+        }
+        return Ok(());
+    }
+}
+
+impl Stmt {
+    fn check_stmt(&mut self, checker: &mut Checker) -> CResult<()> {
+        match self {
+            Stmt::ReturnStmt(ret_stmt) => {
+                let ret_ty = match &mut ret_stmt.expr {
+                    Some(expr) => Some(expr.check(checker)?),
+                    None => None,
+                };
+                match (&checker.cur_ret, ret_ty) {
+                    (Some(SemType::Void), Some(_)) => {
+                        return Err(CompileError::InvalidReturn("void function should not return a value".to_owned(), ret_stmt.span));
+                    },
+                    (Some(SemType::Int), None) => {
+                        return Err(CompileError::InvalidReturn("return value missing in int function".to_owned(), ret_stmt.span));
+                    },
+                    (_, Some(ty)) => ty.expect_int()?,
+                    _ => {},
+                }
+            },
+            Stmt::AssignStmt(assign_stmt) => {
+                assign_stmt.expr.check(checker)?.expect_int()?;
+                assign_stmt.lval.check_assign(checker)?;
+            },
+            Stmt::ExprStmt(expr_stmt) => {
+                if let Some(expr) = &mut expr_stmt.expr {
+                    expr.check(checker)?;
+                }
+            },
+            Stmt::BlockStmt(block) => block.check(checker)?,
+            Stmt::IfStmt(if_stmt) => {
+                if_stmt.condition.check(checker)?.expect_int()?;
+                if if_stmt.condition.const_eval() == Some(0) {
+                    checker.warn(CompileWarning::ConstantCondition("if", if_stmt.span));
+                }
+                if_stmt.then_stmt.check(checker)?;
+                if let Some(else_stmt) = &mut if_stmt.else_stmt {
+                    else_stmt.check(checker)?;
+                }
+            },
+            Stmt::WhileStmt(while_stmt) => {
+                while_stmt.condition.check(checker)?.expect_int()?;
+                if while_stmt.condition.const_eval() == Some(0) {
+                    checker.warn(CompileWarning::ConstantCondition("while", while_stmt.span));
+                }
+                checker.loop_depth += 1;
+                while_stmt.body_stmt.check(checker)?;
+                checker.loop_depth -= 1;
+            },
+            Stmt::BreakStmt(break_stmt) => {
+                if checker.loop_depth == 0 {
+                    return Err(CompileError::NotInLoop("break".to_owned(), break_stmt.span));
+                }
+            },
+            Stmt::ContinueStmt(continue_stmt) => {
+                if checker.loop_depth == 0 {
+                    return Err(CompileError::NotInLoop("continue".to_owned(), continue_stmt.span));
+                }
+            },
+            Stmt::Error(_) => {},
+        }
+        return Ok(());
+    }
+}
+
+impl SemanticCheck for ConstExpr {
+    type Out = i32;
+    fn check(&mut self, checker: &mut Checker) -> CResult<Self::Out> {
+        self.expr.check(checker)?.expect_int()?;
+        return self.const_eval().ok_or(CompileError::InvalidInit("expression is not a compile-time constant".to_owned(), Span::default()));
+    }
+}
+
+impl SemanticCheck for Expr {
+    type Out = SemType;
+    fn check(&mut self, checker: &mut Checker) -> CResult<Self::Out> {
+        match self {
+            Self::LOr(expr) => expr.check(checker),
+        }
+    }
+}
+
+// Both operands of a binary operator must be int, and so is the result
+fn check_int_operands<L: SemanticCheck<Out = SemType>, R: SemanticCheck<Out = SemType>>(lexp: &mut L, rexp: &mut R, checker: &mut Checker) -> CResult<SemType> {
+    lexp.check(checker)?.expect_int()?;
+    rexp.check(checker)?.expect_int()?;
+    return Ok(SemType::Int);
+}
+
+impl SemanticCheck for LOrExpr {
+    type Out = SemType;
+    fn check(&mut self, checker: &mut Checker) -> CResult<Self::Out> {
+        match self {
+            Self::LAndExpr(expr) => expr.check(checker),
+            Self::LOrExpr(lexp, rexp) => check_int_operands(lexp.as_mut(), rexp, checker),
+        }
+    }
+}
+
+impl SemanticCheck for LAndExpr {
+    type Out = SemType;
+    fn check(&mut self, checker: &mut Checker) -> CResult<Self::Out> {
+        match self {
+            Self::EqExpr(expr) => expr.check(checker),
+            Self::LAndExpr(lexp, rexp) => check_int_operands(lexp.as_mut(), rexp, checker),
+        }
+    }
+}
+
+impl SemanticCheck for EqExpr {
+    type Out = SemType;
+    fn check(&mut self, checker: &mut Checker) -> CResult<Self::Out> {
+        match self {
+            Self::RelExpr(expr) => expr.check(checker),
+            Self::EqExpr(lexp, _, rexp) => check_int_operands(lexp.as_mut(), rexp, checker),
+        }
+    }
+}
+
+impl SemanticCheck for RelExpr {
+    type Out = SemType;
+    fn check(&mut self, checker: &mut Checker) -> CResult<Self::Out> {
+        match self {
+            Self::AddExpr(expr) => expr.check(checker),
+            Self::RelExpr(lexp, _, rexp) => check_int_operands(lexp.as_mut(), rexp, checker),
+        }
+    }
+}
+
+impl SemanticCheck for AddExpr {
+    type Out = SemType;
+    fn check(&mut self, checker: &mut Checker) -> CResult<Self::Out> {
+        match self {
+            Self::MulExpr(expr) => expr.check(checker),
+            Self::AddAndMul(lexp, _, rexp) => check_int_operands(lexp.as_mut(), rexp, checker),
+        }
+    }
+}
+
+impl SemanticCheck for MulExpr {
+    type Out = SemType;
+    fn check(&mut self, checker: &mut Checker) -> CResult<Self::Out> {
+        match self {
+            Self::UnaryExpr(expr) => expr.check(checker),
+            Self::MulAndUnary(lexp, _, rexp) => check_int_operands(lexp.as_mut(), rexp, checker),
+        }
+    }
+}
+
+impl SemanticCheck for UnaryExpr {
+    type Out = SemType;
+    fn check(&mut self, checker: &mut Checker) -> CResult<Self::Out> {
+        match self {
+            Self::PrimExpr(expr) => expr.check(checker),
+            Self::FuncCall(func_call) => func_call.check(checker),
+            Self::UnaryExpr(_, expr) => {
+                expr.check(checker)?.expect_int()?;
+                Ok(SemType::Int)
+            },
+        }
+    }
+}
+
+impl SemanticCheck for FuncCall {
+    type Out = SemType;
+    fn check(&mut self, checker: &mut Checker) -> CResult<Self::Out> {
+        let mut args = Vec::new();
+        for arg in &mut self.args {
+            args.push(arg.check(checker)?);
+        }
+        let sig = match checker.lookup_func(&self.funcid, self.span) {
+            Ok(sig) => sig.clone(),
+            Err(err) => return poison(checker, err),
+        };
+        if sig.params.len() != args.len() {
+            checker.report(CompileError::InvalidFunccall(format!("`{}` takes {} arguments but {} were supplied", self.funcid, sig.params.len(), args.len()), self.span));
+            return Ok(sig.ret);
+        }
+        for (i, (param, arg)) in sig.params.iter().zip(args.iter()).enumerate() {
+            if !param.accepts(arg) {
+                checker.report(CompileError::InvalidFunccall(format!("argument {} of `{}` expects {}, found {}", i + 1, self.funcid, param, arg), self.span));
+                break;
+            }
+        }
+        return Ok(sig.ret);
+    }
+}
+
+impl SemanticCheck for PrimExpr {
+    type Out = SemType;
+    fn check(&mut self, checker: &mut Checker) -> CResult<Self::Out> {
+        match self {
+            Self::Expr(expr) => expr.check(checker),
+            Self::LVal(lval) => Ok(lval.check(checker)?.decay()),
+            Self::Number(_) => Ok(SemType::Int),
+        }
+    }
+}
+
+// Type of the subscripted lvalue, before array decay
+impl SemanticCheck for LVal {
+    type Out = SemType;
+    fn check(&mut self, checker: &mut Checker) -> CResult<Self::Out> {
+        let symbol = checker.lookup(&self.id, self.span).cloned();
+        for ind in &mut self.inds {
+            ind.check(checker)?.expect_int()?;
+        }
+        let symbol = match symbol {
+            Ok(symbol) => symbol,
+            Err(err) => return poison(checker, err),
+        };
+        if symbol.const_val.is_some() && !self.inds.is_empty() {
+            let err = CompileError::InvalidIdentifier(format!("`{}` is a constant integer and cannot be subscripted", self.id), self.span);
+            return poison(checker, err);
+        }
+        match symbol.ty.subscript(self.inds.len()) {
+            Some(ty) => {
+                self.const_val = symbol.const_val;
+                Ok(ty)
+            },
+            None => {
+                let err = CompileError::InvalidArrayDeref(format!("`{}` has fewer dimensions than subscripts", self.id), self.span);
+                poison(checker, err)
+            },
+        }
+    }
+}
+
+impl LVal {
+    fn check_assign(&mut self, checker: &mut Checker) -> CResult<()> {
+        let ty = self.check(checker)?;
+        if let Ok(symbol) = checker.lookup(&self.id, self.span) {
+            if symbol.is_const {
+                return Err(CompileError::InvalidIdentifier(format!("cannot assign to constant `{}`", self.id), self.span));
+            }
+        }
+        if !matches!(ty, SemType::Int | SemType::Error) {
+            return Err(CompileError::InvalidType("left-hand side of assignment is not an int variable".to_owned(), self.span));
+        }
+        return Ok(());
+    }
+}
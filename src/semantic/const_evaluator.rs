@@ -1,32 +1,36 @@
-use super::namespace::*;
 use crate::ast::ast_def::*;
 
+// Constant folding over the AST: names resolve through the annotations
+// left by the semantic pass, so it needs no symbol table of its own.
+// Arithmetic wraps like the target's; a division by zero or of i32::MIN
+// by -1 is no constant.
+
 pub trait ConstEvaluator {
-    fn const_eval(&self, namesp: &mut Namesp) -> Option<i32>;
+    fn const_eval(&self) -> Option<i32>;
 }
 
 impl ConstEvaluator for ConstExpr {
-    fn const_eval(&self, namesp: &mut Namesp) -> Option<i32>{
-        return self.expr.const_eval(namesp);
+    fn const_eval(&self) -> Option<i32>{
+        return self.expr.const_eval();
     }
 }
 
 impl ConstEvaluator for Expr {
-    fn const_eval(&self, namesp: &mut Namesp) -> Option<i32>{
+    fn const_eval(&self) -> Option<i32>{
         match self {
-            Self::LOr(expr) => expr.const_eval(namesp),
+            Self::LOr(expr) => expr.const_eval(),
         }
     }
 }
 
 impl ConstEvaluator for LOrExpr {
-    fn const_eval(&self, namesp: &mut Namesp) -> Option<i32>{
+    fn const_eval(&self) -> Option<i32>{
         match self {
-            Self::LAndExpr(land_expr) => land_expr.const_eval(namesp),
+            Self::LAndExpr(land_expr) => land_expr.const_eval(),
 
             Self::LOrExpr(lexpr, rexpr) => {
-                let lval = lexpr.const_eval(namesp);
-                let rval = rexpr.const_eval(namesp);
+                let lval = lexpr.const_eval();
+                let rval = rexpr.const_eval();
                 match (lval, rval) {
                     (Some(lval), Some(rval)) => Some((lval!=0 || rval!=0) as i32),
                     _ => None,
@@ -37,13 +41,13 @@ impl ConstEvaluator for LOrExpr {
 }
 
 impl ConstEvaluator for LAndExpr{
-    fn const_eval(&self, namesp: &mut Namesp) -> Option<i32> {
+    fn const_eval(&self) -> Option<i32> {
         match self {
-            Self::EqExpr(eqexpr) => eqexpr.const_eval(namesp),
+            Self::EqExpr(eqexpr) => eqexpr.const_eval(),
 
             Self::LAndExpr(lexpr, rexpr) => {
-                let lval = lexpr.const_eval(namesp);
-                let rval = rexpr.const_eval(namesp);
+                let lval = lexpr.const_eval();
+                let rval = rexpr.const_eval();
                 match (lval, rval) {
                     (Some(lval), Some(rval)) => Some((lval!=0 && rval!=0) as i32),
                     _ => None,
@@ -54,13 +58,13 @@ impl ConstEvaluator for LAndExpr{
 }
 
 impl ConstEvaluator for EqExpr{
-    fn const_eval(&self, namesp: &mut Namesp) -> Option<i32> {
+    fn const_eval(&self) -> Option<i32> {
         match self {
-            Self::RelExpr(expr) => expr.const_eval(namesp),
+            Self::RelExpr(expr) => expr.const_eval(),
 
             Self::EqExpr(lexpr, op, rexpr) => {
-                let lval = lexpr.const_eval(namesp);
-                let rval = rexpr.const_eval(namesp);
+                let lval = lexpr.const_eval();
+                let rval = rexpr.const_eval();
                 match (lval, rval) {
                     (Some(lval), Some(rval)) => {
                         match op {
@@ -76,13 +80,13 @@ impl ConstEvaluator for EqExpr{
 }
 
 impl ConstEvaluator for RelExpr {
-    fn const_eval(&self, namesp: &mut Namesp) -> Option<i32> {
+    fn const_eval(&self) -> Option<i32> {
         match self {
-            Self::AddExpr(expr) => expr.const_eval(namesp),
+            Self::AddExpr(expr) => expr.const_eval(),
 
             Self::RelExpr(lexpr, op, rexpr) => {
-                let lval = lexpr.const_eval(namesp);
-                let rval = rexpr.const_eval(namesp);
+                let lval = lexpr.const_eval();
+                let rval = rexpr.const_eval();
                 match (lval, rval) {
                     (Some(lval), Some(rval)) => {
                         match op {
@@ -100,18 +104,18 @@ impl ConstEvaluator for RelExpr {
 }
 
 impl ConstEvaluator for AddExpr {
-    fn const_eval(&self, namesp: &mut Namesp) -> Option<i32> {
+    fn const_eval(&self) -> Option<i32> {
         match self {
-            Self::MulExpr(expr) => expr.const_eval(namesp),
+            Self::MulExpr(expr) => expr.const_eval(),
 
             Self::AddAndMul(lexpr, op, rexpr) => {
-                let lval = lexpr.const_eval(namesp);
-                let rval = rexpr.const_eval(namesp);
+                let lval = lexpr.const_eval();
+                let rval = rexpr.const_eval();
                 match (lval, rval) {
                     (Some(lval), Some(rval)) => {
                         match op {
-                            AddOp::Add => Some(lval + rval),
-                            AddOp::Minus => Some(lval - rval),
+                            AddOp::Add => Some(lval.wrapping_add(rval)),
+                            AddOp::Minus => Some(lval.wrapping_sub(rval)),
                         }
                     }
                     _ => None,
@@ -122,19 +126,19 @@ impl ConstEvaluator for AddExpr {
 }
 
 impl ConstEvaluator for MulExpr {
-    fn const_eval(&self, namesp: &mut Namesp) -> Option<i32> {
+    fn const_eval(&self) -> Option<i32> {
         match self {
-            Self::UnaryExpr(expr) => expr.const_eval(namesp),
+            Self::UnaryExpr(expr) => expr.const_eval(),
 
             Self::MulAndUnary(lexpr, op, rexpr) => {
-                let lval = lexpr.const_eval(namesp);
-                let rval = rexpr.const_eval(namesp);
+                let lval = lexpr.const_eval();
+                let rval = rexpr.const_eval();
                 match (lval, rval) {
                     (Some(lval), Some(rval)) => {
                         match op {
-                            MulOp::Mul => Some(lval * rval),
-                            MulOp::Div => (rval!=0).then_some(lval / rval),
-                            MulOp::Mod => (rval!=0).then_some(lval % rval),
+                            MulOp::Mul => Some(lval.wrapping_mul(rval)),
+                            MulOp::Div => lval.checked_div(rval),
+                            MulOp::Mod => lval.checked_rem(rval),
                         }
                     }
                     _ => None,
@@ -145,15 +149,15 @@ impl ConstEvaluator for MulExpr {
 }
 
 impl ConstEvaluator for UnaryExpr {
-    fn const_eval(&self, namesp: &mut Namesp) -> Option<i32> {
+    fn const_eval(&self) -> Option<i32> {
         match self {
-            Self::PrimExpr(expr) => expr.const_eval(namesp),
+            Self::PrimExpr(expr) => expr.const_eval(),
             Self::FuncCall(_) => None,
 
-            Self::UnaryExpr(op, expr) => expr.const_eval(namesp).map(|val| {
+            Self::UnaryExpr(op, expr) => expr.const_eval().map(|val| {
                 match op {
                     UnaryOp::Pos => val,
-                    UnaryOp::Neg => -val,
+                    UnaryOp::Neg => val.wrapping_neg(),
                     UnaryOp::Not => (val==0) as i32,
                 }
             }),
@@ -162,30 +166,17 @@ impl ConstEvaluator for UnaryExpr {
 }
 
 impl ConstEvaluator for PrimExpr {
-    fn const_eval(&self, namesp: &mut Namesp) -> Option<i32> {
+    fn const_eval(&self) -> Option<i32> {
         match self {
-            Self::Expr(expr) => expr.const_eval(namesp),
-            Self::LVal(lval) => lval.const_eval(namesp),
+            Self::Expr(expr) => expr.const_eval(),
+            Self::LVal(lval) => lval.const_eval(),
             Self::Number(num) => Some(*num),
         }
     }
 }
 
 impl ConstEvaluator for LVal {
-    fn const_eval(&self ,namesp: &mut Namesp) -> Option<i32>{
-        let val = namesp.get_value(&self.id);
-        if val.is_err() {
-            return None;
-        }
-
-        let val = val.unwrap();
-        if self.inds.len() != 0 {
-            panic!("Array unit cant be const");
-        }
-        match val {
-            NamespValue::ConstInt(val) => Some(*val),
-            _ => None,
-        }
-
+    fn const_eval(&self) -> Option<i32>{
+        return self.const_val;
     }
-}
\ No newline at end of file
+}
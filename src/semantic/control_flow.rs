@@ -0,0 +1,69 @@
+use crate::ast::ast_def::*;
+use super::const_evaluator::*;
+
+// AST-level control flow: whether execution can fall off the end of a statement
+
+impl Block {
+    pub fn always_returns(&self) -> bool {
+        self.items.iter().any(|item| match item {
+            BlockItem::Stmt(stmt) => stmt.always_returns(),
+            BlockItem::Decl(_) => false,
+        })
+    }
+}
+
+impl Stmt {
+    pub fn always_returns(&self) -> bool {
+        match self {
+            Stmt::ReturnStmt(_) => true,
+            Stmt::BlockStmt(block) => block.always_returns(),
+            Stmt::IfStmt(if_stmt) => {
+                let else_returns = if_stmt.else_stmt.as_ref().is_some_and(|stmt| stmt.always_returns());
+                match if_stmt.condition.const_eval() {
+                    Some(0) => else_returns,
+                    Some(_) => if_stmt.then_stmt.always_returns(),
+                    None => if_stmt.then_stmt.always_returns() && else_returns,
+                }
+            },
+            // while (1) only ends through a break
+            Stmt::WhileStmt(while_stmt) => {
+                matches!(while_stmt.condition.const_eval(), Some(cond) if cond != 0) && !while_stmt.body_stmt.breaks_out()
+            },
+            _ => false,
+        }
+    }
+
+    // Whether control never reaches the statement after this one
+    pub fn diverges(&self) -> bool {
+        match self {
+            Stmt::ReturnStmt(_) | Stmt::BreakStmt(_) | Stmt::ContinueStmt(_) => true,
+            Stmt::BlockStmt(block) => block.items.iter().any(|item| match item {
+                BlockItem::Stmt(stmt) => stmt.diverges(),
+                BlockItem::Decl(_) => false,
+            }),
+            Stmt::IfStmt(if_stmt) => {
+                if_stmt.then_stmt.diverges() && if_stmt.else_stmt.as_ref().is_some_and(|stmt| stmt.diverges())
+            },
+            Stmt::WhileStmt(while_stmt) => {
+                matches!(while_stmt.condition.const_eval(), Some(cond) if cond != 0) && !while_stmt.body_stmt.breaks_out()
+            },
+            _ => false,
+        }
+    }
+
+    // Whether a break in this statement leaves the enclosing loop
+    fn breaks_out(&self) -> bool {
+        match self {
+            Stmt::BreakStmt(_) => true,
+            Stmt::BlockStmt(block) => block.items.iter().any(|item| match item {
+                BlockItem::Stmt(stmt) => stmt.breaks_out(),
+                BlockItem::Decl(_) => false,
+            }),
+            Stmt::IfStmt(if_stmt) => {
+                if_stmt.then_stmt.breaks_out() || if_stmt.else_stmt.as_ref().is_some_and(|stmt| stmt.breaks_out())
+            },
+            // A nested loop catches its own breaks
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,94 @@
+/*
+    Semantic analysis:
+        Runs over the AST before any Koopa IR exists. Resolves names, checks
+        int/void/array types, calls and initializer lists, and annotates the
+        AST (array shapes, folded constants) for koopa_generator.
+*/
+pub mod const_evaluator;
+mod symbol_table;
+mod checker;
+mod control_flow;
+
+use checker::SemanticCheck;
+use crate::ast::ast_def::*;
+use crate::koopa_generator::CompileError;
+use symbol_table::Checker;
+use std::fmt;
+
+pub struct SemanticReport {
+    pub errors: Vec<CompileError>,     // Empty means the AST is well-typed
+    pub warnings: Vec<CompileWarning>,
+}
+
+// Every error and warning found is returned
+pub fn check(comp_unit: &mut CompileInit) -> SemanticReport {
+    let mut checker = Checker::new();
+    if let Err(err) = comp_unit.check(&mut checker) {
+        checker.report(err);
+    }
+    // Scopes are hash maps: sort so warnings come out in source order
+    let mut warnings = checker.take_warnings();
+    warnings.sort_by_key(|warning| warning.span().start);
+    return SemanticReport{
+        errors: checker.take_errors(),
+        warnings,
+    };
+}
+
+#[derive(Debug)]
+pub enum CompileWarning {
+    MissingReturn(String, Span),            // int function that can fall off its end
+    UnusedVariable(String, Span),           // Local or global, const or not
+    UnusedParameter(String, Span),
+    UnusedFunction(String, Span),
+    UnreachableCode(Span),                  // First statement after return/break/continue
+    ConstantCondition(&'static str, Span),  // "if" or "while" whose condition folds to 0
+}
+
+impl CompileWarning {
+    // Every warning name with whether it is on by default
+    pub const ALL: &'static [(&'static str, bool)] = &[
+        ("return-type", true),
+        ("unused-variable", true),
+        ("unused-parameter", false),
+        ("unused-function", true),
+        ("unreachable-code", true),
+        ("constant-condition", true),
+    ];
+
+    pub fn span(&self) -> Span {
+        match self {
+            Self::MissingReturn(_, span)
+            | Self::UnusedVariable(_, span)
+            | Self::UnusedParameter(_, span)
+            | Self::UnusedFunction(_, span)
+            | Self::UnreachableCode(span)
+            | Self::ConstantCondition(_, span) => *span,
+        }
+    }
+
+    // Name used on the command line: -W<name>, -Wno-<name>, -Werror=<name>
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::MissingReturn(..) => "return-type",
+            Self::UnusedVariable(..) => "unused-variable",
+            Self::UnusedParameter(..) => "unused-parameter",
+            Self::UnusedFunction(..) => "unused-function",
+            Self::UnreachableCode(..) => "unreachable-code",
+            Self::ConstantCondition(..) => "constant-condition",
+        }
+    }
+}
+
+impl fmt::Display for CompileWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingReturn(func, _) => write!(f, "control reaches end of non-void function `{}`", func),
+            Self::UnusedVariable(id, _) => write!(f, "unused variable `{}`", id),
+            Self::UnusedParameter(id, _) => write!(f, "unused parameter `{}`", id),
+            Self::UnusedFunction(id, _) => write!(f, "function `{}` is never called", id),
+            Self::UnreachableCode(_) => write!(f, "unreachable statement"),
+            Self::ConstantCondition(stmt, _) => write!(f, "`{}` condition is always false", stmt),
+        }
+    }
+}
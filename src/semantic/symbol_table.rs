@@ -0,0 +1,255 @@
+use crate::ast::ast_def::*;
+use crate::koopa_generator::{CResult, CompileError};
+use super::CompileWarning;
+use std::collections::HashMap;
+use std::fmt;
+
+// Type of a value as the checker sees it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SemType {
+    Int,
+    Void,
+    Array(Vec<usize>), // int a[2][3] => [2, 3]
+    Ptr(Vec<usize>),   // int a[][3] or a decayed array => [3]
+    Error,             // Already reported, accepted everywhere
+}
+
+impl SemType {
+    // Type after `count` subscripts, None if there are too many
+    pub fn subscript(&self, count: usize) -> Option<SemType> {
+        match self {
+            Self::Error => Some(Self::Error),
+            Self::Int | Self::Void => (count == 0).then(|| self.clone()),
+            Self::Array(dims) => {
+                if count < dims.len() {
+                    Some(Self::Array(dims[count..].to_vec()))
+                }
+                else {
+                    (count == dims.len()).then_some(Self::Int)
+                }
+            },
+            Self::Ptr(dims) => {
+                if count == 0 {
+                    Some(self.clone())
+                }
+                else if count - 1 < dims.len() {
+                    Some(Self::Array(dims[count - 1..].to_vec()))
+                }
+                else {
+                    (count - 1 == dims.len()).then_some(Self::Int)
+                }
+            },
+        }
+    }
+
+    // Arrays used as a value decay to a pointer to their first element
+    pub fn decay(self) -> SemType {
+        match self {
+            Self::Array(dims) => Self::Ptr(dims[1..].to_vec()),
+            ty => ty,
+        }
+    }
+
+    // Whether a value of type `arg` can be passed for a parameter of this type
+    pub fn accepts(&self, arg: &SemType) -> bool {
+        matches!(self, Self::Error) || matches!(arg, Self::Error) || self == arg
+    }
+
+    pub fn expect_int(&self) -> CResult<()> {
+        match self {
+            Self::Int | Self::Error => Ok(()),
+            Self::Void => Err(CompileError::InvalidType("void value used in an expression".to_owned(), Span::default())),
+            Self::Array(_) | Self::Ptr(_) => Err(CompileError::InvalidType("array used where an int is expected".to_owned(), Span::default())),
+        }
+    }
+}
+
+impl fmt::Display for SemType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Int => write!(f, "int"),
+            Self::Void => write!(f, "void"),
+            Self::Array(dims) => {
+                write!(f, "int")?;
+                dims.iter().try_for_each(|len| write!(f, "[{}]", len))
+            },
+            Self::Ptr(dims) => {
+                write!(f, "int[]")?;
+                dims.iter().try_for_each(|len| write!(f, "[{}]", len))
+            },
+            Self::Error => write!(f, "{{error}}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Global,
+    Local,
+    Param,
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub ty: SemType,
+    pub const_val: Option<i32>, // Scalar constants are folded
+    pub is_const: bool,
+    // Usage tracking for -Wunused-*, filled in by the Checker
+    kind: SymbolKind,
+    span: Span,
+    used: bool,
+}
+
+impl Symbol {
+    pub fn constant(value: i32) -> Self {
+        Self::new(SemType::Int, Some(value), true)
+    }
+
+    pub fn var(ty: SemType, is_const: bool) -> Self {
+        Self::new(ty, None, is_const)
+    }
+
+    fn new(ty: SemType, const_val: Option<i32>, is_const: bool) -> Self {
+        Self{ ty, const_val, is_const, kind: SymbolKind::Local, span: Span::default(), used: false }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FuncSig {
+    pub ret: SemType,
+    pub params: Vec<SemType>,
+}
+
+struct FuncEntry {
+    sig: FuncSig,
+    span: Span, // Unknown for library functions
+    called: bool,
+}
+
+pub struct Checker {
+    values: Vec<HashMap<String, Symbol>>, // Stack, [0] is global
+    funcs: HashMap<String, FuncEntry>,
+    pub cur_func: Option<String>, // Function being checked and its return type
+    pub cur_ret: Option<SemType>,
+    pub loop_depth: usize,
+
+    errors: Vec<CompileError>, // Diagnostics sink: checking goes on after reporting
+    warnings: Vec<CompileWarning>,
+}
+
+impl Checker {
+    pub fn new() -> Self {
+        Self{
+            values: vec![HashMap::new()],
+            funcs: HashMap::new(),
+            cur_func: None,
+            cur_ret: None,
+            loop_depth: 0,
+            errors: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    pub fn report(&mut self, err: CompileError) {
+        self.errors.push(err);
+    }
+
+    pub fn take_errors(&mut self) -> Vec<CompileError> {
+        std::mem::take(&mut self.errors)
+    }
+
+    pub fn warn(&mut self, warning: CompileWarning) {
+        self.warnings.push(warning);
+    }
+
+    pub fn take_warnings(&mut self) -> Vec<CompileWarning> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    pub fn enter_scope(&mut self) {
+        self.values.push(HashMap::new());
+    }
+
+    pub fn exit_scope(&mut self) {
+        let layer = self.values.pop().unwrap();
+        self.warn_unused(layer);
+    }
+
+    // End of the compile unit: globals and functions nobody referenced
+    pub fn finish(&mut self) {
+        let globals = std::mem::take(&mut self.values[0]);
+        self.warn_unused(globals);
+        let mut unused_funcs = self.funcs.iter()
+            .filter(|(id, func)| !func.called && !func.span.is_unknown() && id.as_str() != "main")
+            .map(|(id, func)| CompileWarning::UnusedFunction(id.clone(), func.span))
+            .collect::<Vec<_>>();
+        self.warnings.append(&mut unused_funcs);
+    }
+
+    fn warn_unused(&mut self, layer: HashMap<String, Symbol>) {
+        for (id, symbol) in layer {
+            if symbol.used || symbol.span.is_unknown() || id.starts_with('_') {
+                continue;
+            }
+            let warning = match symbol.kind {
+                SymbolKind::Param => CompileWarning::UnusedParameter(id, symbol.span),
+                SymbolKind::Global | SymbolKind::Local => CompileWarning::UnusedVariable(id, symbol.span),
+            };
+            self.warnings.push(warning);
+        }
+    }
+
+    pub fn is_global(&self) -> bool {
+        return self.values.len() == 1;
+    }
+
+    pub fn declare(&mut self, id: &str, symbol: Symbol, span: Span) -> CResult<()> {
+        let kind = if self.is_global() { SymbolKind::Global } else { SymbolKind::Local };
+        self.declare_as(id, symbol, kind, span)
+    }
+
+    pub fn declare_param(&mut self, id: &str, symbol: Symbol, span: Span) -> CResult<()> {
+        self.declare_as(id, symbol, SymbolKind::Param, span)
+    }
+
+    fn declare_as(&mut self, id: &str, mut symbol: Symbol, kind: SymbolKind, span: Span) -> CResult<()> {
+        let global = self.is_global();
+        let cur_layer = self.values.last_mut().unwrap();
+        if cur_layer.contains_key(id) || (global && self.funcs.contains_key(id)) {
+            return Err(CompileError::DuplicateIdentifier(id.to_owned(), span));
+        }
+        symbol.kind = kind;
+        symbol.span = span;
+        cur_layer.insert(id.to_owned(), symbol);
+        return Ok(());
+    }
+
+    pub fn declare_func(&mut self, id: &str, sig: FuncSig, span: Span) -> CResult<()> {
+        if self.funcs.contains_key(id) || self.values[0].contains_key(id) {
+            return Err(CompileError::DuplicateIdentifier(id.to_owned(), span));
+        }
+        self.funcs.insert(id.to_owned(), FuncEntry{ sig, span, called: false });
+        return Ok(());
+    }
+
+    // Any reference, read or write, counts as a use
+    pub fn lookup(&mut self, id: &str, span: Span) -> CResult<&Symbol> {
+        for layer in self.values.iter_mut().rev() {
+            if let Some(symbol) = layer.get_mut(id) {
+                symbol.used = true;
+                return Ok(symbol);
+            }
+        }
+        return Err(CompileError::VarNotDeclared(id.to_owned(), span));
+    }
+
+    // A function calling only itself is still never called
+    pub fn lookup_func(&mut self, id: &str, span: Span) -> CResult<&FuncSig> {
+        let recursive = self.cur_func.as_deref() == Some(id);
+        if let Some(func) = self.funcs.get_mut(id) {
+            func.called |= !recursive;
+            return Ok(&func.sig);
+        }
+        return Err(CompileError::FuncNotDeclared(id.to_owned(), span));
+    }
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use common::*;
+use compiler::{OptLevel, OptOptions, RegAlloc};
+
+// A branch passing one block parameter two different arguments: coloring gave
+// the parameter the register of one of them while the other was still live
+#[test]
+fn branch_to_one_block_with_two_arguments() {
+    let source = "
+        int sq(int x) { return x * x; }
+        int add3(int a, int b, int c) { return a + b + c; }
+        int maxi(int a, int b) { if (a > b) return a; return b; }
+        void inc(int a[], int i) { a[i] = a[i] + 1; }
+        int main() {
+            int arr[5] = {};
+            int i = 0, s = 0;
+            while (i < 100) {
+                s = s + sq(i) - add3(i, 1, 2) + maxi(i, 50);
+                inc(arr, i % 5);
+                i = i + 1;
+            }
+            putint(s); putch(32); putint(arr[0] + arr[4] * 2); putch(10);
+            return 0;
+        }
+    ";
+    let options = OptOptions{ level: OptLevel::O2, ..OptOptions::default() };
+    for regalloc in REG_ALLOCS {
+        assert_eq!(run(source, &options, regalloc, ""), "329325 60\n0", "{:?}", regalloc);
+    }
+}
+
+#[test]
+fn assembly_of_each_allocator() {
+    let source = "int f(int a, int b) { return a * b + a; } int main() { int x = getint(); putint(f(x, x + 1)); putch(10); return 0; }";
+    let mut program = compile(source);
+    compiler::optimize(&mut program, OptLevel::O1);
+    for (regalloc, golden) in [(RegAlloc::Stack, "stack.s"), (RegAlloc::LinearScan, "linear.s"), (RegAlloc::GraphColoring, "coloring.s")] {
+        let asm = asm(&program, regalloc);
+        assert_golden(golden, &asm);
+        assert_eq!(run_asm(&asm, "6"), "48\n0", "{:?}", regalloc);
+    }
+}
+
+// More values live across calls than there are saved registers, arguments on
+// the stack, recursion and a global array, at every level with every allocator
+#[test]
+fn every_allocator_at_every_level() {
+    let source = "
+        int g[10];
+        int many(int a, int b, int c, int d, int e, int f, int h, int i, int j, int k) {
+            return a - b + c - d + e - f + h - i + j - k * 2;
+        }
+        int fib(int n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+        int main() {
+            int n = getint();
+            int a = n + 1, b = n * 2, c = n - 3, d = n * n, e = n / 2, f = n % 3, h = a + b;
+            int i = c + d, j = e * f, k = h - i, l = a * c, m = b + d, o = 0;
+            while (o < 10) {
+                g[o] = fib(o) + a * o - m;
+                o = o + 1;
+            }
+            putint(many(a, b, c, d, e, f, h, i, j, k)); putch(32);
+            putint(a + b + c + d + e + f + h + i + j + k + l + m); putch(10);
+            putarray(10, g);
+            return l;
+        }
+    ";
+    let expected = "-15 221\n10: -63 -54 -46 -37 -28 -18 -7 6 22 43\n32";
+    for level in [OptLevel::O0, OptLevel::O1, OptLevel::O2] {
+        for regalloc in REG_ALLOCS {
+            let options = OptOptions{ level, ..OptOptions::default() };
+            assert_eq!(run(source, &options, regalloc, "7"), expected, "{:?} {:?}", level, regalloc);
+        }
+    }
+}
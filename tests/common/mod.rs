@@ -0,0 +1,315 @@
+/*
+    Shared by the integration tests: compiling in memory, golden files, and a small
+    interpreter for the RV32IM assembly the compiler prints, with the SysY
+    library. A library call clobbers every caller-saved register but a0, so
+    a value the allocator wrongly left in one shows up in the output.
+*/
+#![allow(dead_code)]
+use compiler::{OptOptions, Program, RegAlloc};
+use std::collections::HashMap;
+use std::path::Path;
+
+pub const REG_ALLOCS: [RegAlloc; 3] = [RegAlloc::Stack, RegAlloc::LinearScan, RegAlloc::GraphColoring];
+
+pub fn compile(source: &str) -> Program {
+    let comp_init = compiler::parse(source).expect("program should check");
+    compiler::generate_program(&comp_init).expect("program should generate")
+}
+
+// Options running the level's pipeline and then the passes named
+pub fn passes(names: &[&str]) -> OptOptions {
+    OptOptions{ enable: names.iter().map(|name| name.to_string()).collect(), ..OptOptions::default() }
+}
+
+pub fn koopa(program: &Program) -> String {
+    let mut text = Vec::new();
+    compiler::generate_koopa(program, &mut text).unwrap();
+    String::from_utf8(text).unwrap()
+}
+
+pub fn asm(program: &Program, regalloc: RegAlloc) -> String {
+    let mut text = Vec::new();
+    compiler::generate_asm_with(program, regalloc, &mut text).unwrap();
+    String::from_utf8(text).unwrap()
+}
+
+// Compares `actual` with tests/golden/<name>, which UPDATE_GOLDEN=1 rewrites instead
+pub fn assert_golden(name: &str, actual: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|_| panic!("missing {}, run with UPDATE_GOLDEN=1", path.display()));
+    assert!(actual == expected, "{} differs from the output:\n{}", path.display(), actual);
+}
+
+// What the program prints, then its exit code
+pub fn run(source: &str, options: &OptOptions, regalloc: RegAlloc, input: &str) -> String {
+    let mut program = compile(source);
+    compiler::optimize_with(&mut program, options);
+    run_asm(&asm(&program, regalloc), input)
+}
+
+pub fn run_asm(asm: &str, input: &str) -> String {
+    let mut machine = Machine::load(asm, input);
+    let code = machine.run();
+    format!("{}{}", machine.out, code)
+}
+
+const REG_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1",
+    "a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7",
+    "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11",
+    "t3", "t4", "t5", "t6",
+];
+const CALLER_SAVED: [&str; 14] = ["t0", "t1", "t2", "t3", "t4", "t5", "t6", "a1", "a2", "a3", "a4", "a5", "a6", "a7"];
+const GARBAGE: i32 = 0x5a5a5a5a;
+const RETURN_TO_TEST: i32 = -7;
+const STEP_LIMIT: usize = 50_000_000;
+
+fn reg(name: &str) -> Option<usize> {
+    match name {
+        "fp" => Some(8),
+        _ => REG_NAMES.iter().position(|&known| known == name)
+            .or_else(|| name.strip_prefix('x')?.parse().ok().filter(|&index| index < 32)),
+    }
+}
+
+enum Arg {
+    Reg(usize),
+    Imm(i32),
+    Mem(i32, usize), // offset(base)
+    Label(String),
+}
+
+impl Arg {
+    fn parse(arg: &str) -> Self {
+        if let Some((offset, base)) = arg.strip_suffix(')').and_then(|arg| arg.split_once('(')) {
+            return Arg::Mem(offset.parse().unwrap(), reg(base).unwrap());
+        }
+        match (reg(arg), arg.parse::<i64>()) {
+            (Some(reg), _) => Arg::Reg(reg),
+            (None, Ok(imm)) => Arg::Imm(imm as i32),
+            (None, Err(_)) => Arg::Label(arg.to_owned()),
+        }
+    }
+}
+
+struct Machine<'input> {
+    insts: Vec<(String, Vec<Arg>)>,
+    labels: HashMap<String, i32>, // Instruction index in .text, address in .data
+    regs: [i32; 32],
+    mem: HashMap<i32, i32>,
+    input: std::str::SplitWhitespace<'input>,
+    out: String,
+}
+
+impl<'input> Machine<'input> {
+    fn load(asm: &str, input: &'input str) -> Self {
+        let mut machine = Machine{
+            insts: Vec::new(),
+            labels: HashMap::new(),
+            regs: [0; 32],
+            mem: HashMap::new(),
+            input: input.split_whitespace(),
+            out: String::new(),
+        };
+        let (mut data, mut addr) = (false, 0x10000);
+        for line in asm.lines() {
+            let mut line = line.split('#').next().unwrap().trim();
+            while let Some((label, rest)) = line.split_once(':') {
+                let at = if data { addr } else { machine.insts.len() as i32 };
+                machine.labels.insert(label.trim().to_owned(), at);
+                line = rest.trim();
+            }
+            let (op, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            match op {
+                "" | ".globl" | ".align" | ".p2align" | ".section" | ".type" | ".size" => {},
+                ".data" => data = true,
+                ".text" => data = false,
+                ".word" => {
+                    for word in args.split(',') {
+                        machine.mem.insert(addr, word.trim().parse::<i64>().unwrap() as i32);
+                        addr += 4;
+                    }
+                },
+                ".zero" => {
+                    let size = args.trim().parse::<i32>().unwrap();
+                    for offset in (0..size).step_by(4) {
+                        machine.mem.insert(addr + offset, 0);
+                    }
+                    addr += size;
+                },
+                _ if op.starts_with('.') => panic!("unknown directive `{}`", line),
+                _ => {
+                    let args = args.split(',').map(str::trim).filter(|arg| !arg.is_empty()).map(Arg::parse).collect();
+                    machine.insts.push((op.to_owned(), args));
+                },
+            }
+        }
+        return machine;
+    }
+
+    fn write(&mut self, reg: usize, value: i32) {
+        if reg != 0 {
+            self.regs[reg] = value;
+        }
+    }
+
+    fn load_word(&self, addr: i32) -> i32 {
+        assert!(addr % 4 == 0, "unaligned load from {:#x}", addr);
+        *self.mem.get(&addr).unwrap_or_else(|| panic!("load from uninitialized {:#x}", addr))
+    }
+
+    fn store_word(&mut self, addr: i32, value: i32) {
+        assert!(addr % 4 == 0 && addr >= 0x10000, "bad store to {:#x}", addr);
+        self.mem.insert(addr, value);
+    }
+
+    fn next_int(&mut self) -> i32 {
+        self.input.next().map_or(0, |word| word.parse().unwrap())
+    }
+
+    fn call_library(&mut self, name: &str) {
+        let a0 = self.regs[10];
+        let mut result = GARBAGE;
+        match name {
+            "getint" => result = self.next_int(),
+            "getarray" => {
+                result = self.next_int();
+                for index in 0..result {
+                    let value = self.next_int();
+                    self.store_word(a0 + 4 * index, value);
+                }
+            },
+            "putint" => self.out += &a0.to_string(),
+            "putch" => self.out.push(a0 as u8 as char),
+            "putarray" => {
+                let base = self.regs[11];
+                self.out += &format!("{}:", a0);
+                for index in 0..a0 {
+                    self.out += &format!(" {}", self.load_word(base + 4 * index));
+                }
+                self.out.push('\n');
+            },
+            "starttime" | "stoptime" | "_sysy_starttime" | "_sysy_stoptime" => {},
+            _ => panic!("unknown function `{}`", name),
+        }
+        for name in CALLER_SAVED {
+            self.write(reg(name).unwrap(), GARBAGE);
+        }
+        self.write(10, result);
+    }
+
+    // Runs main, returns its exit code
+    fn run(&mut self) -> i32 {
+        self.regs[2] = 0x7ff0000;
+        self.regs[1] = RETURN_TO_TEST;
+        let insts = std::mem::take(&mut self.insts);
+        let mut pc = self.labels["main"];
+        let mut steps = 0;
+        while pc != RETURN_TO_TEST {
+            steps += 1;
+            assert!(steps < STEP_LIMIT, "step limit reached");
+            let (op, args) = &insts[pc as usize];
+            pc += 1;
+            let reg = |index: usize| match args[index] {
+                Arg::Reg(reg) => reg,
+                _ => panic!("`{}` expects a register", op),
+            };
+            let imm = |index: usize| match args[index] {
+                Arg::Imm(imm) => imm,
+                _ => panic!("`{}` expects an immediate", op),
+            };
+            let label = |index: usize| match &args[index] {
+                Arg::Label(label) => label.as_str(),
+                _ => panic!("`{}` expects a label", op),
+            };
+            let op = op.as_str();
+            match op {
+                "li" => {
+                    let (rd, imm) = (reg(0), imm(1));
+                    self.write(rd, imm);
+                },
+                "la" => {
+                    let (rd, addr) = (reg(0), self.labels[label(1)]);
+                    self.write(rd, addr);
+                },
+                "lw" | "sw" => {
+                    let Arg::Mem(offset, base) = args[1] else { panic!("`{}` expects an address", op) };
+                    let (rs, addr) = (reg(0), self.regs[base].wrapping_add(offset));
+                    if op == "lw" {
+                        let value = self.load_word(addr);
+                        self.write(rs, value);
+                    }
+                    else {
+                        self.store_word(addr, self.regs[rs]);
+                    }
+                },
+                "mv" | "neg" | "seqz" | "snez" => {
+                    let (rd, x) = (reg(0), self.regs[reg(1)]);
+                    let value = match op {
+                        "mv" => x,
+                        "neg" => x.wrapping_neg(),
+                        "seqz" => (x == 0) as i32,
+                        _ => (x != 0) as i32,
+                    };
+                    self.write(rd, value);
+                },
+                "j" => pc = self.labels[label(0)],
+                "beqz" | "bnez" => {
+                    let x = self.regs[reg(0)];
+                    if (x == 0) == (op == "beqz") {
+                        pc = self.labels[label(1)];
+                    }
+                },
+                "call" => {
+                    let name = label(0).to_owned();
+                    match self.labels.get(&name) {
+                        Some(&target) => {
+                            self.regs[1] = pc;
+                            pc = target;
+                        },
+                        None => self.call_library(&name),
+                    }
+                },
+                "ret" => pc = self.regs[1],
+                _ => {
+                    let (rd, x) = (reg(0), self.regs[reg(1)]);
+                    let value = match (op.strip_suffix('i'), &args[2]) {
+                        (Some(op), &Arg::Imm(y)) => {
+                            assert!(matches!(op, "sll" | "srl" | "sra") || (-2048..=2047).contains(&y), "immediate out of range: {} {}", op, y);
+                            alu(op, x, y)
+                        },
+                        (_, &Arg::Reg(y)) => alu(op, x, self.regs[y]),
+                        _ => panic!("unknown instruction `{}`", op),
+                    };
+                    self.write(rd, value);
+                },
+            }
+        }
+        return self.regs[10] & 0xff;
+    }
+}
+
+fn alu(op: &str, x: i32, y: i32) -> i32 {
+    match op {
+        "add" => x.wrapping_add(y),
+        "sub" => x.wrapping_sub(y),
+        "mul" => x.wrapping_mul(y),
+        "mulh" => ((x as i64 * y as i64) >> 32) as i32,
+        "div" => if y == 0 { -1 } else { x.wrapping_div(y) },
+        "rem" => if y == 0 { x } else { x.wrapping_rem(y) },
+        "and" => x & y,
+        "or" => x | y,
+        "xor" => x ^ y,
+        "sll" => x.wrapping_shl(y as u32),
+        "srl" => (x as u32).wrapping_shr(y as u32) as i32,
+        "sra" => x.wrapping_shr(y as u32),
+        "slt" => (x < y) as i32,
+        "sgt" => (x > y) as i32,
+        "sltu" => ((x as u32) < (y as u32)) as i32,
+        _ => panic!("unknown instruction `{}`", op),
+    }
+}
@@ -0,0 +1,24 @@
+  .text
+  .globl f
+f:
+.Lentry_index_4:
+  mul t4, a0, a1
+  add a0, t4, a0
+  ret
+
+  .globl main
+main:
+.Lentry_index_5:
+  addi sp, sp, -16
+  sw ra, 12(sp)
+  call getint
+  addi a1, a0, 1
+  call f
+  call putint
+  li a0, 10
+  call putch
+  li a0, 0
+  lw ra, 12(sp)
+  addi sp, sp, 16
+  ret
+
@@ -0,0 +1,32 @@
+  .text
+  .globl f
+f:
+.Lentry_index_2:
+  mv t4, a0
+  mv t5, a1
+  mul t6, t4, t5
+  add t5, t6, t4
+  mv a0, t5
+  ret
+
+  .globl main
+main:
+.Lentry_index_3:
+  addi sp, sp, -16
+  sw ra, 12(sp)
+  call getint
+  mv t4, a0
+  addi t5, t4, 1
+  mv a0, t4
+  mv a1, t5
+  call f
+  mv t6, a0
+  mv a0, t6
+  call putint
+  li a0, 10
+  call putch
+  li a0, 0
+  lw ra, 12(sp)
+  addi sp, sp, 16
+  ret
+
@@ -0,0 +1,44 @@
+  .text
+  .globl f
+f:
+.Lentry_index_0:
+  addi sp, sp, -16
+  sw a0, 0(sp)
+  sw a1, 4(sp)
+  lw t0, 0(sp)
+  mv t1, a1
+  mul t2, t0, t1
+  sw t2, 8(sp)
+  mv t0, t2
+  lw t1, 0(sp)
+  add t2, t0, t1
+  sw t2, 12(sp)
+  mv t0, t2
+  mv a0, t0
+  addi sp, sp, 16
+  ret
+
+  .globl main
+main:
+.Lentry_index_1:
+  addi sp, sp, -16
+  sw ra, 12(sp)
+  call getint
+  mv t0, a0
+  sw t0, 0(sp)
+  addi t2, t0, 1
+  sw t2, 4(sp)
+  lw a0, 0(sp)
+  mv a1, t2
+  call f
+  mv t0, a0
+  sw t0, 8(sp)
+  mv a0, t0
+  call putint
+  li a0, 10
+  call putch
+  li a0, 0
+  lw ra, 12(sp)
+  addi sp, sp, 16
+  ret
+
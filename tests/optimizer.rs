@@ -0,0 +1,170 @@
+mod common;
+
+use common::*;
+use compiler::{OptLevel, OptOptions};
+use std::time::{Duration, Instant};
+
+// The IR after `names` matches tests/golden/<golden>, and the optimized
+// program still prints `expected` with every allocator
+fn check_passes(golden: &str, names: &[&str], source: &str, input: &str, expected: &str) {
+    let mut program = compile(source);
+    compiler::optimize_with(&mut program, &passes(names));
+    // The library declarations are the same everywhere
+    let functions = koopa(&program).split("\n\n").filter(|part| !part.starts_with("decl")).collect::<Vec<_>>().join("\n\n");
+    assert_golden(golden, &functions);
+    for regalloc in REG_ALLOCS {
+        assert_eq!(run_asm(&asm(&program, regalloc), input), expected, "{:?}", regalloc);
+    }
+}
+
+#[test]
+fn mem2reg() {
+    let source = "int main() { int a = getint(); int b = 0; if (a > 0) b = a; else b = -a; putint(b); putch(10); return 0; }";
+    check_passes("mem2reg.koopa", &["mem2reg"], source, "-5", "5\n0");
+}
+
+#[test]
+fn sccp() {
+    let source = "int main() { int a = 3; int b = a * 4; if (b > 10) putint(b); else putint(getint()); putch(10); return 0; }";
+    check_passes("sccp.koopa", &["mem2reg", "sccp"], source, "", "12\n0");
+}
+
+#[test]
+fn dce() {
+    let source = "int main() { int a = getint(); int b = a * 7; int c = b + 1; putint(a); putch(10); return 0; }";
+    check_passes("dce.koopa", &["mem2reg", "dce"], source, "6", "6\n0");
+}
+
+#[test]
+fn gvn() {
+    let source = "int main() { int a = getint(); int b = getint(); int c = a * b + 1; int d = a * b + 1; putint(c + d); putch(10); return 0; }";
+    check_passes("gvn.koopa", &["mem2reg", "gvn"], source, "3 4", "26\n0");
+}
+
+#[test]
+fn licm() {
+    let source = "
+        int main() {
+            int a = getint(); int b = getint(); int i = 0; int s = 0;
+            while (i < 10) { s = s + a * b; i = i + 1; }
+            putint(s); putch(10);
+            return 0;
+        }
+    ";
+    check_passes("licm.koopa", &["mem2reg", "licm"], source, "3 4", "120\n0");
+}
+
+#[test]
+fn inline() {
+    let source = "int sq(int x) { return x * x; } int main() { int a = getint(); putint(sq(a) + sq(a + 1)); putch(10); return 0; }";
+    check_passes("inline.koopa", &["inline", "mem2reg"], source, "3", "25\n0");
+}
+
+#[test]
+fn tre() {
+    let source = "
+        int sum(int n, int acc) { if (n == 0) return acc; return sum(n - 1, acc + n); }
+        int main() { putint(sum(getint(), 0)); putch(10); return 0; }
+    ";
+    check_passes("tre.koopa", &["mem2reg", "tre"], source, "100", "5050\n0");
+}
+
+// Every iteration of the loop would share `b`: the callee's `a` is then its own `b`
+#[test]
+fn tre_keeps_calls_passing_local_arrays() {
+    let source = "
+        int f(int a[], int n, int s) {
+            int b[4] = {0, 0, 0, 0};
+            b[0] = n * 10;
+            s = s + a[0];
+            if (n == 0) return s;
+            return f(b, n - 1, s);
+        }
+        int main() { int x[1]; x[0] = 7; putint(f(x, 3, 0)); putch(10); return 0; }
+    ";
+    let o2 = OptOptions{ level: OptLevel::O2, ..OptOptions::default() };
+    for options in [passes(&["mem2reg", "tre"]), o2] {
+        for regalloc in REG_ALLOCS {
+            assert_eq!(run(source, &options, regalloc, ""), "67\n0", "{:?}", regalloc);
+        }
+    }
+}
+
+#[test]
+fn strength() {
+    let source = "
+        int main() {
+            int n = getint(); int i = 0; int s = 0;
+            while (i < n) { s = s + i * 3; i = i + 1; }
+            putint(s * 8); putch(10);
+            return 0;
+        }
+    ";
+    check_passes("strength.koopa", &["mem2reg", "strength"], source, "10", "1080\n0");
+}
+
+#[test]
+fn unroll() {
+    let source = "
+        int main() {
+            int i = 0; int s = 0;
+            while (i < 3) { s = s + getint(); i = i + 1; }
+            putint(s); putch(10);
+            return 0;
+        }
+    ";
+    check_passes("unroll.koopa", &["mem2reg", "unroll"], source, "1 2 3", "6\n0");
+}
+
+// The row address `m[i]` gets an induction variable of its own: `m[i][i]` is
+// then based on it, so it is not invariant and steps by one element of a row
+#[test]
+fn strength_indexes_both_subscripts() {
+    let source = "
+        int main() {
+            int m[4][5]; int i = 0;
+            while (i < 4) { int j = 0; while (j < 5) { m[i][j] = i * 5 + j; j = j + 1; } i = i + 1; }
+            int n = getint(); int s = 0; i = 0;
+            while (i < n) { s = s + m[i][i] * m[i][i]; i = i + 1; }
+            putint(s); putch(10);
+            return 0;
+        }
+    ";
+    let o2 = OptOptions{ level: OptLevel::O2, ..OptOptions::default() };
+    for options in [passes(&["mem2reg", "strength"]), o2] {
+        for regalloc in REG_ALLOCS {
+            assert_eq!(run(source, &options, regalloc, "4"), "504\n0", "{:?}", regalloc);
+        }
+    }
+}
+
+// Each multiply replaced used to rebuild every value depending on it: a long
+// straight-line function took quadratic time
+#[test]
+fn strength_reduction_is_linear() {
+    let mut source = String::from("int main() {\n  int a = getint(); int b = 1;\n");
+    for i in 0..3000 {
+        source += &format!("  a = a + b * {}; b = b + a;\n", 1 << (i % 8));
+    }
+    source += "  return a + b;\n}\n";
+    let mut program = compile(&source);
+    let start = Instant::now();
+    compiler::optimize_with(&mut program, &passes(&["mem2reg", "strength"]));
+    let elapsed = start.elapsed();
+    assert!(elapsed < Duration::from_secs(10), "strength reduction took {:?}", elapsed);
+}
+
+// Same for every redundant value gvn replaces
+#[test]
+fn gvn_is_linear() {
+    let mut source = String::from("int main() {\n  int a = getint(); int b = getint(); int c = getint();\n");
+    for k in 0..3000 {
+        source += &format!("  a = a + (b * c + {k}) * (b * c + {k}); b = b + a;\n");
+    }
+    source += "  return a + b;\n}\n";
+    let mut program = compile(&source);
+    let start = Instant::now();
+    compiler::optimize_with(&mut program, &passes(&["mem2reg", "gvn"]));
+    let elapsed = start.elapsed();
+    assert!(elapsed < Duration::from_secs(10), "gvn took {:?}", elapsed);
+}
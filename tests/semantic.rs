@@ -0,0 +1,71 @@
+use compiler::diagnostics::WarningConfig;
+
+fn warnings(source: &str) -> Vec<String> {
+    let (_, diags) = compiler::parse_with_warnings(source, &WarningConfig::new()).expect("program should check");
+    diags.iter().map(|diag| diag.message.clone()).collect()
+}
+
+// Conditions folding through an overflow wrap like the target's arithmetic
+#[test]
+fn overflowing_constant_conditions() {
+    assert!(warnings("int f() { if (2147483647 + 1) return 1; } int main() { return f(); }").is_empty());
+    assert!(warnings("int f() { while (-2147483647 - 2) {} } int main() { return f(); }").is_empty());
+    assert!(warnings("int f() { while (65536 * 65536 + 1) {} } int main() { return f(); }").is_empty());
+    assert_eq!(
+        warnings("int f() { if (65536 * 65536) return 1; return 0; } int main() { return f(); }"),
+        ["`if` condition is always false [-Wconstant-condition]"],
+    );
+}
+
+// A division by zero or of i32::MIN by -1 is not folded
+#[test]
+fn unfoldable_constant_conditions() {
+    let missing = ["control reaches end of non-void function `f` [-Wreturn-type]"];
+    assert_eq!(warnings("int f() { if (1 / 0) return 1; } int main() { return f(); }"), missing);
+    assert_eq!(warnings("int f() { while ((-2147483647 - 1) / -1) {} } int main() { return f(); }"), missing);
+    assert_eq!(warnings("int f() { while ((-2147483647 - 1) % -1) {} } int main() { return f(); }"), missing);
+}
+
+// The caret of an unused name covers the identifier, not its dimensions or initializer
+#[test]
+fn unused_name_spans() {
+    let source = "int main() { int arr[4] = {1}; const int k = 2; return 0; } int f(int p[][2]) { return 0; }";
+    let mut config = WarningConfig::new();
+    config.parse_flag("-Wunused-parameter").unwrap();
+    let (_, diags) = compiler::parse_with_warnings(source, &config).expect("program should check");
+    let spans = diags.iter()
+        .filter_map(|diag| diag.span.map(|span| &source[span.start..span.end]))
+        .collect::<Vec<_>>();
+    assert!(spans.contains(&"arr"));
+    assert!(spans.contains(&"k"));
+    assert!(spans.contains(&"p"));
+}
+
+// Every diagnostic of a source that fails to check, with the source under its caret
+fn errors(source: &str) -> Vec<(String, &str)> {
+    let diags = compiler::parse_with_warnings(source, &WarningConfig::new()).map(|_| ()).expect_err("program should fail");
+    diags.iter().map(|diag| (diag.message.clone(), diag.span.map_or("", |span| &source[span.start..span.end]))).collect()
+}
+
+// Recovery skips over the block a broken statement opens instead of closing the
+// function at its `}`, and says nothing about what the skipped source did
+#[test]
+fn recovery_keeps_braces_balanced() {
+    assert_eq!(
+        errors("int main() { int x = 1; if (x > 1 { x = 2; } return 0; }"),
+        [("unexpected token `{`".to_owned(), "{")],
+    );
+}
+
+// A name the skipped source mentions may have been declared there
+#[test]
+fn recovery_hides_names_it_skipped() {
+    assert_eq!(
+        errors("int main() {\n  int a = 1\n  int b = a + 1;\n  return a + b + c;\n}"),
+        [("unexpected token `int`".to_owned(), "int"), ("use of undeclared variable `c`".to_owned(), "c")],
+    );
+    assert_eq!(
+        errors("int main() { int a = 1 return a; }"),
+        [("unexpected token `return`".to_owned(), "return")],
+    );
+}